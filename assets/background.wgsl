@@ -8,13 +8,24 @@ struct GlobalUniforms {
     expansion_time: f32,
     time: f32,
     scale_factor: f32,
+    vertical: f32,
+    mirrored: f32,
+    corner_radius: f32,
+    expansion_duration: f32,
+    opacity: f32,
+    fade_edges: vec2<f32>, // [start_x, end_x] tracks fade out towards
+    square_edge: f32, // 1 squares the top corners, -1 the bottom ones
 };
 
 struct BackgroundPill {
     rect: vec2<f32>, // [x_position, width]
-    colors: array<u32, 4>,
+    colors: array<u32, 6>,
     alpha: f32,
     image_index: i32,
+    swatch_count: u32,
+    progress: f32, // Played fraction of the current track
+    loading: f32, // 1 while the art or palette hasn't arrived, easing to 0 once it has
+    _padding: f32,
 };
 
 @group(0) @binding(0) var<uniform> global: GlobalUniforms;
@@ -35,15 +46,24 @@ fn vs_main(@builtin(vertex_index) v_idx: u32, @builtin(instance_index) i_idx: u3
     let pill = pills[i_idx];
     let margin = 16.0;
     let unit_coord = vec2<f32>(f32(v_idx % 2u), f32(v_idx / 2u));
-    let pill_size = vec2(pill.rect.y, global.bar_height.y);
+    let y_range = pill_y_range(pill);
+    let pill_size = vec2(pill.rect.y, y_range.y);
 
     // Expand vertex bounds to accommodate shadows/glows
     let local_pixel = unit_coord * (pill_size + 2.0 * margin) - margin;
-    let pixel_pos = vec2(pill.rect.x, global.bar_height.x) + local_pixel;
+    let pixel_pos = vec2(pill.rect.x, y_range.x) + local_pixel;
 
     var out: VertexOutput;
     let ndc = (pixel_pos / global.screen_size) * 2.0 - 1.0;
     out.clip_pos = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+    // Right to left timelines mirror the scene before it is rotated
+    if (global.mirrored > 0.5) {
+        out.clip_pos.x = -out.clip_pos.x;
+    }
+    // Vertical bars rotate the scene a quarter turn clockwise
+    if (global.vertical > 0.5) {
+        out.clip_pos = vec4(out.clip_pos.y, -out.clip_pos.x, 0.0, 1.0);
+    }
     out.local_uv = local_pixel / pill_size;
     out.world_uv = local_pixel / global.screen_size.y;
     out.pill_idx = i_idx;
@@ -51,8 +71,21 @@ fn vs_main(@builtin(vertex_index) v_idx: u32, @builtin(instance_index) i_idx: u3
     return out;
 }
 
-/// 4th-order squircle distance function
-fn sd_squircle(p: vec2<f32>, b: vec2<f32>, r: f32) -> f32 {
+/// Start y and height of a pill, outlines (image_index -2) carry their own in the first colours
+fn pill_y_range(pill: BackgroundPill) -> vec2<f32> {
+    let outline_range = vec2(bitcast<f32>(pill.colors[0]), bitcast<f32>(pill.colors[1]));
+    return select(global.bar_height, outline_range, pill.image_index == -2);
+}
+
+/// Palette colour, wrapping around when the album has fewer swatches
+fn swatch(pill: BackgroundPill, i: u32) -> vec3<f32> {
+    return unpack4x8unorm(pill.colors[i % max(pill.swatch_count, 1u)]).rgb;
+}
+
+/// 4th-order squircle distance function, the corners facing the anchored screen edge are square
+fn sd_squircle(p: vec2<f32>, b: vec2<f32>, radius: f32) -> f32 {
+    let facing_edge = (global.square_edge > 0.5 && p.y < 0.0) || (global.square_edge < -0.5 && p.y > 0.0);
+    let r = select(radius, 0.0, facing_edge);
     let q = abs(p) - b + r;
     return pow(pow(max(q.x, 0.0), 4.0) + pow(max(q.y, 0.0), 4.0), 0.25) - r + min(max(q.x, q.y), 0.0);
 }
@@ -60,11 +93,24 @@ fn sd_squircle(p: vec2<f32>, b: vec2<f32>, r: f32) -> f32 {
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let pill = pills[in.pill_idx];
+    if (pill.image_index == -2) {
+        // Outline, a thin line just inside the rect
+        let size = vec2(pill.rect.y, pill_y_range(pill).y);
+        let edge = abs((in.local_uv - 0.5) * size) - size * 0.5;
+        let line = smoothstep(-2.0, -1.0, max(edge.x, edge.y)) * step(max(edge.x, edge.y), 0.0);
+        if (line <= 0.0) { discard; }
+        return vec4(unpack4x8unorm(pill.colors[2]).rgb, 1.0) * 0.8 * line;
+    }
+    if (pill.image_index == -3) {
+        // Time tick, a faint line the height of the bar
+        if (any(in.local_uv < vec2(0.0)) || any(in.local_uv > vec2(1.0))) { discard; }
+        return vec4(1.0) * 0.15 * pill.alpha * global.opacity;
+    }
     let pill_size = vec2(pill.rect.y, global.bar_height.y);
-    let rounding = 22.0 * global.scale_factor;
+    let rounding = global.corner_radius * global.scale_factor;
 
     // --- Interaction Logic ---
-    let anim_t = (global.time - global.expansion_time) * 1.2;
+    let anim_t = (global.time - global.expansion_time) / global.expansion_duration;
     let ripple_active = step(0.0, anim_t) * step(anim_t, 1.0);
 
     // Geometry Ripple (Expand wave from expansion_xy)
@@ -103,27 +149,49 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let mix_val = clamp((s1 * 0.5 + s2 * 0.3 + sin(length(p) * 4.0 + s1 + t) * 0.2) * 0.5 + 0.5, 0.0, 1.0);
 
     // Color Palette Unpacking
-    let c0 = unpack4x8unorm(pill.colors[0]).rgb;
-    let c1 = unpack4x8unorm(pill.colors[1]).rgb;
-    let c2 = unpack4x8unorm(pill.colors[2]).rgb;
-    let c3 = unpack4x8unorm(pill.colors[3]).rgb;
+    let c0 = swatch(pill, 0u);
+    let c1 = swatch(pill, 1u);
+    let c2 = swatch(pill, 2u);
+    let c3 = swatch(pill, 3u);
+    var average = vec3(0.0);
+    for (var i = 0u; i < pill.swatch_count; i++) {
+        average += swatch(pill, i);
+    }
+    average /= f32(max(pill.swatch_count, 1u));
 
     // Vibrancy Post-Processing
     var color = mix(mix(c0, c1, mix_val), mix(c3, c2, s2 * 0.5 + 0.5), mix_val);
-    color = mix(color, (c0 + c1 + c2 + c3) * 0.25, 0.1); // Base color blend
+    if (pill.swatch_count > 4u) {
+        // Extra swatches flow through as a third layer
+        let extra = mix(swatch(pill, 4u), swatch(pill, 5u), s1 * 0.5 + 0.5);
+        color = mix(color, extra, smoothstep(0.6, 1.0, s1 * s2 * 0.5 + 0.5) * 0.6);
+    }
+    color = mix(color, average, 0.1); // Base color blend
 
     let luma = dot(color, vec3(0.2126, 0.7152, 0.0722));
     color = mix(vec3(luma), color, mix(3.2, 1.6, smoothstep(0.1, 0.4, luma))); // Saturation boost
     color = clamp(color, vec3(0.06), vec3(0.85)) * min(1.0, 0.52 / max(luma, 0.001)); // Luma cap for UI readability
-    color = mix(color, color * 0.45, smoothstep(global.playhead_x + 1.2, global.playhead_x - 1.2, in.pixel_pos.x));
+
+    // Progress fill, the played part of the current track is lit up instead of dimmed like the history
+    let local_x = in.local_uv.x * pill_size.x;
+    let fill = smoothstep(pill.progress * pill_size.x + 1.2, pill.progress * pill_size.x - 1.2, local_x) * step(0.0001, pill.progress);
+    let played = smoothstep(global.playhead_x + 1.2, global.playhead_x - 1.2, in.pixel_pos.x);
+    color = mix(color, color * 0.45, played * (1.0 - fill));
+    let fill_luma = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    color = mix(color, mix(vec3(fill_luma), color, 1.4) * 1.2, fill);
+
+    // Loading shimmer, a soft band sweeping across the whole bar over pills still waiting on assets
+    let sweep = fract(global.time * 0.4) * (global.screen_size.x + 400.0) - 200.0;
+    let band = exp(-pow((in.pixel_pos.x + in.pixel_pos.y * 0.5 - sweep) / 60.0, 2.0));
+    color = mix(color, vec3(0.2 + band * 0.15), pill.loading * 0.8);
 
     // Cover art
     let img_x = pill_size.x - pill_size.y;
-    let local_x = in.local_uv.x * pill_size.x;
     let uv_img = vec2((local_x - img_x) / pill_size.y, stretched_uv_y);
-    let tex = textureSample(t_images, s_images, uv_img, max(0, pill.image_index));
+    let tex_uv = vec2(select(uv_img.x, 1.0 - uv_img.x, global.mirrored > 0.5), uv_img.y); // Keep art unmirrored
+    let tex = textureSample(t_images, s_images, tex_uv, max(0, pill.image_index));
     let img_mask = (1.0 - smoothstep(-0.5, 0.5, sd_squircle((uv_img - 0.5) * pill_size.y, vec2(pill_size.y * 0.5), rounding)))
-                 * step(0.0, f32(pill.image_index)) * step(img_x, local_x);
+                 * step(0.0, f32(pill.image_index)) * step(img_x, local_x) * (1.0 - pill.loading);
     color = mix(color, tex.rgb, img_mask * tex.a);
 
     // Glass sheen, rim light, and mouse-reactive highlight
@@ -141,6 +209,11 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     // Expansion flash
     color = mix(color, color * 1.5 + 0.1, (1.0 - anim_t) * smoothstep(80.0, 0.0, wave_dist) * ripple_active * 0.5);
 
+    // Fade out towards the ends of the timeline rather than clipping at them
+    let edge_fade = smoothstep(global.fade_edges.x, global.fade_edges.x + 24.0, in.pixel_pos.x)
+                  * smoothstep(global.fade_edges.y, global.fade_edges.y - 24.0, in.pixel_pos.x);
+
     // Composition
-    return vec4(color * mask * pill.alpha, max(mask, shadow) * pill.alpha);
+    let alpha = pill.alpha * edge_fade;
+    return vec4(color * mask * alpha, max(mask, shadow) * alpha) * global.opacity;
 }
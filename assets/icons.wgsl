@@ -8,6 +8,11 @@ struct GlobalUniforms {
     expansion_time: f32,
     time: f32,
     scale_factor: f32,
+    vertical: f32,
+    mirrored: f32,
+    corner_radius: f32,
+    expansion_duration: f32,
+    opacity: f32,
 };
 
 struct IconInstance {
@@ -58,6 +63,14 @@ fn vs_main(@builtin(vertex_index) v_idx: u32, @builtin(instance_index) i_idx: u3
 
     var out: VertexOutput;
     out.clip_pos = vec4(ndc_pos.x, -ndc_pos.y, 0.0, 1.0);
+    // Right to left timelines mirror the scene before it is rotated
+    if (global.mirrored > 0.5) {
+        out.clip_pos.x = -out.clip_pos.x;
+    }
+    // Vertical bars rotate the scene a quarter turn clockwise
+    if (global.vertical > 0.5) {
+        out.clip_pos = vec4(out.clip_pos.y, -out.clip_pos.x, 0.0, 1.0);
+    }
     out.local_uv = unit_coord;
     out.icon_id = i_idx;
     out.pixel_radius = pixel_radius;
@@ -70,6 +83,22 @@ fn sd_squircle(p: vec2<f32>, half_size: vec2<f32>, radius: f32) -> f32 {
     return pow(pow(max(q.x, 0.0), n) + pow(max(q.y, 0.0), n), 1.0/n) - radius + min(max(q.x, q.y), 0.0);
 }
 
+fn sd_segment(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / dot(ba, ba), 0.0, 1.0);
+    return length(pa - ba * h);
+}
+
+fn sd_heart(p_in: vec2<f32>) -> f32 {
+    let p = vec2(abs(p_in.x), p_in.y);
+    if (p.y + p.x > 1.0) {
+        return length(p - vec2(0.25, 0.75)) - sqrt(2.0) / 4.0;
+    }
+    let d = min(dot(p - vec2(0.0, 1.0), p - vec2(0.0, 1.0)), dot(p - 0.5 * max(p.x + p.y, 0.0), p - 0.5 * max(p.x + p.y, 0.0)));
+    return sqrt(d) * sign(p.x - p.y);
+}
+
 fn sd_star(p: vec2<f32>, radius: f32, indent: f32) -> f32 {
     let k1 = vec2(0.80901699, -0.58778525);
     let k2 = vec2(-k1.x, k1.y);
@@ -96,7 +125,28 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let param = data.x;
     let alpha = data.y;
 
-    if param >= 0.5 {
+    if icon.image_index < 0 {
+        // Render Playback Control, -1 shuffle, -2 repeat, -3 repeat track, -4 like
+        let r = in.pixel_radius * 0.45;
+        let thickness = in.pixel_radius * 0.1;
+        if icon.image_index == -1 {
+            dist_to_shape = min(
+                sd_segment(local_pixel, vec2(-r, -r * 0.6), vec2(r, r * 0.6)),
+                sd_segment(local_pixel, vec2(-r, r * 0.6), vec2(r, -r * 0.6))
+            ) - thickness;
+        } else if icon.image_index == -4 {
+            // Heart is drawn y-up from its bottom tip
+            let scale = r * 1.9;
+            dist_to_shape = sd_heart(vec2(local_pixel.x, -local_pixel.y) / scale + vec2(0.0, 0.5)) * scale;
+        } else {
+            dist_to_shape = abs(length(local_pixel) - r) - thickness;
+            if icon.image_index == -3 {
+                dist_to_shape = min(dist_to_shape, length(local_pixel) - thickness * 1.5);
+            }
+        }
+        let enabled_color = select(vec3(0.9), vec3(1.0, 0.35, 0.45), icon.image_index == -4);
+        out_color = select(enabled_color, vec3(0.33), param > 0.0);
+    } else if param >= 0.5 {
         // Render Favorite Star
         dist_to_shape = sd_star(local_pixel, in.pixel_radius * 0.5, in.pixel_radius * 0.32) - in.pixel_radius * 0.1 * global.scale_factor;
         // Horizontal split effect for toggle animation
@@ -104,10 +154,20 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         let split_line = in.local_uv.x - star_fullness;
         let selection_mask = clamp(split_line / fwidth(split_line) + 0.5, 0.0, 1.0);
         out_color = mix(vec3(1.0, 0.85, 0.2), vec3(0.33), selection_mask);
+    } else if param > 0.3 {
+        // Render compact playlist dot, coloured by averaging the playlist image
+        dist_to_shape = length(local_pixel) - in.pixel_radius * 0.3;
+        var average = vec3(0.0);
+        for (var i = 0; i < 9; i++) {
+            let uv = vec2(f32(i % 3), f32(i / 3)) * 0.3 + 0.2;
+            average += textureSampleLevel(t_images, s_images, uv, icon.image_index, 0.0).rgb;
+        }
+        out_color = average / 9.0;
     } else {
         // Render Playlist Squircle
         dist_to_shape = sd_squircle(local_pixel, vec2(in.pixel_radius * 0.6), 6.0 * global.scale_factor);
-        let tex_sample = textureSample(t_images, s_images, in.local_uv, icon.image_index).rgb;
+        let tex_uv = vec2(select(in.local_uv.x, 1.0 - in.local_uv.x, global.mirrored > 0.5), in.local_uv.y);
+        let tex_sample = textureSample(t_images, s_images, tex_uv, icon.image_index).rgb;
         let icon_saturation = select(0.0, 0.7, param > 0.0);
         out_color = mix(tex_sample, vec3(0.24), icon_saturation);
     }
@@ -122,5 +182,5 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let highlighting = pow((1.0 - smoothstep(0.0, -5.0, dist_to_shape)), 4.0) * 0.04;
     out_color += highlighting * mask;
 
-    return vec4(out_color * mask * alpha, max(mask, shadow) * alpha);
+    return vec4(out_color * mask * alpha, max(mask, shadow) * alpha) * global.opacity;
 }
@@ -8,6 +8,11 @@ struct GlobalUniforms {
     expansion_time: f32,
     time: f32,
     scale_factor: f32,
+    vertical: f32,
+    mirrored: f32,
+    corner_radius: f32,
+    expansion_duration: f32,
+    opacity: f32,
 };
 
 struct Particle {
@@ -69,6 +74,14 @@ fn vs_main(@builtin(vertex_index) v_idx: u32, @builtin(instance_index) i_idx: u3
 
     var out: VertexOutput;
     out.clip_pos = vec4((world_pos / global.screen_size * 2.0 - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
+    // Right to left timelines mirror the scene before it is rotated
+    if (global.mirrored > 0.5) {
+        out.clip_pos.x = -out.clip_pos.x;
+    }
+    // Vertical bars rotate the scene a quarter turn clockwise
+    if (global.vertical > 0.5) {
+        out.clip_pos = vec4(out.clip_pos.y, -out.clip_pos.x, 0.0, 1.0);
+    }
     out.color = vec4(spark_color, p_life_inv * smoothstep(0.0, 0.15, dt) * 0.3);
     out.uv = uv;
     return out;
@@ -81,5 +94,5 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let alpha = in.color.a * smoothstep(1.0, 0.2, dist);
 
     if (alpha <= 0.0) { discard; }
-    return vec4(in.color.rgb * alpha, alpha);
+    return vec4(in.color.rgb * alpha, alpha) * global.opacity;
 }
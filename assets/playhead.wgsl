@@ -8,6 +8,11 @@ struct GlobalUniforms {
     expansion_time: f32,
     time: f32,
     scale_factor: f32,
+    vertical: f32,
+    mirrored: f32,
+    corner_radius: f32,
+    expansion_duration: f32,
+    opacity: f32,
 };
 
 struct PlayheadState {
@@ -15,6 +20,8 @@ struct PlayheadState {
     bar_visibility: f32,
     play_animation: f32,
     pause_animation: f32,
+    playhead_color: u32,
+    icon_color: u32,
 };
 
 @group(0) @binding(0) var<uniform> global: GlobalUniforms;
@@ -42,6 +49,14 @@ fn vs_main(@builtin(vertex_index) v_idx: u32) -> VertexOutput {
 
     var out: VertexOutput;
     out.clip_pos = vec4((world_pos / global.screen_size * 2.0 - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
+    // Right to left timelines mirror the scene before it is rotated
+    if (global.mirrored > 0.5) {
+        out.clip_pos.x = -out.clip_pos.x;
+    }
+    // Vertical bars rotate the scene a quarter turn clockwise
+    if (global.vertical > 0.5) {
+        out.clip_pos = vec4(out.clip_pos.y, -out.clip_pos.x, 0.0, 1.0);
+    }
     out.world_pos = world_pos;
     return out;
 }
@@ -110,11 +125,15 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     if (main_mask > 0.0 || shadow_mask > 0.0) {
         let normalized_y = 1.0 - clamp((pixel_pos.y - start_y) / height, 0.0, 1.0);
-        let color_state = mix(vec3(0.5), vec3(1.0, 0.878, 0.824), f32(normalized_y <= state.volume));
+        // Filled up to the volume level, grey above it
+        let filled = f32(normalized_y <= state.volume);
+        let bar_rgb = mix(vec3(0.5), unpack4x8unorm(state.playhead_color).rgb, filled);
+        let icon_rgb = mix(vec3(0.5), unpack4x8unorm(state.icon_color).rgb, filled);
+        let color_state = select(bar_rgb, icon_rgb, mask_icon > mask_bar);
         let border_mask = smoothstep(-2.5, -1.0, min(dist_bar, dist_icon));
         let final_rgb = mix(color_state, vec3(0.15), border_mask);
 
-        return vec4(mix(vec3(0.0), final_rgb, main_mask), max(main_mask, shadow_mask));
+        return vec4(mix(vec3(0.0), final_rgb, main_mask), max(main_mask, shadow_mask)) * global.opacity;
     }
 
     discard;
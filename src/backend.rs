@@ -0,0 +1,69 @@
+use crate::{PlaybackDevice, PlaylistId, RepeatMode, TrackId};
+use std::sync::LazyLock;
+
+/// Playback controls for the active music service.
+///
+/// Implementations are expected to log their own failures, interaction code fires and forgets.
+pub trait PlaybackBackend {
+    fn play(&self);
+    fn pause(&self);
+    fn seek_ms(&self, ms: u32);
+    fn set_volume(&self, pct: u8);
+    fn skip_next(&self);
+    fn skip_prev(&self);
+    fn set_shuffle(&self, _shuffle: bool) {}
+    fn set_repeat(&self, _repeat: RepeatMode) {}
+    /// Jump to a track `offset` places away in the queue.
+    ///
+    /// Services that can't start a track directly step through the queue instead.
+    fn jump_to_track(&self, _track_id: &TrackId, offset: isize) {
+        step_through_queue(self, offset);
+    }
+
+    // Library editing, only services with playlists need to implement these
+    fn add_to_playlist(&self, _track_id: &TrackId, _playlist_id: &PlaylistId) {}
+    fn remove_from_playlist(&self, _track_id: &TrackId, _playlist_id: &PlaylistId) {}
+    /// Whether the track is in the users liked songs, `None` if unknown or unsupported.
+    fn is_liked(&self, _track_id: &TrackId) -> Option<bool> {
+        None
+    }
+    fn set_liked(&self, _track_id: &TrackId, _liked: bool) {}
+
+    // Devices, only services that play on a remote device need to implement these
+    /// Devices available to play on, empty if unknown or unsupported.
+    #[cfg_attr(not(feature = "spotify"), allow(dead_code))]
+    fn list_devices(&self) -> Vec<PlaybackDevice> {
+        Vec::new()
+    }
+    /// Move playback to the device and start playing there.
+    fn transfer_playback(&self, _device_id: &str) {}
+
+    /// Write anything cached in memory to disk, called once before exiting.
+    fn persist(&self) {}
+}
+
+/// Skip `offset` tracks forwards or backwards one at a time, at most 10.
+pub fn step_through_queue<B: PlaybackBackend + ?Sized>(backend: &B, offset: isize) {
+    for _ in 0..offset.unsigned_abs().min(10) {
+        if offset > 0 {
+            backend.skip_next();
+        } else {
+            backend.skip_prev();
+        }
+    }
+}
+
+pub static BACKEND: LazyLock<Box<dyn PlaybackBackend + Send + Sync>> = LazyLock::new(|| {
+    #[cfg(feature = "spotify")]
+    {
+        Box::new(crate::spotify::SpotifyBackend)
+    }
+    #[cfg(feature = "mpris")]
+    {
+        Box::new(crate::mpris::MprisBackend)
+    }
+    #[cfg(not(any(feature = "spotify", feature = "mpris")))]
+    {
+        Box::new(crate::spotify_debug::DebugBackend)
+    }
+});
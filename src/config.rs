@@ -1,20 +1,48 @@
-use serde::Deserialize;
-use std::{fs, sync::LazyLock};
-use tracing::warn;
+use crate::{ALBUM_PALETTE_CACHE, MAX_SWATCHES, layer_shell, render::update_color_palettes};
+use arc_swap::ArcSwap;
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, LazyLock, mpsc},
+    thread::{sleep, spawn},
+    time::Duration,
+};
+use tracing::{info, warn};
 
+/// Stars drawn for ratings in the `stars` style, the rating playlists are spread evenly across them.
+pub const RATING_STARS: u8 = 5;
+/// Space kept clear after the end of the timeline, in pixels.
+const TIMELINE_END_PADDING: f32 = 16.0;
+/// Timeline width in pixels the history gives up its space to keep, below it the layout goes
+/// compact.
+const COMPACT_TIMELINE_WIDTH: f32 = 400.0;
+
+// Each flag is an independent user setting
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Deserialize)]
 #[serde(default)]
 pub struct Config {
     // Spotify client ID
     pub spotify_client_id: Option<String>,
+    /// The MPRIS player to control when built with the `mpris` backend, matched against its bus name.
+    ///
+    /// Defaults to the first player found.
+    pub mpris_player: Option<String>,
 
-    /// The monitor to display on.
-    pub monitor: Option<String>,
+    /// The monitor to display on, a list of monitors, or `"all"`.
+    ///
+    /// Defaults to the first monitor found.
+    pub monitor: Option<Monitors>,
 
     /// The width of the timeline in pixels.
     pub width: f32,
     /// The height of the timeline in pixels.
     pub height: f32,
+    /// Render at this scale instead of the one the compositor reports, such as `1.5`.
+    pub scale_override: Option<f32>,
 
     /// The layer the app should be on.
     ///
@@ -22,69 +50,749 @@ pub struct Config {
     pub layer: String,
     /// The corner/edge the application should anchor to.
     ///
-    /// Can be one of 'top' or 'bottom'.
+    /// Can be one of 'top' or 'bottom', or 'left' or 'right' for a vertical bar.
     pub layer_anchor: String,
+    /// Gap from the screen edges in pixels, as `[top, right, bottom, left]`.
+    pub margin: [i32; 4],
+    /// Space reserved so other surfaces don't overlap the bar, in pixels.
+    ///
+    /// `0` reserves nothing but stays clear of other bars, `-1` ignores them and overlaps anything.
+    /// Defaults to `-1`, and is always `0` on the `background` layer so windows are never pushed.
+    pub exclusive_zone: Option<i32>,
+    /// Take clicks, scrolls and hovers, turn off so everything passes through to what's below, for
+    /// a display only widget on the `background` layer.
+    pub interactive: bool,
+    /// Let the bar take keyboard focus when clicked, so `/` searches the queue by title. Off by
+    /// default so clicking the bar never takes focus from the window being typed in.
+    pub queue_search: bool,
+    /// Lay the timeline out along the edge of the screen, `horizontal` or `vertical`.
+    ///
+    /// Vertical bars run top to bottom, with `width` as their length.
+    pub orientation: Orientation,
 
+    /// Which way the timeline runs, `left_to_right` puts the history on the left.
+    pub timeline_direction: TimelineDirection,
+
+    /// What the bar shows, `timeline` or `nowplaying`.
+    ///
+    /// Now playing leaves out the other tracks and spreads the current one across the bar.
+    pub mode: DisplayMode,
     /// How many minutes in the future to display in the timeline.
     pub timeline_future_minutes: f32,
     /// How many minutes before the current time to display in the timeline.
     pub timeline_past_minutes: f32,
-    /// The width in pixels on the left where previous tracks are displayed.
-    pub history_width: f32,
+    /// How far the timeline zooms in around the playhead while hovered, 1 leaves it alone.
+    pub hover_zoom: f32,
+    /// The width on the left where previous tracks are displayed, in pixels or a percentage of
+    /// `width` such as `"20%"`. Narrow bars shrink it to give the timeline room.
+    pub history_width: Length,
+    /// Mark every few minutes ahead of the playhead with a faint line, labelled on taller bars.
+    pub show_time_ticks: bool,
+    /// What clicking the album art of past tracks stacked on the left does, `seek` or `ignore`.
+    ///
+    /// Seek plays the track again, ignore only shows its name when hovered.
+    pub history_click: HistoryClick,
+    /// The most tracks kept and laid out either side of the current one, for very long queues.
+    pub max_visible_tracks: usize,
+    /// Show sparks trailing off the playhead and when clicking buttons.
+    pub particles_enabled: bool,
+    /// Sparks emitted per second while the timeline moves.
+    pub particle_emission_rate: f32,
+    /// RGB colour of the playhead sparks, picked from the album palette when unset.
+    pub particle_color: Option<[u8; 3]>,
+    /// RGB colour of the sparks when clicking ratings and playlists, gold when unset.
+    pub rating_particle_color: Option<[u8; 3]>,
+    /// RGB colour of the playhead up to the volume level, the rest of it stays grey.
+    pub playhead_color: [u8; 3],
+    /// RGB colour of the play and pause icons on the playhead, grey above the volume level too.
+    pub icon_color: [u8; 3],
+    /// How fast the playhead morphs between playing and paused, 2 is twice as fast.
+    pub animation_speed: f32,
+    /// How long the ripple after a click lasts, in seconds.
+    pub expansion_duration: f32,
+    /// How opaque the bar fades to once left alone, from 0 to 1, it comes back when hovered.
+    pub idle_opacity: f32,
+    /// Seconds without the pointer over the bar or any changes before it fades to `idle_opacity`.
+    pub idle_opacity_delay: f32,
+    /// Leave out the sparks and click ripple, and snap to new states instead of animating.
+    pub reduced_motion: bool,
+    /// How rounded the corners of each track are, in pixels.
+    pub corner_radius: f32,
+    /// Leave the corners of each track facing the anchored screen edge square, so the bar looks
+    /// attached to it.
+    pub square_edge_corners: bool,
+    /// The gap between tracks in pixels, the same however far `timeline_future_minutes` zooms.
+    pub track_gap_px: f32,
+    /// How quickly the timeline eases to a new position after a seek, lower is smoother.
+    pub scroll_catch_up: f32,
+    /// Timeline jumps shorter than this many milliseconds snap instead of easing.
+    pub scroll_snap_threshold_ms: f32,
 
     /// Array of favourite playlists to display as buttons.
     pub playlists: Vec<String>,
+    /// Show a button for the playlist being played from too, without listing it in `playlists`.
+    pub track_current_context: bool,
     /// Should star ratings be enabled
     pub ratings_enabled: bool,
+    /// Names of the playlists holding each rating, lowest first, spread evenly across the stars.
+    ///
+    /// Defaults to ten half star steps, `0.5` to `5.0`.
+    pub rating_playlist_names: Vec<String>,
+    /// How ratings are shown.
+    ///
+    /// Can be one of `stars` or `number`, number shows a badge like `7/10` and clicking along it
+    /// picks the rating.
+    pub rating_style: RatingStyle,
+    /// Show playlist membership as small coloured dots on tracks too narrow for the full icons.
+    pub compact_icons: bool,
+    /// Hide the bar while the queue is empty or no device is playing.
+    pub auto_hide: bool,
+    /// Stop the screen from dimming or locking while music is playing.
+    pub inhibit_idle: bool,
+    /// What scrolling over the bar does, `volume` or `seek`.
+    pub scroll_action: ScrollAction,
+    /// How far one scroll notch seeks in milliseconds when `scroll_action` is `seek`.
+    pub seek_step_ms: u32,
+    /// How much one scroll notch changes the volume by, in percent.
+    pub volume_step: u8,
+    /// Scroll down to raise the volume instead of up.
+    pub invert_scroll: bool,
+    /// Font file to render text with, defaults to the bundled Noto Sans Bold.
+    pub font_path: Option<PathBuf>,
+    /// Weight to use when `font_path` is a variable font, 700 is bold.
+    pub font_weight: Option<f32>,
+    /// Pixel size album art is stored and drawn at, raise it to 128 or 256 for sharper art on high DPI
+    /// displays at the cost of more video memory. Needs a restart.
+    pub art_resolution: u32,
+    /// Samples per pixel for anti-aliasing the pill and playhead edges, one of 1 (off), 2 or 4.
+    /// Smooths jagged corners on low DPI displays for a little more GPU time.
+    pub msaa_samples: u32,
+    /// Fonts tried in order for characters the main font is missing, such as CJK or emoji.
+    pub font_fallbacks: Vec<PathBuf>,
+    /// What the second line of each track shows, details are filled in from `{artist}`, `{album}`,
+    /// `{time}` (until the track starts, or since it started) and `{remaining}`.
+    pub bottom_line_format: LineFormat,
+    /// How text that doesn't fit is handled.
+    ///
+    /// Can be one of `shrink` or `ellipsis`.
+    pub text_overflow: TextOverflow,
+    /// How the background colours are picked from album art.
+    ///
+    /// Can be one of `kmeans` or `median_cut`, median cut is cheaper but less accurate.
+    pub palette_algorithm: PaletteAlgorithm,
+    /// How many colours to pick from each album cover, between 2 and 6.
+    pub palette_swatches: usize,
+    /// Shell command run whenever the current track changes.
+    ///
+    /// The track is passed in `CANTUS_TITLE`, `CANTUS_ARTIST`, `CANTUS_ALBUM`, `CANTUS_DURATION_MS`,
+    /// `CANTUS_TRACK_ID` and `CANTUS_ART_URL`.
+    pub on_track_change: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             spotify_client_id: None,
+            mpris_player: None,
             monitor: None,
             width: 1050.0,
             height: 50.0,
+            scale_override: None,
             layer: "top".into(),
             layer_anchor: "top".into(),
+            margin: [0; 4],
+            exclusive_zone: None,
+            interactive: true,
+            queue_search: false,
+            orientation: Orientation::Horizontal,
+            timeline_direction: TimelineDirection::LeftToRight,
+            mode: DisplayMode::Timeline,
             timeline_future_minutes: 12.0,
             timeline_past_minutes: 1.5,
-            history_width: 100.0,
+            hover_zoom: 1.0,
+            history_width: Length::Pixels(100.0),
+            show_time_ticks: false,
+            history_click: HistoryClick::Seek,
+            max_visible_tracks: 50,
+            particles_enabled: true,
+            particle_emission_rate: 20.0,
+            particle_color: None,
+            rating_particle_color: None,
+            playhead_color: [255, 224, 210],
+            icon_color: [255, 224, 210],
+            animation_speed: 1.0,
+            expansion_duration: 0.83,
+            idle_opacity: 1.0,
+            idle_opacity_delay: 10.0,
+            reduced_motion: false,
+            corner_radius: 22.0,
+            square_edge_corners: false,
+            track_gap_px: 5.0,
+            scroll_catch_up: 3.5,
+            scroll_snap_threshold_ms: 200.0,
             playlists: Vec::new(),
+            track_current_context: false,
             ratings_enabled: false,
+            rating_playlist_names: [
+                "0.5", "1.0", "1.5", "2.0", "2.5", "3.0", "3.5", "4.0", "4.5", "5.0",
+            ]
+            .map(String::from)
+            .to_vec(),
+            rating_style: RatingStyle::Stars,
+            compact_icons: false,
+            auto_hide: false,
+            inhibit_idle: false,
+            scroll_action: ScrollAction::Volume,
+            seek_step_ms: 5000,
+            volume_step: 5,
+            invert_scroll: false,
+            font_path: None,
+            font_weight: None,
+            art_resolution: 64,
+            msaa_samples: 1,
+            font_fallbacks: Vec::new(),
+            bottom_line_format: LineFormat::default(),
+            text_overflow: TextOverflow::Shrink,
+            palette_algorithm: PaletteAlgorithm::Kmeans,
+            palette_swatches: 4,
+            on_track_change: None,
         }
     }
 }
 
-pub static CONFIG: LazyLock<Config> = LazyLock::new(load_config);
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Horizontal,
+    Vertical,
+}
 
-fn load_config() -> Config {
-    let path = dirs::config_dir()
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineDirection {
+    LeftToRight,
+    RightToLeft,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayMode {
+    Timeline,
+    NowPlaying,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryClick {
+    Seek,
+    Ignore,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrollAction {
+    Volume,
+    Seek,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingStyle {
+    Stars,
+    Number,
+}
+
+/// A piece of `bottom_line_format`, either plain text or a track detail to fill in.
+pub enum LineToken {
+    Text(String),
+    Artist,
+    Album,
+    Time,
+    Remaining,
+}
+
+/// `bottom_line_format` split into its pieces once, when the config is read.
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+pub struct LineFormat(pub Vec<LineToken>);
+
+impl Default for LineFormat {
+    fn default() -> Self {
+        Self(vec![
+            LineToken::Time,
+            LineToken::Text("\u{2004}•\u{2004}".into()),
+            LineToken::Artist,
+        ])
+    }
+}
+
+impl TryFrom<String> for LineFormat {
+    type Error = String;
+
+    fn try_from(format: String) -> Result<Self, String> {
+        let mut tokens = Vec::new();
+        let mut rest = format.as_str();
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                tokens.push(LineToken::Text(rest[..open].into()));
+            }
+            let Some(close) = rest[open..].find('}') else {
+                return Err(format!("unclosed `{{` in `bottom_line_format` '{format}'"));
+            };
+            tokens.push(match &rest[open + 1..open + close] {
+                "artist" => LineToken::Artist,
+                "album" => LineToken::Album,
+                "time" => LineToken::Time,
+                "remaining" => LineToken::Remaining,
+                other => {
+                    return Err(format!(
+                        "unknown `{{{other}}}` in `bottom_line_format`, expected one of {{artist}}, {{album}}, {{time}} or {{remaining}}"
+                    ));
+                }
+            });
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            tokens.push(LineToken::Text(rest.into()));
+        }
+        Ok(Self(tokens))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextOverflow {
+    Shrink,
+    Ellipsis,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteAlgorithm {
+    #[default]
+    Kmeans,
+    MedianCut,
+}
+
+/// A size in pixels, or a percentage of another such as `"20%"`.
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+#[serde(try_from = "LengthValue")]
+pub enum Length {
+    Pixels(f32),
+    Percent(f32),
+}
+
+impl Length {
+    /// The size in pixels, with percentages taken of `total`.
+    pub fn resolve(self, total: f32) -> f32 {
+        match self {
+            Self::Pixels(pixels) => pixels,
+            Self::Percent(percent) => total * percent / 100.0,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LengthValue {
+    Number(f32),
+    Text(String),
+}
+
+impl TryFrom<LengthValue> for Length {
+    type Error = String;
+
+    fn try_from(value: LengthValue) -> Result<Self, String> {
+        match value {
+            LengthValue::Number(pixels) => Ok(Self::Pixels(pixels)),
+            LengthValue::Text(text) => text
+                .trim()
+                .strip_suffix('%')
+                .and_then(|percent| percent.trim().parse().ok())
+                .filter(|percent: &f32| percent.is_finite())
+                .map(Self::Percent)
+                .ok_or_else(|| {
+                    format!("expected pixels or a percentage like \"20%\", got '{text}'")
+                }),
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Monitors {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Monitors {
+    pub fn targets(&self) -> &[String] {
+        match self {
+            Self::One(target) => std::slice::from_ref(target),
+            Self::Many(targets) => targets,
+        }
+    }
+}
+
+/// The current config, swapped out whenever the file changes on disk.
+pub static CONFIG: LazyLock<ArcSwap<Config>> =
+    LazyLock::new(|| ArcSwap::from_pointee(load_config()));
+
+fn config_path() -> PathBuf {
+    dirs::config_dir()
         .expect("config directory unavailable")
         .join("cantus")
-        .join("cantus.toml");
+        .join("cantus.toml")
+}
 
-    match fs::read_to_string(&path) {
-        Ok(contents) => match toml::from_str::<Config>(&contents) {
-            Ok(config) => config,
+fn read_config(path: &Path) -> Result<Config, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|err| format!("unable to read {}: {err}", path.display()))?;
+    let config = toml::from_str::<Config>(&contents)
+        .map_err(|err| format!("failed to parse {}: {err}", path.display()))?;
+    config.validate().map_err(|errors| {
+        format!(
+            "invalid settings in {}:\n  {}",
+            path.display(),
+            errors.join("\n  ")
+        )
+    })?;
+    Ok(config)
+}
+
+/// Read the config at startup, a missing file gives the defaults.
+///
+/// Any other problem stops startup with every error printed, rather than dropping all the user's
+/// settings for the defaults.
+fn load_config() -> Config {
+    let path = config_path();
+    if !path.exists() {
+        info!("No config at {}, using the defaults", path.display());
+        return Config::default();
+    }
+    read_config(&path).unwrap_or_else(|err| {
+        eprintln!("cantus: {err}");
+        process::exit(1);
+    })
+}
+
+/// Watch the config file and reload it whenever it's saved.
+pub fn watch() {
+    spawn(|| {
+        let path = config_path();
+        let Some(dir) = path.parent() else {
+            return;
+        };
+        let (sender, receiver) = mpsc::channel();
+        // Editors often replace the file rather than writing to it, so watch the whole directory
+        let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            Ok(watcher)
+        });
+        let _watcher = match watcher {
+            Ok(watcher) => watcher,
             Err(err) => {
-                warn!("Falling back to default config, failed to parse {path:?}: {err}");
-                Config::default()
+                warn!("Unable to watch {dir:?} for config changes: {err}");
+                return;
             }
-        },
+        };
+
+        while let Ok(event) = receiver.recv() {
+            let Ok(event) = event else {
+                continue;
+            };
+            if !(event.kind.is_create() || event.kind.is_modify())
+                || !event.paths.iter().any(|p| p == &path)
+            {
+                continue;
+            }
+            // A save usually comes as a burst of events, wait for it to finish
+            sleep(Duration::from_millis(100));
+            while receiver.try_recv().is_ok() {}
+            reload_config(&path);
+        }
+    });
+}
+
+fn reload_config(path: &Path) {
+    let config = match read_config(path) {
+        Ok(config) => config,
         Err(err) => {
-            warn!("Falling back to default config, unable to read {path:?}: {err}");
-            Config::default()
+            warn!("Keeping the current config, {err}");
+            return;
         }
+    };
+    let old = CONFIG.load_full();
+
+    let restart_only = [
+        (
+            "spotify_client_id",
+            old.spotify_client_id != config.spotify_client_id,
+        ),
+        ("font_path", old.font_path != config.font_path),
+        ("font_weight", old.font_weight != config.font_weight),
+        (
+            "font_fallbacks",
+            old.font_fallbacks != config.font_fallbacks,
+        ),
+        (
+            "art_resolution",
+            old.art_resolution != config.art_resolution,
+        ),
+    ];
+    for (name, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+        info!("Changes to `{name}` take effect after a restart");
+    }
+    let palette_changed = old.palette_algorithm != config.palette_algorithm
+        || old.swatch_count() != config.swatch_count();
+
+    CONFIG.store(Arc::new(config));
+    info!("Reloaded config from {path:?}");
+
+    if palette_changed {
+        ALBUM_PALETTE_CACHE.clear();
+        update_color_palettes();
     }
+    layer_shell::request_reconfigure();
 }
 
 impl Config {
-    pub fn playhead_x(&self) -> f32 {
-        let history_width = self.history_width;
-        let total_width = self.width - history_width - 10.0;
-        let timeline_duration_ms = self.timeline_future_minutes * 60_000.0;
-        let timeline_start_ms = -self.timeline_past_minutes * 60_000.0;
-        history_width - timeline_start_ms * (total_width / timeline_duration_ms)
+    /// Check values the config format can't express limits for, returning a message for each problem.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if !(self.width.is_finite() && self.width > 0.0) {
+            errors.push(format!("`width` must be above 0, got {}", self.width));
+        }
+        if !(self.height.is_finite() && self.height > 0.0) {
+            errors.push(format!("`height` must be above 0, got {}", self.height));
+        }
+        if let Some(scale) = self.scale_override
+            && !(scale.is_finite() && scale > 0.0)
+        {
+            errors.push(format!("`scale_override` must be above 0, got {scale}"));
+        }
+        let history_width = self.history_width();
+        if history_width.is_nan() || history_width < 0.0 {
+            errors.push(format!(
+                "`history_width` must be at least 0, got {history_width}px"
+            ));
+        }
+        // The timeline is scaled by the future duration, zero would make it infinitely wide
+        if !(self.timeline_future_minutes.is_finite() && self.timeline_future_minutes > 0.0) {
+            errors.push(format!(
+                "`timeline_future_minutes` must be above 0, got {}",
+                self.timeline_future_minutes
+            ));
+        }
+        if !(self.timeline_past_minutes >= 0.0
+            && self.timeline_past_minutes < self.timeline_future_minutes)
+        {
+            errors.push(format!(
+                "`timeline_past_minutes` must be at least 0 and below `timeline_future_minutes`, got {}",
+                self.timeline_past_minutes
+            ));
+        }
+        if !(1.0..=10.0).contains(&self.hover_zoom) {
+            errors.push(format!(
+                "`hover_zoom` must be between 1 and 10, got {}",
+                self.hover_zoom
+            ));
+        }
+        if !(self.particle_emission_rate.is_finite() && self.particle_emission_rate >= 0.0) {
+            errors.push(format!(
+                "`particle_emission_rate` must be at least 0, got {}",
+                self.particle_emission_rate
+            ));
+        }
+        if !(self.animation_speed.is_finite() && self.animation_speed > 0.0) {
+            errors.push(format!(
+                "`animation_speed` must be above 0, got {}",
+                self.animation_speed
+            ));
+        }
+        if !(self.expansion_duration.is_finite() && self.expansion_duration > 0.0) {
+            errors.push(format!(
+                "`expansion_duration` must be above 0, got {}",
+                self.expansion_duration
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.idle_opacity) {
+            errors.push(format!(
+                "`idle_opacity` must be between 0 and 1, got {}",
+                self.idle_opacity
+            ));
+        }
+        if !(self.idle_opacity_delay.is_finite() && self.idle_opacity_delay >= 0.0) {
+            errors.push(format!(
+                "`idle_opacity_delay` must be at least 0, got {}",
+                self.idle_opacity_delay
+            ));
+        }
+        if !(self.corner_radius.is_finite() && self.corner_radius >= 0.0) {
+            errors.push(format!(
+                "`corner_radius` must be at least 0, got {}",
+                self.corner_radius
+            ));
+        }
+        if !(self.track_gap_px.is_finite() && self.track_gap_px >= 0.0) {
+            errors.push(format!(
+                "`track_gap_px` must be at least 0, got {}",
+                self.track_gap_px
+            ));
+        }
+        if !(self.scroll_catch_up.is_finite() && self.scroll_catch_up > 0.0) {
+            errors.push(format!(
+                "`scroll_catch_up` must be above 0, got {}",
+                self.scroll_catch_up
+            ));
+        }
+        if !(self.scroll_snap_threshold_ms.is_finite() && self.scroll_snap_threshold_ms >= 0.0) {
+            errors.push(format!(
+                "`scroll_snap_threshold_ms` must be at least 0, got {}",
+                self.scroll_snap_threshold_ms
+            ));
+        }
+        if !["background", "bottom", "top", "overlay"].contains(&self.layer.as_str()) {
+            errors.push(format!(
+                "`layer` must be one of 'background', 'bottom', 'top' or 'overlay', got '{}'",
+                self.layer
+            ));
+        }
+        let anchors: &[&str] = if self.vertical() {
+            &["left", "right"]
+        } else {
+            &["top", "bottom"]
+        };
+        if !anchors.contains(&self.layer_anchor.as_str()) {
+            errors.push(format!(
+                "`layer_anchor` must be '{}' or '{}' for a {} bar, got '{}'",
+                anchors[0],
+                anchors[1],
+                if self.vertical() {
+                    "vertical"
+                } else {
+                    "horizontal"
+                },
+                self.layer_anchor
+            ));
+        }
+        if let Some(zone) = self.exclusive_zone
+            && zone < -1
+        {
+            errors.push(format!("`exclusive_zone` must be at least -1, got {zone}"));
+        }
+        if !(2..=MAX_SWATCHES).contains(&self.palette_swatches) {
+            errors.push(format!(
+                "`palette_swatches` must be between 2 and {MAX_SWATCHES}, got {}",
+                self.palette_swatches
+            ));
+        }
+        if !(1..=100).contains(&self.volume_step) {
+            errors.push(format!(
+                "`volume_step` must be between 1 and 100, got {}",
+                self.volume_step
+            ));
+        }
+        if self.ratings_enabled && !(1..=255).contains(&self.rating_playlist_names.len()) {
+            errors.push(format!(
+                "`rating_playlist_names` must have between 1 and 255 names, got {}",
+                self.rating_playlist_names.len()
+            ));
+        }
+        if self.max_visible_tracks == 0 {
+            errors.push("`max_visible_tracks` must be above 0".into());
+        }
+        if self.seek_step_ms == 0 {
+            errors.push("`seek_step_ms` must be above 0".into());
+        }
+        if !(16..=512).contains(&self.art_resolution) {
+            errors.push(format!(
+                "`art_resolution` must be between 16 and 512, got {}",
+                self.art_resolution
+            ));
+        }
+        if ![1, 2, 4].contains(&self.msaa_samples) {
+            errors.push(format!(
+                "`msaa_samples` must be 1, 2 or 4, got {}",
+                self.msaa_samples
+            ));
+        }
+        if let Some(weight) = self.font_weight
+            && !(1.0..=1000.0).contains(&weight)
+        {
+            errors.push(format!(
+                "`font_weight` must be between 1 and 1000, got {weight}"
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn vertical(&self) -> bool {
+        self.orientation == Orientation::Vertical
+    }
+
+    pub fn mirrored(&self) -> bool {
+        self.timeline_direction == TimelineDirection::RightToLeft
+    }
+
+    /// Sparks are drawn unless turned off, or left out for reduced motion.
+    pub const fn show_particles(&self) -> bool {
+        self.particles_enabled && !self.reduced_motion
+    }
+
+    /// `history_width` in pixels, percentages are taken of `width`.
+    ///
+    /// On bars too narrow for it and `COMPACT_TIMELINE_WIDTH` of timeline, the history shrinks
+    /// first, down to nothing.
+    pub fn history_width(&self) -> f32 {
+        let room = (self.width - TIMELINE_END_PADDING - COMPACT_TIMELINE_WIDTH).max(0.0);
+        self.history_width.resolve(self.width).min(room)
+    }
+
+    /// The timeline is narrow even with the history gone, text is drawn smaller to fit.
+    pub fn compact(&self) -> bool {
+        self.timeline_width() < COMPACT_TIMELINE_WIDTH
+    }
+
+    /// Pixels the timeline spans after the history, can be 0 or below on bars too narrow for it.
+    pub fn timeline_width(&self) -> f32 {
+        self.width - self.history_width() - TIMELINE_END_PADDING
+    }
+
+    pub fn swatch_count(&self) -> usize {
+        self.palette_swatches.clamp(2, MAX_SWATCHES)
+    }
+
+    /// How many rating icons are drawn, the number badge is a single one covering every rating.
+    pub const fn rating_stars(&self) -> u8 {
+        match self.rating_style {
+            RatingStyle::Stars => RATING_STARS,
+            RatingStyle::Number => 1,
+        }
+    }
+
+    /// The rating slot, an index into `rating_playlist_names`, under a point `fraction` of the way
+    /// across the given star.
+    pub fn rating_slot(&self, star: u8, fraction: f32) -> u8 {
+        let slots = self.rating_playlist_names.len().clamp(1, 255);
+        let position =
+            (f32::from(star) + fraction.clamp(0.0, 1.0)) / f32::from(self.rating_stars());
+        ((position * slots as f32).ceil() as u8).clamp(1, slots as u8) - 1
+    }
+
+    /// How full the given star is drawn between 0 and 1, for a rating counted from 1, or 0 if unrated.
+    pub fn star_fill(&self, rating: u8, star: u8) -> f32 {
+        let slots = self.rating_playlist_names.len().max(1) as f32;
+        let stars = f32::from(rating) * f32::from(self.rating_stars()) / slots;
+        (stars - f32::from(star)).clamp(0.0, 1.0)
+    }
+
+    /// Tracks rated three stars or above are added to the liked songs.
+    pub fn rating_liked(&self, slot: u8) -> bool {
+        (usize::from(slot) + 1) * usize::from(RATING_STARS) >= self.rating_playlist_names.len() * 3
     }
 }
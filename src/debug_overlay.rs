@@ -0,0 +1,48 @@
+use crate::{
+    CantusApp, PANEL_EXTENSION, PANEL_START, config::CONFIG, pipelines::MAX_PILLS,
+    render::BackgroundPill,
+};
+use std::iter::once;
+
+const TRACK_COLOR: [u8; 3] = [80, 220, 120];
+const ICON_COLOR: [u8; 3] = [90, 160, 255];
+const PLAY_COLOR: [u8; 3] = [255, 90, 90];
+
+impl CantusApp {
+    /// Outline every hitbox and print the frame rate, scale and queue position under the bar.
+    pub fn draw_debug_overlay(&mut self, dt: f32, queue_index: usize, queue_len: usize) {
+        // Eased so the readout can be read
+        let fps = if dt > 0.0 { 1.0 / dt } else { 0.0 };
+        self.render_state.debug_fps += (fps - self.render_state.debug_fps) * 0.1;
+
+        let interaction = &self.interaction;
+        let outlines = interaction
+            .track_hitboxes
+            .iter()
+            .map(|h| (h.rect, TRACK_COLOR))
+            .chain(
+                interaction
+                    .icon_hitboxes
+                    .iter()
+                    .map(|h| (h.rect, ICON_COLOR)),
+            )
+            .chain(once((interaction.play_hitbox, PLAY_COLOR)))
+            .filter(|(rect, _)| rect.x1 > rect.x0 && rect.y1 > rect.y0);
+        let room = MAX_PILLS.saturating_sub(self.background_pills.len());
+        self.background_pills
+            .extend(outlines.take(room).map(|(rect, [r, g, b])| {
+                BackgroundPill::outline(rect, u32::from_le_bytes([r, g, b, 0]))
+            }));
+
+        let stats = format!(
+            "{:.0} fps, scale {:.2}, track {}/{queue_len}",
+            self.render_state.debug_fps,
+            self.scale_factor,
+            queue_index + 1,
+        );
+        if let Some(text_renderer) = &mut self.text_renderer {
+            let y = PANEL_START + CONFIG.load().height + PANEL_EXTENSION * 0.5;
+            text_renderer.render_debug_text(&stats, (4.0, y));
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
-    CantusApp, CondensedPlaylist, PANEL_START, PLAYBACK_STATE, PlaylistId, Track, TrackId,
-    config::CONFIG,
+    CantusApp, CondensedPlaylist, LIKED_CACHE, PANEL_START, PLAYBACK_STATE, PlaybackDevice,
+    PlaylistId, RepeatMode, Track, TrackId,
+    backend::BACKEND,
+    config::{CONFIG, DisplayMode, RatingStyle, ScrollAction},
     render::{IconInstance, Point, Rect, lerpf32},
     update_playback_state,
 };
-use itertools::Itertools;
 use std::{
     collections::HashMap,
     thread::spawn,
@@ -12,26 +13,61 @@ use std::{
 };
 use tracing::{error, info, warn};
 
+pub struct TrackHitbox {
+    pub rect: Rect,
+    pub track_id: Option<TrackId>,
+    /// The x range the whole track would span, to turn a click into a position within it.
+    pub range: (f32, f32),
+    /// Stacked history art is left alone when `history_click` is `ignore`, it still shows a label.
+    pub clickable: bool,
+}
+
 pub struct IconHitbox {
     pub rect: Rect,
     pub track_id: TrackId,
     pub playlist_id: Option<PlaylistId>,
     pub rating_index: Option<u8>,
+    pub control: Option<PlaybackControl>,
+}
+
+/// A key press the search cares about, see `CantusApp::handle_key`.
+pub enum KeyInput {
+    /// What the key types in the current keyboard layout.
+    Text(String),
+    Backspace,
+    Enter,
+    Escape,
+}
+
+#[derive(Clone, Copy)]
+pub enum PlaybackControl {
+    Shuffle,
+    Repeat,
+    Like,
 }
 
+/// Longest gap between two clicks for them to count as a double click.
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
 pub struct InteractionState {
     pub mouse_position: Point,
     pub mouse_pressure: f32, // 0 not hovered - 1 hovered - 2 mouse down
 
     pub last_hitbox_hash: u64,
     pub play_hitbox: Rect,
-    pub track_hitboxes: Vec<(Option<TrackId>, Rect, (f32, f32))>,
+    pub track_hitboxes: Vec<TrackHitbox>,
     pub icon_hitboxes: Vec<IconHitbox>,
 
     pub mouse_down: bool,
     pub dragging: bool,
     pub drag_origin: Option<Point>,
     pub drag_track: Option<(Option<TrackId>, f32)>,
+    /// When the empty part of the bar was last clicked, to spot double clicks.
+    pub last_empty_click: Instant,
+    /// The track under the mouse and when it was first hovered, for the title tooltip.
+    pub hover_start: Option<(Option<TrackId>, Instant)>,
+    /// What has been typed since `/` opened the queue search, `None` while it's closed.
+    pub search: Option<String>,
 
     // Playhead
     pub last_expansion: (Instant, Point),
@@ -52,6 +88,9 @@ impl Default for InteractionState {
             dragging: false,
             drag_origin: None,
             drag_track: None,
+            last_empty_click: Instant::now().checked_sub(Duration::from_secs(5)).unwrap(),
+            hover_start: None,
+            search: None,
             last_expansion: (
                 Instant::now().checked_sub(Duration::from_secs(5)).unwrap(),
                 Point::default(),
@@ -80,10 +119,7 @@ impl CantusApp {
         let interaction = &mut self.interaction;
         if let Some((track_id, position)) = interaction.drag_track.take() {
             // Get the x position of the playhead, run an expansion animation there
-            interaction.last_expansion = (
-                Instant::now(),
-                Point::new(CONFIG.playhead_x(), PANEL_START + CONFIG.height * 0.5),
-            );
+            interaction.last_expansion = (Instant::now(), interaction.play_hitbox.center());
             if let Some(track_id) = track_id {
                 spawn(move || {
                     skip_to_track(track_id, position, false);
@@ -102,6 +138,43 @@ impl CantusApp {
         self.interaction.mouse_down = false;
     }
 
+    /// Middle click a track to start playing it straight away, or the playhead to toggle mute.
+    pub fn middle_click(&mut self) {
+        let interaction = &mut self.interaction;
+        if interaction.dragging {
+            return;
+        }
+        let mouse_pos = interaction.mouse_position;
+        if interaction.play_hitbox.contains(mouse_pos) {
+            interaction.last_expansion = (Instant::now(), interaction.play_hitbox.center());
+            spawn(toggle_mute);
+        } else if let Some(TrackHitbox {
+            track_id: Some(track_id),
+            clickable: true,
+            ..
+        }) = interaction
+            .track_hitboxes
+            .iter()
+            .rev()
+            .find(|hitbox| hitbox.rect.contains(mouse_pos))
+        {
+            // Local files can't be started directly, a normal click still skips to them
+            let track_id = *track_id;
+            if PLAYBACK_STATE
+                .read()
+                .queue
+                .iter()
+                .any(|track| track.id == Some(track_id) && track.is_local)
+            {
+                return;
+            }
+            interaction.last_expansion = (Instant::now(), mouse_pos);
+            spawn(move || {
+                jump_to_track(track_id);
+            });
+        }
+    }
+
     /// Handle click events.
     fn handle_click(&mut self) {
         let mouse_pos = self.interaction.mouse_position;
@@ -123,7 +196,15 @@ impl CantusApp {
         {
             // Spawn particles
             let time = self.start_time.elapsed().as_secs_f32();
-            let mut emit_count = 20;
+            let [r, g, b] = CONFIG
+                .load()
+                .rating_particle_color
+                .unwrap_or([255, 215, 50]);
+            let mut emit_count = if CONFIG.load().show_particles() {
+                20
+            } else {
+                0
+            };
             for particle in &mut self.particles {
                 if emit_count > 0 && time > particle.end_time {
                     particle.spawn_pos = [mouse_pos.x, mouse_pos.y];
@@ -132,18 +213,24 @@ impl CantusApp {
                     particle.spawn_vel = [angle.cos() * speed, angle.sin() * speed];
                     let duration = lerpf32(fastrand::f32(), 0.5, 1.5);
                     particle.color =
-                        u32::from_le_bytes([255, 215, 50, (duration * 100.0).min(255.0) as u8]);
+                        u32::from_le_bytes([r, g, b, (duration * 100.0).min(255.0) as u8]);
                     particle.end_time = time + duration;
                     emit_count -= 1;
                 }
             }
 
             let track_id = hitbox.track_id;
-            if CONFIG.ratings_enabled
+            if let Some(control) = hitbox.control {
+                spawn(move || match control {
+                    PlaybackControl::Shuffle => toggle_shuffle(),
+                    PlaybackControl::Repeat => cycle_repeat(),
+                    PlaybackControl::Like => toggle_liked(&track_id),
+                });
+            } else if CONFIG.load().ratings_enabled
                 && let Some(index) = hitbox.rating_index
             {
-                let center_x = (hitbox.rect.x0 + hitbox.rect.x1) * 0.5;
-                let rating_slot = index * 2 + u8::from(mouse_pos.x >= center_x);
+                let fraction = (mouse_pos.x - hitbox.rect.x0) / hitbox.rect.width();
+                let rating_slot = CONFIG.load().rating_slot(index, fraction);
                 spawn(move || {
                     update_star_rating(&track_id, rating_slot);
                 });
@@ -154,34 +241,46 @@ impl CantusApp {
             }
         } else if interaction.play_hitbox.contains(mouse_pos) {
             // Play/pause
-            interaction.last_expansion = (
-                Instant::now(),
-                Point::new(CONFIG.playhead_x(), PANEL_START + CONFIG.height * 0.5),
-            );
+            interaction.last_expansion = (Instant::now(), interaction.play_hitbox.center());
             interaction.last_toggle_playing = Instant::now();
             spawn(move || {
                 toggle_playing(!playing);
             });
-        } else if let Some((track_id, _, (track_range_a, track_range_b))) = interaction
+        } else if let Some(hitbox) = interaction
             .track_hitboxes
             .iter()
             .rev()
-            .find(|(_, track_rect, _)| track_rect.contains(mouse_pos))
+            .find(|hitbox| hitbox.rect.contains(mouse_pos))
         {
+            if !hitbox.clickable {
+                return;
+            }
             // Seek track
             interaction.last_expansion = (Instant::now(), mouse_pos);
+            let (track_range_a, track_range_b) = hitbox.range;
 
             // If click is near the very left, reset to the start of the song, else seek to clicked position
-            let position = if mouse_pos.x < CONFIG.history_width + 40.0 {
+            let config = CONFIG.load();
+            let position = if config.mode == DisplayMode::Timeline
+                && mouse_pos.x < config.history_width() + 40.0
+            {
                 0.0
             } else {
                 (mouse_pos.x - track_range_a) / (track_range_b - track_range_a)
             };
-            if let Some(track_id) = *track_id {
+            if let Some(track_id) = hitbox.track_id {
                 spawn(move || {
                     skip_to_track(track_id, position, false);
                 });
             }
+        } else if interaction.last_empty_click.elapsed() < DOUBLE_CLICK_TIME {
+            // Double clicking the empty bar jumps the timeline back to what's playing
+            interaction.drag_origin = None;
+            interaction.drag_track = None;
+            self.render_state.recenter = true;
+            interaction.last_expansion = (Instant::now(), interaction.play_hitbox.center());
+        } else {
+            interaction.last_empty_click = Instant::now();
         }
         PLAYBACK_STATE.write().interaction = false;
     }
@@ -200,17 +299,32 @@ impl CantusApp {
     }
 
     /// Handle scrolling events to adjust volume.
-    pub fn handle_scroll(delta: i32) {
-        let scroll_direction = delta.signum();
+    pub fn handle_scroll(&mut self, delta: i32) {
+        // Wayland axis values are positive when scrolling down (towards the user),
+        // so a negative delta is the wheel moving up and raises the volume
+        let scroll_direction = if CONFIG.load().invert_scroll {
+            -delta.signum()
+        } else {
+            delta.signum()
+        };
         if scroll_direction == 0 {
             return;
         }
+        if CONFIG.load().scroll_action == ScrollAction::Seek {
+            // Scrolling up moves forward through the track
+            self.interaction.last_expansion =
+                (Instant::now(), self.interaction.play_hitbox.center());
+            spawn(move || {
+                seek_by(-i64::from(scroll_direction) * i64::from(CONFIG.load().seek_step_ms));
+            });
+            return;
+        }
         update_playback_state(|state| {
             if let Some(volume) = &mut state.volume {
                 *volume = if scroll_direction < 0 {
-                    volume.saturating_add(5).min(100)
+                    volume.saturating_add(CONFIG.load().volume_step).min(100)
                 } else {
-                    volume.saturating_sub(5)
+                    volume.saturating_sub(CONFIG.load().volume_step)
                 };
                 let volume = *volume;
                 spawn(move || {
@@ -227,14 +341,61 @@ impl CantusApp {
         interaction.dragging = false;
         PLAYBACK_STATE.write().interaction = false;
     }
+
+    /// Type into the queue search, `/` opens it, Enter jumps to the match and Escape closes it.
+    pub fn handle_key(&mut self, key: KeyInput) {
+        let interaction = &mut self.interaction;
+        let Some(query) = &mut interaction.search else {
+            if matches!(&key, KeyInput::Text(text) if text == "/") {
+                interaction.search = Some(String::new());
+            }
+            return;
+        };
+        match key {
+            KeyInput::Text(text) => query.push_str(&text),
+            // Deleting past the start closes the search, like most command lines
+            KeyInput::Backspace => {
+                if query.pop().is_none() {
+                    interaction.search = None;
+                }
+            }
+            KeyInput::Escape => interaction.search = None,
+            KeyInput::Enter => {
+                let track_id = {
+                    let state = PLAYBACK_STATE.read();
+                    search_match(&state.queue, state.queue_index, query)
+                        .and_then(|index| state.queue[index].id)
+                };
+                interaction.search = None;
+                if let Some(track_id) = track_id {
+                    interaction.last_expansion = (Instant::now(), interaction.play_hitbox.center());
+                    spawn(move || skip_to_track(track_id, 0.0, false));
+                }
+            }
+        }
+    }
+}
+
+/// The queue index of the first track after `current` whose title contains `query`, ignoring
+/// case, wrapping around to the history after the end of the queue and the current track last.
+pub fn search_match(queue: &[Track], current: usize, query: &str) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    let query = query.to_lowercase();
+    let start = (current + 1).min(queue.len());
+    (start..queue.len())
+        .chain(0..start)
+        .find(|&index| queue[index].name.to_lowercase().contains(&query))
 }
 
-enum IconEntry<'a> {
+#[derive(Clone, Copy)]
+pub enum IconEntry {
     Star {
         index: u8,
     },
     Playlist {
-        playlist: &'a CondensedPlaylist,
+        playlist_id: PlaylistId,
         contained: bool,
     },
 }
@@ -249,42 +410,71 @@ impl CantusApp {
         width: f32,
         pos_x: f32,
     ) {
-        let Some(track_id) = track.id else { return };
-        let (track_rating_index, mut icon_entries) = if CONFIG.ratings_enabled {
-            let index = playlists
+        let Some(track_id) = track.id.filter(|_| !track.is_local) else {
+            return;
+        };
+        let config = CONFIG.load();
+        let mut icon_entries = std::mem::take(&mut self.icon_entries);
+        icon_entries.clear();
+        let track_rating_index = if config.ratings_enabled {
+            let stars = config.rating_stars();
+            icon_entries.extend((0..stars).map(|index| IconEntry::Star { index }));
+            playlists
                 .values()
                 .find(|p| p.rating_index.is_some() && p.tracks.contains(&track_id))
                 .and_then(|p| p.rating_index.map(|r| r + 1))
-                .unwrap_or(0);
-            (
-                index,
-                (0..5).map(|index| IconEntry::Star { index }).collect_vec(),
-            )
+                .unwrap_or(0)
         } else {
-            (0, Vec::new())
+            0
         };
 
         // Add playlists that are contained in the favourited playlists
+        let stars_len = icon_entries.len();
         icon_entries.extend(
             playlists
                 .values()
                 .filter(|p| p.rating_index.is_none())
                 .filter_map(|p| {
                     let contained = p.tracks.contains(&track_id);
-                    (contained || hovered).then_some((p, contained))
-                })
-                .sorted_by(|(a, ac), (b, bc)| bc.cmp(ac).then_with(|| a.name.cmp(&b.name)))
-                .map(|(playlist, contained)| IconEntry::Playlist {
-                    playlist,
-                    contained,
+                    (contained || hovered).then_some(IconEntry::Playlist {
+                        playlist_id: p.id,
+                        contained,
+                    })
                 }),
         );
+        icon_entries[stars_len..].sort_by(|a, b| match (a, b) {
+            (
+                IconEntry::Playlist {
+                    playlist_id: a,
+                    contained: ac,
+                },
+                IconEntry::Playlist {
+                    playlist_id: b,
+                    contained: bc,
+                },
+            ) => bc
+                .cmp(ac)
+                .then_with(|| playlists[a].name.cmp(&playlists[b].name)),
+            _ => std::cmp::Ordering::Equal,
+        });
 
         // Fade out and fit based on size
         let icon_size = 20.0;
+        let dot_size = 8.0;
+        let badge_size = icon_size * 2.5;
         let mouse_pos = self.interaction.mouse_position;
 
-        if width < icon_size * icon_entries.len() as f32 {
+        let rating_badge = config.rating_style == RatingStyle::Number;
+        let full_size = |entry: &IconEntry| {
+            if rating_badge && matches!(entry, IconEntry::Star { .. }) {
+                badge_size
+            } else {
+                icon_size
+            }
+        };
+        let constrained = width < icon_entries.iter().map(full_size).sum::<f32>();
+        let compact = constrained && config.compact_icons;
+        if constrained {
             // Strip out all playlists that arent contained
             icon_entries.retain(|entry| {
                 if let IconEntry::Playlist { contained, .. } = entry {
@@ -295,11 +485,18 @@ impl CantusApp {
             });
         }
 
-        let num_icons = icon_entries.len();
-        let needed_width = icon_size * num_icons as f32;
-        if num_icons == 0 {
+        if icon_entries.is_empty() {
+            self.icon_entries = icon_entries;
             return;
         }
+        let entry_size = |entry: &IconEntry| {
+            if compact && matches!(entry, IconEntry::Playlist { .. }) {
+                dot_size
+            } else {
+                full_size(entry)
+            }
+        };
+        let needed_width = icon_entries.iter().map(entry_size).sum::<f32>();
 
         let fade_alpha = if hovered {
             1.0
@@ -307,10 +504,10 @@ impl CantusApp {
             ((width - needed_width) / (needed_width * 0.25)).clamp(0.0, 1.0)
         };
         let center_x = pos_x + width * 0.5;
-        let center_y = PANEL_START + CONFIG.height * 0.975;
+        let center_y = PANEL_START + config.height * 0.975;
 
         // Count only the standard icons for spacing
-        let half_icons = icon_entries
+        let half_width = icon_entries
             .iter()
             .filter(|entry| {
                 if let IconEntry::Playlist { contained, .. } = entry {
@@ -319,43 +516,51 @@ impl CantusApp {
                     true
                 }
             })
-            .count() as f32
+            .map(entry_size)
+            .sum::<f32>()
             / 2.0;
 
         let mut hover_rating_index = None;
-        let mut icon_data = Vec::with_capacity(num_icons);
+        let mut icon_data = std::mem::take(&mut self.icon_data);
+        icon_data.clear();
 
-        for (i, entry) in icon_entries.into_iter().enumerate() {
-            let origin_x = center_x + (i as f32 - half_icons) * icon_size;
-            let half_size = icon_size * 0.6; // Add slight hitbox padding
+        let mut offset = 0.0;
+        for &entry in &icon_entries {
+            let size = entry_size(&entry);
+            // Centre entries that aren't icon sized, like dots and the rating badge, in their space
+            let origin_x = center_x + offset - half_width + (size - icon_size) * 0.5;
+            offset += size;
+            let half_size = size * 0.6; // Add slight hitbox padding
+            let half_height = size.min(icon_size) * 0.6;
             let rect = Rect::new(
                 origin_x - half_size,
-                center_y - half_size,
+                center_y - half_height,
                 origin_x + half_size,
-                center_y + half_size,
+                center_y + half_height,
             );
             let is_hovered = rect.contains(mouse_pos) && self.interaction.mouse_pressure > 0.0;
 
             match &entry {
                 IconEntry::Star { index } => {
                     if is_hovered {
-                        hover_rating_index = Some(
-                            index * 2 + 1 + u8::from(mouse_pos.x >= (rect.x0 + rect.x1) * 0.5),
-                        );
+                        let fraction = (mouse_pos.x - rect.x0) / rect.width();
+                        hover_rating_index = Some(config.rating_slot(*index, fraction) + 1);
                     }
                     self.interaction.icon_hitboxes.push(IconHitbox {
                         rect,
                         track_id,
                         playlist_id: None,
                         rating_index: Some(*index),
+                        control: None,
                     });
                 }
-                IconEntry::Playlist { playlist, .. } => {
+                IconEntry::Playlist { playlist_id, .. } => {
                     self.interaction.icon_hitboxes.push(IconHitbox {
                         rect,
                         track_id,
-                        playlist_id: Some(playlist.id),
+                        playlist_id: Some(*playlist_id),
                         rating_index: None,
+                        control: None,
                     });
                 }
             }
@@ -370,27 +575,31 @@ impl CantusApp {
         });
 
         let display_rating = hover_rating_index.unwrap_or(track_rating_index);
-        let full_stars = display_rating / 2;
-        let has_half = display_rating % 2 == 1;
 
-        for (entry, is_hovered, origin_x) in icon_data {
+        for &(entry, is_hovered, origin_x) in &icon_data {
+            if rating_badge && matches!(entry, IconEntry::Star { .. }) {
+                if let Some(text_renderer) = &mut self.text_renderer {
+                    let slots = config.rating_playlist_names.len();
+                    text_renderer.render_rating_badge(
+                        display_rating,
+                        slots,
+                        (origin_x, center_y),
+                        fade_alpha,
+                    );
+                }
+                continue;
+            }
             let instance = IconInstance {
                 pos: [origin_x, center_y],
                 data: (((fade_alpha * 65535.0) as u32) << 16)
                     | (match entry {
                         IconEntry::Star { index } => {
-                            (if index < full_stars {
-                                1.0
-                            } else if index == full_stars && has_half {
-                                0.75
-                            } else {
-                                0.51
-                            } * 65535.0) as u32
+                            // Fullness is mapped into 0.5-1.0, just above 0.5 stays a star when empty
+                            let fill = config.star_fill(display_rating, index);
+                            ((0.5 + fill * 0.5).max(0.51) * 65535.0) as u32
                         }
-                        IconEntry::Playlist {
-                            playlist: _playlist,
-                            contained,
-                        } => {
+                        IconEntry::Playlist { .. } if compact => (65535.0 * 0.4) as u32,
+                        IconEntry::Playlist { contained, .. } => {
                             if !contained && !is_hovered {
                                 (65535.0 * 0.2) as u32
                             } else {
@@ -399,20 +608,112 @@ impl CantusApp {
                         }
                     }),
                 image_index: match entry {
-                    IconEntry::Playlist {
-                        playlist:
-                            CondensedPlaylist {
-                                image_url: Some(url),
-                                ..
-                            },
-                        contained: _contained,
-                    } => self.get_image_index(url),
-                    _ => 0,
+                    IconEntry::Playlist { playlist_id, .. } => playlists[&playlist_id]
+                        .image_url
+                        .as_deref()
+                        .map_or(0, |url| self.get_image_index(url)),
+                    IconEntry::Star { .. } => 0,
                 },
             };
             self.icon_pills.push(instance);
         }
+        self.icon_entries = icon_entries;
+        self.icon_data = icon_data;
+    }
+
+    /// Shuffle and repeat toggles above the playhead, shown while hovering it or when enabled.
+    pub fn draw_playback_controls(
+        &mut self,
+        track: &Track,
+        playhead_x: f32,
+        shuffle: bool,
+        repeat: RepeatMode,
+    ) {
+        let Some(track_id) = track.id else { return };
+        let icon_size = 20.0;
+        let center_y = PANEL_START + CONFIG.load().height * 0.025;
+        let mouse_pos = self.interaction.mouse_position;
+        let hovered = !self.interaction.dragging
+            && self.interaction.mouse_pressure > 0.0
+            && (mouse_pos.x - playhead_x).abs() <= icon_size * 2.0
+            && mouse_pos.y <= PANEL_START + CONFIG.load().height;
+
+        // Negative image indices select the control glyphs in the icon shader
+        let repeat_glyph = if repeat == RepeatMode::Track { -3 } else { -2 };
+        let like = track
+            .likeable()
+            .then(|| liked_state(track_id))
+            .flatten()
+            .map(|liked| (PlaybackControl::Like, 0.0, liked, -4));
+        for (control, offset, enabled, image_index) in [
+            Some((PlaybackControl::Shuffle, -1.0, shuffle, -1)),
+            like,
+            Some((
+                PlaybackControl::Repeat,
+                1.0,
+                repeat != RepeatMode::Off,
+                repeat_glyph,
+            )),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            let alpha = if hovered {
+                1.0
+            } else if enabled {
+                0.6
+            } else {
+                continue;
+            };
+            let origin_x = playhead_x + offset * icon_size;
+            let half_size = icon_size * 0.6;
+            let rect = Rect::new(
+                origin_x - half_size,
+                center_y - half_size,
+                origin_x + half_size,
+                center_y + half_size,
+            );
+            if hovered {
+                self.interaction.icon_hitboxes.push(IconHitbox {
+                    rect,
+                    track_id,
+                    playlist_id: None,
+                    rating_index: None,
+                    control: Some(control),
+                });
+            }
+            self.icon_pills.push(IconInstance {
+                pos: [origin_x, center_y],
+                data: (((alpha * 65535.0) as u32) << 16)
+                    | if enabled { 0 } else { (65535.0 * 0.2) as u32 },
+                image_index,
+            });
+        }
+    }
+}
+
+/// Start playing the specified track from the beginning without skipping through the queue.
+fn jump_to_track(track_id: TrackId) {
+    let (queue_index, position_in_queue) = {
+        let state = PLAYBACK_STATE.read();
+        let Some(position_in_queue) = state.queue.iter().position(|t| t.id == Some(track_id))
+        else {
+            error!("Track not found in queue");
+            return;
+        };
+        (state.queue_index, position_in_queue)
+    };
+    if queue_index == position_in_queue {
+        return;
     }
+    update_playback_state(|state| {
+        state.queue_index = position_in_queue;
+        state.progress = 0;
+        state.last_progress_update = Instant::now();
+        state.last_interaction = Instant::now() + Duration::from_millis(2000);
+    });
+    info!("Jumping to track {track_id}");
+    BACKEND.jump_to_track(&track_id, position_in_queue as isize - queue_index as isize);
 }
 
 /// Skip to the specified track in the queue.
@@ -451,60 +752,99 @@ fn skip_to_track(track_id: TrackId, position: f32, always_seek: bool) {
             "{} to track {track_id}, {skips} skips",
             if forward { "Skipping" } else { "Rewinding" }
         );
-        #[cfg(feature = "spotify")]
         for _ in 0..skips.min(10) {
-            let result = if forward {
-                // https://developer.spotify.com/documentation/web-api/reference/#/operations/skip-users-playback-to-next-track
-                crate::spotify::SPOTIFY_CLIENT.api_post("me/player/next")
+            if forward {
+                BACKEND.skip_next();
             } else {
-                // https://developer.spotify.com/documentation/web-api/reference/#/operations/skip-users-playback-to-previous-track
-                crate::spotify::SPOTIFY_CLIENT.api_post("me/player/previous")
-            };
-            if let Err(err) = result {
-                error!("Failed to skip to track: {err}");
+                BACKEND.skip_prev();
             }
         }
     }
     // Seek to the position
     if queue_index == position_in_queue || always_seek {
         let song_ms = ms_lookup[position_in_queue];
-        let milliseconds = if position < 0.05 {
-            0.0
-        } else {
-            song_ms as f32 * position
-        };
+        let milliseconds = resolve_seek(song_ms, position);
         info!(
             "Seeking track {track_id} to {}%",
-            (milliseconds / song_ms as f32 * 100.0).round()
+            (milliseconds as f32 / song_ms.max(1) as f32 * 100.0).round()
         );
         update_playback_state(|state| {
-            state.progress = milliseconds.round() as u32;
+            state.progress = milliseconds;
             state.last_progress_update = Instant::now();
             state.last_interaction = Instant::now() + Duration::from_millis(2000);
         });
+        BACKEND.seek_ms(milliseconds);
+    }
+}
 
-        #[cfg(feature = "spotify")]
-        {
-            // https://developer.spotify.com/documentation/web-api/reference/#/operations/seek-to-position-in-currently-playing-track
-            if let Err(err) = crate::spotify::SPOTIFY_CLIENT.api_put(&format!(
-                "me/player/seek?position_ms={}",
-                milliseconds.round()
-            )) {
-                error!("Failed to seek track: {err}");
-            }
-        }
+/// The position in milliseconds to seek to for a fraction of the way through a track.
+///
+/// Positions near the start (below 5%) snap to the beginning, drag maths can land outside the
+/// track so the result is kept within it.
+fn resolve_seek(song_ms: u32, position: f32) -> u32 {
+    if position.is_nan() || position < 0.05 {
+        return 0;
     }
+    (song_ms as f32 * position.min(1.0)).round() as u32
+}
+
+/// Skip forwards or backwards through the queue by a number of tracks.
+pub fn skip_by(offset: isize) {
+    let track_id = {
+        let state = PLAYBACK_STATE.read();
+        state
+            .queue_index
+            .checked_add_signed(offset)
+            .and_then(|index| state.queue.get(index))
+            .and_then(|track| track.id)
+    };
+    match track_id {
+        Some(track_id) => skip_to_track(track_id, 0.0, false),
+        // Past either end of the known queue, the service may still have somewhere to go
+        None if offset > 0 => BACKEND.skip_next(),
+        None => BACKEND.skip_prev(),
+    }
+}
+
+/// Seek the current track forwards or backwards by an offset in milliseconds.
+pub fn seek_by(offset_ms: i64) {
+    let milliseconds = {
+        let state = PLAYBACK_STATE.read();
+        let progress = i64::from(state.progress)
+            + if state.playing {
+                state.last_progress_update.elapsed().as_millis() as i64
+            } else {
+                0
+            };
+        (progress + offset_ms).max(0) as u32
+    };
+    seek_to(milliseconds);
+}
+
+/// Seek the current track to a position in milliseconds, clamped to its length.
+pub fn seek_to(milliseconds: u32) {
+    let state = PLAYBACK_STATE.read();
+    let Some(duration_ms) = state.queue.get(state.queue_index).map(|t| t.duration_ms) else {
+        return;
+    };
+    drop(state);
+    let milliseconds = milliseconds.min(duration_ms);
+    info!("Seeking current track to {milliseconds}ms");
+    update_playback_state(|state| {
+        state.progress = milliseconds;
+        state.last_progress_update = Instant::now();
+        state.last_interaction = Instant::now() + Duration::from_millis(2000);
+    });
+    BACKEND.seek_ms(milliseconds);
 }
 
-/// Update Spotify rating playlists for the given track.
+/// Update the rating playlists for the given track.
 fn update_star_rating(track_id: &TrackId, rating_slot: u8) {
-    if !CONFIG.ratings_enabled {
+    if !CONFIG.load().ratings_enabled {
         return;
     }
 
-    #[cfg(feature = "spotify")]
     let mut playlists_to_remove_from = Vec::new();
-    #[cfg(feature = "spotify")]
     let mut playlists_to_add_to = Vec::new();
 
     // Remove tracks from existing playlists, add to target playlist if not present
@@ -515,77 +855,70 @@ fn update_star_rating(track_id: &TrackId, rating_slot: u8) {
                 && playlist.rating_index != Some(rating_slot)
                 && playlist.tracks.remove(track_id)
             {
-                #[cfg(feature = "spotify")]
                 playlists_to_remove_from.push((playlist.id, playlist.name.clone()));
             }
             if playlist.rating_index == Some(rating_slot) && playlist.tracks.insert(*track_id) {
-                #[cfg(feature = "spotify")]
                 playlists_to_add_to.push((playlist.id, playlist.name.clone()));
             }
         });
     });
 
-    #[cfg(feature = "spotify")]
-    {
-        // Make the changes
-        for (playlist_id, playlist_name) in playlists_to_remove_from {
-            info!("Removing track {track_id} from rating playlist {playlist_name}");
-            let track_uri = format!("spotify:track:{track_id}");
-            // https://developer.spotify.com/documentation/web-api/reference/#/operations/remove-tracks-playlist
-            if let Err(err) = crate::spotify::SPOTIFY_CLIENT.api_delete_payload(
-                &format!("playlists/{playlist_id}/tracks"),
-                &format!(r#"{{"tracks": [ {{"uri": "{track_uri}"}} ]}}"#),
-            ) {
-                error!(
-                    "Failed to remove track {track_id} from rating playlist {playlist_name}: {err}"
-                );
-            }
+    // Make the changes
+    for (playlist_id, playlist_name) in playlists_to_remove_from {
+        info!("Removing track {track_id} from rating playlist {playlist_name}");
+        BACKEND.remove_from_playlist(track_id, &playlist_id);
+    }
+    for (playlist_id, playlist_name) in playlists_to_add_to {
+        info!("Adding track {track_id} to rating playlist {playlist_name}");
+        BACKEND.add_to_playlist(track_id, &playlist_id);
+    }
+
+    // Add the track the liked songs if its rated 3 stars or above
+    let liked = CONFIG.load().rating_liked(rating_slot);
+    match (BACKEND.is_liked(track_id), liked) {
+        (Some(true), false) => {
+            info!("Removing track {track_id} from liked songs");
+            BACKEND.set_liked(track_id, false);
         }
-        for (playlist_id, playlist_name) in playlists_to_add_to {
-            info!("Adding track {track_id} to rating playlist {playlist_name}");
-            let track_uri = format!("spotify:track:{track_id}");
-            // https://developer.spotify.com/documentation/web-api/reference/#/operations/add-tracks-to-playlist)
-            if let Err(err) = crate::spotify::SPOTIFY_CLIENT.api_post_payload(
-                &format!("playlists/{playlist_id}/tracks"),
-                &format!(r#"{{"uris": ["{track_uri}"]}}"#),
-            ) {
-                error!("Failed to add track {track_id} to rating playlist {playlist_name}: {err}");
-            }
+        (Some(false), true) => {
+            info!("Adding track {track_id} to liked songs");
+            BACKEND.set_liked(track_id, true);
         }
+        (None, _) => return,
+        _ => {}
+    }
+    LIKED_CACHE.insert(*track_id, Some(liked));
+}
 
-        // Add the track the liked songs if its rated above 3 stars
-        // https://developer.spotify.com/documentation/web-api/reference/#/operations/check-users-saved-tracks
-        match crate::spotify::SPOTIFY_CLIENT.api_get(&format!("me/tracks/contains/?ids={track_id}"))
-        {
-            Ok(already_liked) => match (already_liked == "[true]", rating_slot >= 5) {
-                (true, false) => {
-                    info!("Removing track {track_id} from liked songs");
-                    // https://developer.spotify.com/documentation/web-api/reference/#/operations/remove-tracks-user
-                    if let Err(err) = crate::spotify::SPOTIFY_CLIENT
-                        .api_delete(&format!("me/tracks/?ids={track_id}"))
-                    {
-                        error!("Failed to remove track {track_id} from liked songs: {err}");
-                    }
-                }
-                (false, true) => {
-                    info!("Adding track {track_id} to liked songs");
-                    // https://developer.spotify.com/documentation/web-api/reference/#/operations/save-tracks-user
-                    if let Err(err) = crate::spotify::SPOTIFY_CLIENT
-                        .api_put(&format!("me/tracks/?ids={track_id}"))
-                    {
-                        error!("Failed to add track {track_id} to liked songs: {err}");
-                    }
-                }
-                _ => {}
-            },
-            Err(err) => {
-                error!("Failed to check if track {track_id} is already liked: {err}");
-            }
-        }
+/// The cached liked state of a track, querying the backend in the background the first time.
+fn liked_state(track_id: TrackId) -> Option<bool> {
+    if let Some(liked) = LIKED_CACHE.get(&track_id) {
+        return *liked;
     }
+    LIKED_CACHE.insert(track_id, None);
+    spawn(move || {
+        if let Some(liked) = BACKEND.is_liked(&track_id) {
+            LIKED_CACHE.insert(track_id, Some(liked));
+        }
+    });
+    None
 }
 
-/// Toggle Spotify playlist membership for the given track.
+/// Add or remove the track from the liked songs.
+pub fn toggle_liked(track_id: &TrackId) {
+    let Some(liked) = LIKED_CACHE.get(track_id).and_then(|liked| *liked) else {
+        return;
+    };
+    info!(
+        "{} track {track_id} {} liked songs",
+        if liked { "Removing" } else { "Adding" },
+        if liked { "from" } else { "to" }
+    );
+    LIKED_CACHE.insert(*track_id, Some(!liked));
+    BACKEND.set_liked(track_id, !liked);
+}
+
+/// Toggle playlist membership for the given track.
 fn toggle_playlist_membership(track_id: &TrackId, playlist_id: &PlaylistId) {
     let Some((playlist_id, playlist_name, contained)) = PLAYBACK_STATE
         .read()
@@ -620,62 +953,175 @@ fn toggle_playlist_membership(track_id: &TrackId, playlist_id: &PlaylistId) {
         state.last_interaction = Instant::now() + Duration::from_millis(500);
     });
 
-    #[cfg(feature = "spotify")]
-    {
-        let track_uri = format!("spotify:track:{track_id}");
-        let result = if contained {
-            crate::spotify::SPOTIFY_CLIENT.api_delete_payload(
-                &format!("playlists/{playlist_id}/tracks"),
-                &format!(r#"{{"tracks": [ {{"uri": "{track_uri}"}} ]}}"#),
-            )
-        } else {
-            crate::spotify::SPOTIFY_CLIENT.api_post_payload(
-                &format!("playlists/{playlist_id}/tracks"),
-                &format!(r#"{{"uris": ["{track_uri}"]}}"#),
-            )
-        };
-        if let Err(err) = result {
-            error!(
-                "Failed to {} track {track_id} {} playlist {playlist_name}: {err}",
-                if contained { "remove" } else { "add" },
-                if contained { "from" } else { "to" }
-            );
-        }
+    if contained {
+        BACKEND.remove_from_playlist(track_id, &playlist_id);
+    } else {
+        BACKEND.add_to_playlist(track_id, &playlist_id);
     }
 }
 
-/// Set Spotify playing or paused.
-fn toggle_playing(play: bool) {
+/// Set playback playing or paused.
+pub fn toggle_playing(play: bool) {
     info!("{} current track", if play { "Playing" } else { "Pausing" });
     update_playback_state(|state| {
         state.playing = play;
+        state.last_play_toggle = Instant::now() + Duration::from_millis(500);
     });
-
-    #[cfg(feature = "spotify")]
-    {
-        // https://developer.spotify.com/documentation/web-api/reference/#/operations/start-a-users-playback
-        // https://developer.spotify.com/documentation/web-api/reference/#/operations/pause-a-users-playback
-        if play {
-            if let Err(err) = crate::spotify::SPOTIFY_CLIENT.api_put("me/player/play") {
-                error!("Failed to play playback: {err}");
-            }
-        } else if let Err(err) = crate::spotify::SPOTIFY_CLIENT.api_put("me/player/pause") {
-            error!("Failed to pause playback: {err}");
+    if !play {
+        BACKEND.pause();
+        return;
+    }
+    // With no active device there is nothing to resume, so start playing on one instead
+    let device = {
+        let state = PLAYBACK_STATE.read();
+        if state.devices.iter().any(|device| device.is_active) {
+            None
+        } else {
+            state
+                .devices
+                .iter()
+                .find(|device| device.id.is_some())
+                .cloned()
         }
+    };
+    if let Some(PlaybackDevice {
+        id: Some(id), name, ..
+    }) = device
+    {
+        info!("No active device, starting playback on {name}");
+        BACKEND.transfer_playback(&id);
+    } else {
+        BACKEND.play();
     }
 }
 
+/// Toggle shuffle on the current playback.
+pub fn toggle_shuffle() {
+    let shuffle = !PLAYBACK_STATE.read().shuffle;
+    info!("{} shuffle", if shuffle { "Enabling" } else { "Disabling" });
+    update_playback_state(|state| {
+        state.shuffle = shuffle;
+        state.last_interaction = Instant::now() + Duration::from_millis(500);
+    });
+    BACKEND.set_shuffle(shuffle);
+}
+
+/// Cycle the repeat mode between off, context and track.
+pub fn cycle_repeat() {
+    let repeat = PLAYBACK_STATE.read().repeat.next();
+    info!("Setting repeat mode to {repeat:?}");
+    update_playback_state(|state| {
+        state.repeat = repeat;
+        state.last_interaction = Instant::now() + Duration::from_millis(500);
+    });
+    BACKEND.set_repeat(repeat);
+}
+
 /// Set the volume of the current playback device.
-fn set_volume(volume_percent: u8) {
+pub fn set_volume(volume_percent: u8) {
     info!("Setting volume to {}%", volume_percent);
+    BACKEND.set_volume(volume_percent);
+}
 
-    #[cfg(feature = "spotify")]
-    {
-        // https://developer.spotify.com/documentation/web-api/reference/#/operations/set-volume-for-users-playback
-        if let Err(err) = crate::spotify::SPOTIFY_CLIENT
-            .api_put(&format!("me/player/volume?volume_percent={volume_percent}"))
-        {
-            error!("Failed to set volume: {err}");
-        }
+/// Mute the current playback device, or restore the volume it had before being muted.
+pub fn toggle_mute() {
+    let mut volume = None;
+    update_playback_state(|state| {
+        let Some(current) = state.volume else {
+            return;
+        };
+        // Raising the volume while muted counts as unmuting, so the next toggle mutes again
+        let target = match state.pre_mute_volume.take() {
+            Some(previous) if current == 0 => previous,
+            _ if current == 0 => return,
+            _ => {
+                state.pre_mute_volume = Some(current);
+                0
+            }
+        };
+        state.volume = Some(target);
+        state.last_interaction = Instant::now();
+        volume = Some(target);
+    });
+    if let Some(volume) = volume {
+        set_volume(volume);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Album, Artist};
+
+    const SONG_MS: u32 = 200_000;
+
+    #[test]
+    fn seek_snaps_to_the_start_below_five_percent() {
+        assert_eq!(resolve_seek(SONG_MS, 0.05), 10_000);
+        assert_eq!(resolve_seek(SONG_MS, 0.049_99), 0);
+        assert_eq!(resolve_seek(SONG_MS, 0.0), 0);
+    }
+
+    #[test]
+    fn seek_stays_within_the_track() {
+        assert_eq!(resolve_seek(SONG_MS, 1.0), SONG_MS);
+        assert_eq!(resolve_seek(SONG_MS, 1.5), SONG_MS);
+        assert_eq!(resolve_seek(SONG_MS, f32::INFINITY), SONG_MS);
+        assert_eq!(resolve_seek(SONG_MS, -0.5), 0);
+        assert_eq!(resolve_seek(SONG_MS, f32::NEG_INFINITY), 0);
+        assert_eq!(resolve_seek(SONG_MS, f32::NAN), 0);
+    }
+
+    #[test]
+    fn seek_in_an_empty_track_is_the_start() {
+        assert_eq!(resolve_seek(0, 0.5), 0);
+        assert_eq!(resolve_seek(0, 2.0), 0);
+    }
+
+    fn queue(names: &[&str]) -> Vec<Track> {
+        names
+            .iter()
+            .map(|name| Track {
+                id: None,
+                name: (*name).into(),
+                album: Album {
+                    id: None,
+                    name: String::new(),
+                    image: None,
+                },
+                artist: Artist {
+                    id: None,
+                    name: String::new(),
+                    image: None,
+                },
+                duration_ms: SONG_MS,
+                is_local: false,
+                uri: String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn search_ignores_case() {
+        let queue = queue(&["Intro", "Love Story", "Outro"]);
+        assert_eq!(search_match(&queue, 0, "love"), Some(1));
+        assert_eq!(search_match(&queue, 0, "STORY"), Some(1));
+    }
+
+    #[test]
+    fn search_wraps_around_to_the_current_track_last() {
+        let queue = queue(&["Style", "Blank Space", "Style (Live)", "Shake It Off"]);
+        assert_eq!(search_match(&queue, 0, "style"), Some(2));
+        assert_eq!(search_match(&queue, 2, "style"), Some(0));
+        assert_eq!(search_match(&queue, 1, "blank"), Some(1));
+        assert_eq!(search_match(&queue, 3, "shake"), Some(3));
+        assert_eq!(search_match(&queue, 3, "space"), Some(1));
+    }
+
+    #[test]
+    fn empty_search_matches_nothing() {
+        let queue = queue(&["Intro"]);
+        assert_eq!(search_match(&queue, 0, ""), None);
+        assert_eq!(search_match(&[], 0, "intro"), None);
     }
 }
@@ -0,0 +1,170 @@
+use crate::interaction::{
+    cycle_repeat, seek_by, seek_to, skip_by, toggle_liked, toggle_mute, toggle_playing,
+    toggle_shuffle,
+};
+use crate::{
+    LIKED_CACHE, PLAYBACK_STATE, backend::BACKEND, interaction::set_volume, update_playback_state,
+};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::spawn,
+};
+use tracing::{error, info, warn};
+
+/// Set once this process owns the socket, so exiting never removes another instance's.
+static SOCKET_BOUND: AtomicBool = AtomicBool::new(false);
+
+/// Where the control socket lives, `$XDG_RUNTIME_DIR/cantus.sock`.
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("cantus.sock")
+}
+
+/// Listen on the control socket so scripts and window manager keybindings can drive playback.
+pub fn listen() {
+    let path = socket_path();
+    // A socket left behind by a previous run refuses new binds
+    if path.exists() && UnixStream::connect(&path).is_err() {
+        let _ = fs::remove_file(&path);
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to bind control socket {}: {err}", path.display());
+            return;
+        }
+    };
+    SOCKET_BOUND.store(true, Ordering::Relaxed);
+    info!("Listening for commands on {}", path.display());
+    spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    spawn(move || handle_client(stream));
+                }
+                Err(err) => warn!("Failed to accept control connection: {err}"),
+            }
+        }
+    });
+}
+
+/// Remove the control socket on exit, if this process created it.
+pub fn remove_socket() {
+    if SOCKET_BOUND.swap(false, Ordering::Relaxed) {
+        let _ = fs::remove_file(socket_path());
+    }
+}
+
+/// Read commands from a client, one per line, answering each with `ok` or `error: <reason>`.
+///
+/// Commands:
+/// - `play`, `pause`, `toggle`
+/// - `next`, `prev`
+/// - `volume <percent>`, or `volume +<step>` / `volume -<step>` to change it relatively
+/// - `seek <seconds>`, or `seek +<seconds>` / `seek -<seconds>` to seek relatively
+/// - `shuffle`, `repeat` and `like` toggle their setting like the buttons above the playhead
+/// - `mute` mutes the device, or restores the volume it had before
+fn handle_client(stream: UnixStream) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match run_command(&line) {
+            Ok(()) => "ok".to_owned(),
+            Err(err) => {
+                warn!("Control command `{}` failed: {err}", line.trim());
+                format!("error: {err}")
+            }
+        };
+        if writeln!(writer, "{reply}").is_err() {
+            return;
+        }
+    }
+}
+
+fn run_command(line: &str) -> Result<(), String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let argument = words.next();
+    if words.next().is_some() {
+        return Err("too many arguments".to_owned());
+    }
+    match (command, argument) {
+        ("play", None) => toggle_playing(true),
+        ("pause", None) => toggle_playing(false),
+        ("toggle", None) => toggle_playing(!PLAYBACK_STATE.read().playing),
+        ("next", None) => skip_by(1),
+        ("prev", None) => skip_by(-1),
+        ("shuffle", None) => toggle_shuffle(),
+        ("repeat", None) => cycle_repeat(),
+        ("mute", None) => toggle_mute(),
+        ("like", None) => {
+            let track_id = {
+                let state = PLAYBACK_STATE.read();
+                state
+                    .queue
+                    .get(state.queue_index)
+                    .filter(|track| track.likeable())
+                    .and_then(|track| track.id)
+            };
+            let track_id = track_id.ok_or("no track to like")?;
+            // The liked state is only fetched once the like button has been shown
+            if LIKED_CACHE
+                .get(&track_id)
+                .and_then(|liked| *liked)
+                .is_none()
+            {
+                let liked = BACKEND
+                    .is_liked(&track_id)
+                    .ok_or("unable to tell if the track is liked")?;
+                LIKED_CACHE.insert(track_id, Some(liked));
+            }
+            toggle_liked(&track_id);
+        }
+        ("volume", Some(value)) => {
+            let (relative, amount) = parse_signed(value)?;
+            let current = PLAYBACK_STATE.read().volume;
+            let volume = match (relative, current) {
+                (false, _) => amount,
+                (true, Some(current)) => i64::from(current) + amount,
+                (true, None) => return Err("the volume is unknown".to_owned()),
+            }
+            .clamp(0, 100) as u8;
+            update_playback_state(|state| state.volume = Some(volume));
+            set_volume(volume);
+        }
+        ("seek", Some(value)) => {
+            let (relative, seconds) = parse_signed(value)?;
+            if relative {
+                seek_by(seconds.saturating_mul(1000));
+            } else {
+                let milliseconds = u32::try_from(seconds.saturating_mul(1000))
+                    .map_err(|_| "seek position out of range")?;
+                seek_to(milliseconds);
+            }
+        }
+        ("volume" | "seek", None) => return Err(format!("`{command}` needs a value")),
+        (_, _) => return Err(format!("unknown command `{}`", line.trim())),
+    }
+    Ok(())
+}
+
+/// Parse a number, and whether it had a sign marking it as relative.
+fn parse_signed(value: &str) -> Result<(bool, i64), String> {
+    let relative = value.starts_with(['+', '-']);
+    value
+        .parse()
+        .map(|number| (relative, number))
+        .map_err(|_| format!("`{value}` is not a whole number"))
+}
@@ -1,4 +1,9 @@
-use crate::{CantusApp, PANEL_EXTENSION, PANEL_START, config::CONFIG, render::Point};
+use crate::{
+    CantusApp, PANEL_EXTENSION, PANEL_START, PLAYBACK_STATE,
+    config::{CONFIG, Config, Monitors},
+    interaction::KeyInput,
+    render::{Point, Rect},
+};
 use itertools::Itertools;
 use raw_window_handle::{
     RawDisplayHandle, RawWindowHandle, WaylandDisplayHandle, WaylandWindowHandle,
@@ -6,28 +11,46 @@ use raw_window_handle::{
 use std::{
     collections::hash_map::DefaultHasher,
     ffi::c_void,
+    fs::File,
     hash::{Hash, Hasher},
+    os::{fd::OwnedFd, unix::fs::FileExt},
     ptr::NonNull,
+    sync::{
+        OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
-use tracing::error;
+use tracing::{error, info, warn};
 use wayland_client::{
     Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    backend::ObjectId,
     protocol::{
         wl_callback::{self, WlCallback},
         wl_compositor::{self, WlCompositor},
+        wl_keyboard::{self, WlKeyboard},
         wl_output::{self, WlOutput},
         wl_pointer::{self, WlPointer},
         wl_region::{self, WlRegion},
         wl_registry::{self, WlRegistry},
         wl_seat::{self, WlSeat},
         wl_surface::{self, WlSurface},
+        wl_touch::{self, WlTouch},
     },
 };
 use wayland_protocols::wp::{
+    cursor_shape::v1::client::{
+        wp_cursor_shape_device_v1::{self, Shape as CursorShape, WpCursorShapeDeviceV1},
+        wp_cursor_shape_manager_v1::{self, WpCursorShapeManagerV1},
+    },
     fractional_scale::v1::client::{
         wp_fractional_scale_manager_v1::{self, WpFractionalScaleManagerV1},
         wp_fractional_scale_v1::{self, WpFractionalScaleV1},
     },
+    idle_inhibit::zv1::client::{
+        zwp_idle_inhibit_manager_v1::{self, ZwpIdleInhibitManagerV1},
+        zwp_idle_inhibitor_v1::{self, ZwpIdleInhibitorV1},
+    },
     viewporter::client::{
         wp_viewport::{self, WpViewport},
         wp_viewporter::{self, WpViewporter},
@@ -35,9 +58,47 @@ use wayland_protocols::wp::{
 };
 use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_shell_v1::{self, Layer as LayerStyle, ZwlrLayerShellV1},
-    zwlr_layer_surface_v1::{self, Anchor as LayerAnchor, ZwlrLayerSurfaceV1},
+    zwlr_layer_surface_v1::{
+        self, Anchor as LayerAnchor, KeyboardInteractivity, ZwlrLayerSurfaceV1,
+    },
 };
 use wgpu::SurfaceTargetUnsafe;
+use xkbcommon::xkb;
+
+/// How long to keep drawing after a wake up, so hover and toggle animations can finish.
+const REDRAW_SETTLE: Duration = Duration::from_millis(1500);
+
+/// Set when something outside of frame callbacks changed what should be on screen.
+static REDRAW_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set by pointer and touch events, which keep drawing for a while so hover animations finish.
+static INPUT_RECEIVED: AtomicBool = AtomicBool::new(false);
+/// Set when the config file was reloaded and the surfaces need placing again.
+static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+/// Set when the process was asked to quit, the event loop breaks on its next wake up.
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static WAKER: OnceLock<(Connection, QueueHandle<LayerShellApp>)> = OnceLock::new();
+
+/// Wake the event loop to draw idle surfaces again, safe to call from any thread.
+pub fn request_redraw() {
+    REDRAW_REQUESTED.store(true, Ordering::Relaxed);
+    // A roundtrip with the compositor is enough to return from the blocking dispatch
+    if let Some((connection, qhandle)) = WAKER.get() {
+        connection.display().sync(qhandle, ());
+        let _ = connection.flush();
+    }
+}
+
+/// Resize and redraw every surface after the config changed, safe to call from any thread.
+pub fn request_reconfigure() {
+    CONFIG_CHANGED.store(true, Ordering::Relaxed);
+    request_redraw();
+}
+
+/// Stop the event loop so `run` returns, safe to call from any thread.
+pub fn request_exit() {
+    EXIT_REQUESTED.store(true, Ordering::Relaxed);
+    request_redraw();
+}
 
 pub fn run() {
     let connection = Connection::connect_to_env().expect("Failed to connect to Wayland display");
@@ -52,74 +113,181 @@ pub fn run() {
     event_queue
         .roundtrip(&mut app)
         .expect("Initial roundtrip failed");
-    let compositor = app.compositor.take().expect("Missing compositor");
-    let layer_shell = app.layer_shell.take().expect("Missing layer shell");
+    assert!(app.compositor.is_some(), "Missing compositor");
+    assert!(app.layer_shell.is_some(), "Missing layer shell");
     assert!(!app.outputs.is_empty(), "No Wayland outputs found");
 
     event_queue
         .roundtrip(&mut app)
         .expect("Failed to fetch output details");
 
-    let wl_surface = compositor.create_surface(&qhandle, ());
-    let surface_ptr = NonNull::new(wl_surface.id().as_ptr().cast::<c_void>())
-        .expect("Failed to get surface pointer");
-    app.surface_ptr = Some(surface_ptr);
-    assert!(app.try_select_output(), "Failed to select a Wayland output");
-
-    let surface = app.wl_surface.insert(wl_surface);
-    if let (Some(vp), Some(fm)) = (app.viewporter.take(), app.fractional_manager.take()) {
-        app.viewport = Some(vp.get_viewport(surface, &qhandle, ()));
-        app.fractional = Some(fm.get_fractional_scale(surface, &qhandle, ()));
-    }
-
-    let layer_surface = layer_shell.get_layer_surface(
-        surface,
-        app.outputs.get(app.output_index).map(|info| &info.handle),
-        match CONFIG.layer.as_str() {
-            "background" => LayerStyle::Background,
-            "bottom" => LayerStyle::Bottom,
-            "top" => LayerStyle::Top,
-            "overlay" => LayerStyle::Overlay,
-            other => {
-                error!("Invalid layer '{other}', defaulting to 'top'");
-                LayerStyle::Top
-            }
-        },
-        "cantus".into(),
-        &qhandle,
-        (),
+    app.update_surfaces(&qhandle);
+    assert!(
+        !app.surfaces.is_empty(),
+        "Failed to select a Wayland output"
     );
-    let total_height = CONFIG.height + PANEL_EXTENSION + PANEL_START;
-    layer_surface.set_size(0, total_height as u32);
-    layer_surface.set_anchor(match CONFIG.layer_anchor.as_str() {
-        "top" => LayerAnchor::Top | LayerAnchor::Left | LayerAnchor::Right,
-        "bottom" => LayerAnchor::Bottom | LayerAnchor::Left | LayerAnchor::Right,
-        other => {
-            error!("Invalid layer anchor '{other}', defaulting to 'top'");
-            LayerAnchor::Top | LayerAnchor::Left | LayerAnchor::Right
-        }
-    });
-    layer_surface.set_margin(0, 0, 0, 0);
-    layer_surface.set_exclusive_zone(-1);
-
-    surface.commit();
+    app.initialized = true;
     connection.flush().expect("Failed to flush initial commit");
-
-    app.compositor = Some(compositor);
+    let _ = WAKER.set((connection, qhandle.clone()));
 
     while !app.should_exit {
         event_queue
             .blocking_dispatch(&mut app)
             .expect("Wayland dispatch error");
+        if EXIT_REQUESTED.load(Ordering::Relaxed) {
+            app.should_exit = true;
+            break;
+        }
+        if CONFIG_CHANGED.swap(false, Ordering::Relaxed) {
+            app.apply_config(&qhandle);
+        }
+        let input = INPUT_RECEIVED.swap(false, Ordering::Relaxed);
+        if REDRAW_REQUESTED.swap(false, Ordering::Relaxed) || input {
+            app.wake_surfaces(&qhandle, input);
+        }
+    }
+}
+
+fn config_layer() -> LayerStyle {
+    match CONFIG.load().layer.as_str() {
+        "background" => LayerStyle::Background,
+        "bottom" => LayerStyle::Bottom,
+        "top" => LayerStyle::Top,
+        "overlay" => LayerStyle::Overlay,
+        other => {
+            error!("Invalid layer '{other}', defaulting to 'top'");
+            LayerStyle::Top
+        }
+    }
+}
+
+/// Size and anchor the layer surface to the configured edge, a `hidden` bar reserves no space.
+fn place_layer_surface(layer_surface: &ZwlrLayerSurfaceV1, hidden: bool) {
+    let config = CONFIG.load();
+    let (width, height) = surface_size(&CONFIG.load());
+    if config.vertical() {
+        layer_surface.set_size(width as u32, 0);
+        layer_surface.set_anchor(match config.layer_anchor.as_str() {
+            "left" => LayerAnchor::Left | LayerAnchor::Top | LayerAnchor::Bottom,
+            "right" => LayerAnchor::Right | LayerAnchor::Top | LayerAnchor::Bottom,
+            other => {
+                error!("Invalid vertical layer anchor '{other}', defaulting to 'left'");
+                LayerAnchor::Left | LayerAnchor::Top | LayerAnchor::Bottom
+            }
+        });
+    } else {
+        layer_surface.set_size(0, height as u32);
+        layer_surface.set_anchor(match config.layer_anchor.as_str() {
+            "top" => LayerAnchor::Top | LayerAnchor::Left | LayerAnchor::Right,
+            "bottom" => LayerAnchor::Bottom | LayerAnchor::Left | LayerAnchor::Right,
+            other => {
+                error!("Invalid layer anchor '{other}', defaulting to 'top'");
+                LayerAnchor::Top | LayerAnchor::Left | LayerAnchor::Right
+            }
+        });
+    }
+    let [top, right, bottom, left] = config.margin;
+    layer_surface.set_margin(top, right, bottom, left);
+    // Behind the windows there's nothing to keep clear of, and reserving space would push them
+    let exclusive_zone = if hidden || config.layer == "background" {
+        0
+    } else {
+        config.exclusive_zone.unwrap_or(-1)
+    };
+    layer_surface.set_exclusive_zone(exclusive_zone);
+    // Focus only comes when the bar is clicked, typing elsewhere is never taken over
+    layer_surface.set_keyboard_interactivity(if config.interactive && config.queue_search {
+        KeyboardInteractivity::OnDemand
+    } else {
+        KeyboardInteractivity::None
+    });
+}
+
+/// Compile the keymap the compositor sent, so keys type what they would in any other window.
+fn read_keymap(fd: OwnedFd, size: u32) -> Option<xkb::State> {
+    let mut contents = vec![0; size as usize];
+    // Read from the start whatever offset the shared file is at
+    if let Err(err) = File::from(fd).read_exact_at(&mut contents, 0) {
+        warn!("Failed to read the keymap: {err}");
+        return None;
+    }
+    let text = String::from_utf8_lossy(&contents)
+        .trim_end_matches('\0')
+        .to_owned();
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let Some(keymap) = xkb::Keymap::new_from_string(
+        &context,
+        text,
+        xkb::KEYMAP_FORMAT_TEXT_V1,
+        xkb::KEYMAP_COMPILE_NO_FLAGS,
+    ) else {
+        warn!("Failed to compile the keymap, the queue search won't take any keys");
+        return None;
+    };
+    Some(xkb::State::new(&keymap))
+}
+
+/// Turn the text a key types into input for the queue search.
+fn key_input(text: String) -> Option<KeyInput> {
+    // Enter, Backspace and Escape type their control characters
+    match text.as_str() {
+        "\r" | "\n" => Some(KeyInput::Enter),
+        "\u{8}" => Some(KeyInput::Backspace),
+        "\u{1b}" => Some(KeyInput::Escape),
+        _ if !text.is_empty() && !text.chars().any(char::is_control) => Some(KeyInput::Text(text)),
+        _ => None,
+    }
+}
+
+/// Size of the surface, the scene is always laid out horizontally and turned for vertical bars.
+fn surface_size(config: &Config) -> (f32, f32) {
+    let total_height = config.height + PANEL_EXTENSION + PANEL_START;
+    if config.vertical() {
+        (total_height, config.width)
+    } else {
+        (config.width, total_height)
+    }
+}
+
+/// Map a point on the surface into scene coordinates.
+fn scene_point(config: &Config, x: f64, y: f64) -> Point {
+    let mut point = if config.vertical() {
+        let (surface_width, _) = surface_size(config);
+        Point::new(y as f32, surface_width - x as f32)
+    } else {
+        Point::new(x as f32, y as f32)
+    };
+    if config.mirrored() {
+        point.x = config.width - point.x;
+    }
+    point
+}
+
+/// Map a scene rect back onto the surface.
+fn surface_rect(config: &Config, r: &Rect) -> Rect {
+    let r = if config.mirrored() {
+        Rect::new(config.width - r.x1, r.y0, config.width - r.x0, r.y1)
+    } else {
+        *r
+    };
+    if config.vertical() {
+        let (surface_width, _) = surface_size(config);
+        Rect::new(surface_width - r.y1, r.x0, surface_width - r.y0, r.x1)
+    } else {
+        r
     }
 }
 
 struct OutputInfo {
     handle: WlOutput,
+    /// The registry name, used to match `GlobalRemove` events.
+    global_name: u32,
     name: Option<String>,
     description: Option<String>,
     make: Option<String>,
     model: Option<String>,
+    /// Integer scale of the output, used for its surface when fractional scaling is unavailable.
+    scale: i32,
 }
 
 impl OutputInfo {
@@ -137,62 +305,33 @@ impl OutputInfo {
     }
 }
 
-pub struct LayerShellApp {
-    pub cantus: CantusApp,
-
+/// A layer surface on a single output, with its own render surface, scale and interaction state.
+struct OutputSurface {
+    cantus: CantusApp,
+    output: WlOutput,
     is_configured: bool,
-    should_exit: bool,
-
-    compositor: Option<WlCompositor>,
-    layer_shell: Option<ZwlrLayerShellV1>,
-    seat: Option<WlSeat>,
-    pointer: Option<WlPointer>,
-    outputs: Vec<OutputInfo>,
-    output_index: usize,
 
-    surface_ptr: Option<NonNull<c_void>>,
-    wl_surface: Option<WlSurface>,
+    surface_ptr: NonNull<c_void>,
+    wl_surface: WlSurface,
+    layer_surface: ZwlrLayerSurfaceV1,
     viewport: Option<WpViewport>,
     fractional: Option<WpFractionalScaleV1>,
     frame_callback: Option<WlCallback>,
-    viewporter: Option<WpViewporter>,
-    fractional_manager: Option<WpFractionalScaleManagerV1>,
-    display_ptr: NonNull<c_void>,
+    /// The scale the compositor asked for, kept so `scale_override` can be turned off again.
+    detected_scale: f32,
+    /// Keeps the screen awake while music plays, when enabled with `inhibit_idle`.
+    idle_inhibitor: Option<ZwpIdleInhibitorV1>,
 }
 
-impl LayerShellApp {
-    fn new(display_ptr: NonNull<c_void>) -> Self {
-        Self {
-            cantus: CantusApp::default(),
-            is_configured: false,
-            should_exit: false,
-            compositor: None,
-            layer_shell: None,
-            seat: None,
-            pointer: None,
-            outputs: Vec::new(),
-            output_index: 0,
-            surface_ptr: None,
-            wl_surface: None,
-            viewport: None,
-            fractional: None,
-            frame_callback: None,
-            viewporter: None,
-            fractional_manager: None,
-            display_ptr,
-        }
-    }
-
-    fn request_frame(&mut self, qhandle: &QueueHandle<Self>) {
+impl OutputSurface {
+    fn request_frame(&mut self, qhandle: &QueueHandle<LayerShellApp>) {
         if self.frame_callback.is_some() {
             return;
         }
-        if let Some(surface) = &self.wl_surface {
-            self.frame_callback = Some(surface.frame(qhandle, ()));
-        }
+        self.frame_callback = Some(self.wl_surface.frame(qhandle, ()));
     }
 
-    fn ensure_surface(&mut self, width: f32, height: f32) {
+    fn ensure_surface(&mut self, display_ptr: NonNull<c_void>, width: f32, height: f32) {
         if width == 0.0 || height == 0.0 || !self.is_configured {
             return;
         }
@@ -200,19 +339,19 @@ impl LayerShellApp {
         let recreate = self.cantus.gpu_resources.as_ref().is_none_or(|surface| {
             surface.surface_config.width != width as u32
                 || surface.surface_config.height != height as u32
+                || surface.device_lost.load(Ordering::Relaxed)
+                || surface.requested_samples != CONFIG.load().msaa_samples
         });
         if !recreate {
             return;
         }
+        // Let go of the old surface and device first, a lost one can't be reused for anything and
+        // the new resources start with an empty image table so all the art is uploaded again
+        self.cantus.gpu_resources = None;
 
-        let Some(surface_ptr) = self.surface_ptr else {
-            return;
-        };
         let target = SurfaceTargetUnsafe::RawHandle {
-            raw_display_handle: RawDisplayHandle::Wayland(WaylandDisplayHandle::new(
-                self.display_ptr,
-            )),
-            raw_window_handle: RawWindowHandle::Wayland(WaylandWindowHandle::new(surface_ptr)),
+            raw_display_handle: RawDisplayHandle::Wayland(WaylandDisplayHandle::new(display_ptr)),
+            raw_window_handle: RawWindowHandle::Wayland(WaylandWindowHandle::new(self.surface_ptr)),
         };
         let surface = unsafe { self.cantus.instance.create_surface_unsafe(target) }
             .expect("Failed to create surface");
@@ -221,65 +360,84 @@ impl LayerShellApp {
             .configure_render_surface(surface, width as u32, height as u32);
     }
 
-    fn try_select_output(&mut self) -> bool {
-        if self.outputs.is_empty() {
-            return false;
+    fn try_render_frame(
+        &mut self,
+        display_ptr: NonNull<c_void>,
+        compositor: Option<&WlCompositor>,
+        qhandle: &QueueHandle<LayerShellApp>,
+    ) {
+        if !self.is_configured {
+            return;
         }
-
-        self.output_index = CONFIG
-            .monitor
-            .as_ref()
-            .and_then(|target| self.outputs.iter().position(|info| info.matches(target)))
-            .unwrap_or(0);
-        true
-    }
-
-    fn try_render_frame(&mut self, qhandle: &QueueHandle<Self>) {
         let scale = self.cantus.scale_factor;
-        let buffer_width = (CONFIG.width * scale).round();
-        let buffer_height = ((CONFIG.height + PANEL_EXTENSION + PANEL_START) * scale).round();
-        self.ensure_surface(buffer_width, buffer_height);
+        let (width, height) = surface_size(&CONFIG.load());
+        let buffer_width = (width * scale).round();
+        let buffer_height = (height * scale).round();
+        self.ensure_surface(display_ptr, buffer_width, buffer_height);
 
-        self.update_input_region(qhandle);
+        if let Some(compositor) = compositor {
+            self.update_input_region(compositor, qhandle);
+        }
 
         self.cantus.render();
-        self.request_frame(qhandle);
-        if let Some(surface) = &self.wl_surface {
-            surface.commit();
+        // Settled surfaces stop asking for frames until something wakes them
+        if self.cantus.needs_redraw() {
+            self.request_frame(qhandle);
         }
+        self.wl_surface.commit();
+    }
+
+    /// Set the scale to render at, without a viewport the buffer can only be scaled by whole numbers.
+    fn set_scale(&mut self, scale: f32) {
+        self.detected_scale = scale;
+        let scale = CONFIG.load().scale_override.unwrap_or(scale);
+        self.cantus.scale_factor = if self.viewport.is_some() {
+            scale
+        } else {
+            scale.ceil()
+        };
     }
 
     fn update_scale_and_viewport(&self) {
         let scale = self.cantus.scale_factor;
-        let total_height = CONFIG.height + PANEL_EXTENSION + PANEL_START;
-        if let Some(surface) = &self.wl_surface {
-            surface.set_buffer_scale(if self.viewport.is_some() {
+        let (width, height) = surface_size(&CONFIG.load());
+        self.wl_surface
+            .set_buffer_scale(if self.viewport.is_some() {
                 1
             } else {
-                scale.ceil() as i32
+                scale as i32
             });
-        }
         if let Some(viewport) = &self.viewport {
             viewport.set_source(
                 0.0,
                 0.0,
-                f64::from(CONFIG.width * scale).round(),
-                f64::from(total_height * scale).round(),
+                f64::from(width * scale).round(),
+                f64::from(height * scale).round(),
             );
-            viewport.set_destination(CONFIG.width as i32, total_height as i32);
+            viewport.set_destination(width as i32, height as i32);
         }
     }
 
-    fn update_input_region(&mut self, qhandle: &QueueHandle<Self>) {
-        let (Some(wl_surface), Some(compositor)) = (&self.wl_surface, &self.compositor) else {
-            return;
-        };
+    fn update_input_region(
+        &mut self,
+        compositor: &WlCompositor,
+        qhandle: &QueueHandle<LayerShellApp>,
+    ) {
+        // Only what's on the surface, tracks scrolled off the ends or collapsed to nothing are left out
+        let bounds = Rect::new(
+            0.0,
+            0.0,
+            CONFIG.load().width,
+            CONFIG.load().height + PANEL_START + PANEL_EXTENSION,
+        );
+        // Without `interactive` the region stays empty, so input passes through to what's below
+        let interactive = CONFIG.load().interactive;
         let rects = self
             .cantus
             .interaction
             .track_hitboxes
             .iter()
-            .map(|(_, r, _)| r)
+            .map(|h| &h.rect)
             .chain(
                 self.cantus
                     .interaction
@@ -287,6 +445,8 @@ impl LayerShellApp {
                     .iter()
                     .map(|h| &h.rect),
             )
+            .filter(|_| interactive)
+            .filter_map(|r| r.intersection(&bounds))
             .collect_vec();
 
         // Hash every hitbox rect at low precision so it only updates input regions on substantial changes
@@ -304,7 +464,8 @@ impl LayerShellApp {
 
         if hash != self.cantus.interaction.last_hitbox_hash {
             let region = compositor.create_region(qhandle, ());
-            for r in rects {
+            let config = CONFIG.load();
+            for r in rects.iter().map(|r| surface_rect(&config, r)) {
                 region.add(
                     r.x0.round() as i32,
                     r.y0.round() as i32,
@@ -312,10 +473,344 @@ impl LayerShellApp {
                     (r.y1 - r.y0).round() as i32,
                 );
             }
-            wl_surface.set_input_region(Some(&region));
+            self.wl_surface.set_input_region(Some(&region));
             self.cantus.interaction.last_hitbox_hash = hash;
         }
     }
+
+    fn destroy(self) {
+        // The wgpu surface and its swapchain have to go before the wl_surface they present to
+        drop(self.cantus);
+        if let Some(inhibitor) = self.idle_inhibitor {
+            inhibitor.destroy();
+        }
+        if let Some(viewport) = self.viewport {
+            viewport.destroy();
+        }
+        if let Some(fractional) = self.fractional {
+            fractional.destroy();
+        }
+        self.layer_surface.destroy();
+        self.wl_surface.destroy();
+    }
+}
+
+pub struct LayerShellApp {
+    /// Set once the initial surfaces exist, output changes after this are hotplugs.
+    initialized: bool,
+    should_exit: bool,
+    /// Nothing is playing, surfaces are unmapped until it is.
+    hidden: bool,
+
+    compositor: Option<WlCompositor>,
+    layer_shell: Option<ZwlrLayerShellV1>,
+    seat: Option<WlSeat>,
+    pointer: Option<WlPointer>,
+    /// The surface the pointer is currently over.
+    pointer_focus: Option<ObjectId>,
+    /// Serial of the latest pointer enter, required to set the cursor shape.
+    pointer_serial: u32,
+    cursor_shape_manager: Option<WpCursorShapeManagerV1>,
+    cursor_shape_device: Option<WpCursorShapeDeviceV1>,
+    cursor_shape: Option<CursorShape>,
+    touch: Option<WlTouch>,
+    keyboard: Option<WlKeyboard>,
+    /// The surface with keyboard focus, given when it's clicked.
+    keyboard_focus: Option<ObjectId>,
+    /// Turns key codes into text with the compositor's keymap and the held modifiers.
+    xkb_state: Option<xkb::State>,
+    /// The touch point driving the interaction and the surface it started on, extra fingers are ignored.
+    active_touch: Option<(i32, ObjectId)>,
+    outputs: Vec<OutputInfo>,
+    surfaces: Vec<OutputSurface>,
+
+    viewporter: Option<WpViewporter>,
+    fractional_manager: Option<WpFractionalScaleManagerV1>,
+    idle_inhibit_manager: Option<ZwpIdleInhibitManagerV1>,
+    display_ptr: NonNull<c_void>,
+}
+
+impl LayerShellApp {
+    const fn new(display_ptr: NonNull<c_void>) -> Self {
+        Self {
+            initialized: false,
+            should_exit: false,
+            hidden: false,
+            compositor: None,
+            layer_shell: None,
+            seat: None,
+            pointer: None,
+            pointer_focus: None,
+            pointer_serial: 0,
+            cursor_shape_manager: None,
+            cursor_shape_device: None,
+            cursor_shape: None,
+            touch: None,
+            keyboard: None,
+            keyboard_focus: None,
+            xkb_state: None,
+            active_touch: None,
+            outputs: Vec::new(),
+            surfaces: Vec::new(),
+            viewporter: None,
+            fractional_manager: None,
+            idle_inhibit_manager: None,
+            display_ptr,
+        }
+    }
+
+    /// Whether the output should get a bar, following the `monitor` config.
+    ///
+    /// Falls back to the first output when nothing matches.
+    fn is_output_selected(&self, index: usize) -> bool {
+        let config = CONFIG.load();
+        let Some(targets) = config.monitor.as_ref().map(Monitors::targets) else {
+            return index == 0;
+        };
+        if targets.iter().any(|target| target == "all") {
+            return true;
+        }
+        let matches = |info: &OutputInfo| targets.iter().any(|target| info.matches(target));
+        if self.outputs.iter().any(matches) {
+            matches(&self.outputs[index])
+        } else {
+            index == 0
+        }
+    }
+
+    /// Create a layer surface on every selected output that doesn't have one yet, and drop
+    /// surfaces on outputs that are no longer selected.
+    fn update_surfaces(&mut self, qhandle: &QueueHandle<Self>) {
+        let selected = (0..self.outputs.len())
+            .filter(|&index| self.is_output_selected(index))
+            .map(|index| self.outputs[index].handle.id())
+            .collect_vec();
+        let (keep, removed): (Vec<_>, Vec<_>) = self
+            .surfaces
+            .drain(..)
+            .partition(|s| selected.contains(&s.output.id()));
+        self.surfaces = keep;
+        for surface in removed {
+            surface.destroy();
+        }
+
+        for index in 0..self.outputs.len() {
+            let output_id = self.outputs[index].handle.id();
+            if self.is_output_selected(index)
+                && !self.surfaces.iter().any(|s| s.output.id() == output_id)
+            {
+                self.create_surface(index, qhandle);
+            }
+        }
+    }
+
+    fn create_surface(&mut self, output_index: usize, qhandle: &QueueHandle<Self>) {
+        let (Some(compositor), Some(layer_shell)) = (&self.compositor, &self.layer_shell) else {
+            return;
+        };
+        let output = &self.outputs[output_index].handle;
+        let output_scale = self.outputs[output_index].scale;
+
+        let wl_surface = compositor.create_surface(qhandle, ());
+        let surface_ptr = NonNull::new(wl_surface.id().as_ptr().cast::<c_void>())
+            .expect("Failed to get surface pointer");
+        let (viewport, fractional) = match (&self.viewporter, &self.fractional_manager) {
+            (Some(vp), Some(fm)) => (
+                Some(vp.get_viewport(&wl_surface, qhandle, ())),
+                Some(fm.get_fractional_scale(&wl_surface, qhandle, ())),
+            ),
+            _ => (None, None),
+        };
+
+        let layer_surface = layer_shell.get_layer_surface(
+            &wl_surface,
+            Some(output),
+            config_layer(),
+            "cantus".into(),
+            qhandle,
+            (),
+        );
+        place_layer_surface(&layer_surface, self.hidden);
+        wl_surface.commit();
+
+        self.surfaces.push(OutputSurface {
+            cantus: CantusApp::default(),
+            output: output.clone(),
+            is_configured: false,
+            surface_ptr,
+            wl_surface,
+            layer_surface,
+            viewport,
+            fractional,
+            frame_callback: None,
+            detected_scale: 1.0,
+            idle_inhibitor: None,
+        });
+        // Fractional scaling sends its preferred scale per surface, otherwise go by the output
+        if let Some(surface) = self.surfaces.last_mut() {
+            surface.set_scale(if surface.fractional.is_none() {
+                output_scale as f32
+            } else {
+                1.0
+            });
+        }
+    }
+
+    /// Bring the surfaces in line with a reloaded config, the compositor replies with a configure.
+    fn apply_config(&mut self, qhandle: &QueueHandle<Self>) {
+        self.update_surfaces(qhandle);
+        let layer = config_layer();
+        for surface in &mut self.surfaces {
+            surface.set_scale(surface.detected_scale);
+            if !surface.is_configured {
+                continue;
+            }
+            surface.layer_surface.set_layer(layer);
+            place_layer_surface(&surface.layer_surface, self.hidden);
+            surface.update_scale_and_viewport();
+            surface.wl_surface.commit();
+        }
+    }
+
+    /// Show a hand cursor while over anything clickable.
+    fn update_cursor_shape(&mut self) {
+        let Some(device) = &self.cursor_shape_device else {
+            return;
+        };
+        let focus = self.pointer_focus.as_ref();
+        let clickable = self
+            .surfaces
+            .iter()
+            .find(|s| Some(&s.wl_surface.id()) == focus)
+            .is_some_and(|surface| {
+                let interaction = &surface.cantus.interaction;
+                let pos = interaction.mouse_position;
+                interaction.play_hitbox.contains(pos)
+                    || interaction
+                        .icon_hitboxes
+                        .iter()
+                        .any(|h| h.rect.contains(pos))
+                    || interaction
+                        .track_hitboxes
+                        .iter()
+                        .any(|h| h.clickable && h.rect.contains(pos))
+            });
+        let shape = if clickable {
+            CursorShape::Pointer
+        } else {
+            CursorShape::Default
+        };
+        if self.cursor_shape != Some(shape) {
+            device.set_shape(self.pointer_serial, shape);
+            self.cursor_shape = Some(shape);
+        }
+    }
+
+    /// Hide the bar while nothing is playing, when enabled with `auto_hide`.
+    ///
+    /// Hidden surfaces are unmapped so they take no space and draw no frames, showing them again
+    /// waits for the compositor to configure them like new ones.
+    fn update_hidden(&mut self) {
+        let hidden = CONFIG.load().auto_hide && {
+            let state = PLAYBACK_STATE.read();
+            state.queue.is_empty() || state.no_active_device
+        };
+        if hidden == self.hidden {
+            return;
+        }
+        info!("{} bar", if hidden { "Hiding" } else { "Showing" });
+        self.hidden = hidden;
+        for surface in &mut self.surfaces {
+            place_layer_surface(&surface.layer_surface, hidden);
+            if hidden && surface.is_configured {
+                // The swapchain goes first so nothing is presented to the surface once unmapped
+                surface.cantus.gpu_resources = None;
+                surface.frame_callback = None;
+                surface.wl_surface.attach(None, 0, 0);
+                surface.is_configured = false;
+            }
+            // Unmapped surfaces treat this like their first commit and are configured again
+            surface.wl_surface.commit();
+        }
+    }
+
+    /// Hold an idle inhibitor on every surface while music is playing, when enabled with `inhibit_idle`.
+    fn update_idle_inhibit(&mut self, qhandle: &QueueHandle<Self>) {
+        let Some(manager) = &self.idle_inhibit_manager else {
+            return;
+        };
+        let inhibit = CONFIG.load().inhibit_idle && !self.hidden && PLAYBACK_STATE.read().playing;
+        for surface in &mut self.surfaces {
+            match (inhibit, &surface.idle_inhibitor) {
+                (true, None) => {
+                    surface.idle_inhibitor =
+                        Some(manager.create_inhibitor(&surface.wl_surface, qhandle, ()));
+                }
+                (false, Some(_)) => {
+                    if let Some(inhibitor) = surface.idle_inhibitor.take() {
+                        inhibitor.destroy();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Draw surfaces that went idle, after input every surface keeps drawing long enough to settle.
+    fn wake_surfaces(&mut self, qhandle: &QueueHandle<Self>, input: bool) {
+        let redraw_until = Instant::now() + REDRAW_SETTLE;
+        for index in 0..self.surfaces.len() {
+            let surface = &mut self.surfaces[index];
+            if input {
+                surface.cantus.redraw_until = redraw_until;
+            }
+            if surface.frame_callback.is_none() {
+                self.render_surface(index, qhandle);
+            }
+        }
+    }
+
+    fn render_surface(&mut self, index: usize, qhandle: &QueueHandle<Self>) {
+        self.update_hidden();
+        self.update_idle_inhibit(qhandle);
+        if self.hidden {
+            return;
+        }
+        if let Some(surface) = self.surfaces.get_mut(index) {
+            surface.try_render_frame(self.display_ptr, self.compositor.as_ref(), qhandle);
+        }
+    }
+
+    /// Rescale the surfaces on an output that changed scale, unless they are told their scale directly.
+    fn update_output_scale(&mut self, output: &WlOutput, scale: i32, qhandle: &QueueHandle<Self>) {
+        for index in 0..self.surfaces.len() {
+            let surface = &self.surfaces[index];
+            // Version 6 surfaces get `PreferredBufferScale` instead
+            if surface.output == *output
+                && surface.fractional.is_none()
+                && surface.wl_surface.version() < 6
+            {
+                self.rescale_surface(index, scale as f32, qhandle);
+            }
+        }
+    }
+
+    fn rescale_surface(&mut self, index: usize, scale: f32, qhandle: &QueueHandle<Self>) {
+        let surface = &mut self.surfaces[index];
+        surface.set_scale(scale);
+        if surface.is_configured {
+            surface.update_scale_and_viewport();
+            surface.wl_surface.commit();
+            self.render_surface(index, qhandle);
+        }
+    }
+
+    fn surface_mut(&mut self, surface_id: Option<&ObjectId>) -> Option<&mut OutputSurface> {
+        let surface_id = surface_id?;
+        self.surfaces
+            .iter_mut()
+            .find(|s| &s.wl_surface.id() == surface_id)
+    }
 }
 
 impl Dispatch<ZwlrLayerSurfaceV1, ()> for LayerShellApp {
@@ -327,19 +822,27 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for LayerShellApp {
         _conn: &Connection,
         qhandle: &QueueHandle<Self>,
     ) {
+        let id = proxy.id();
+        let Some(index) = state
+            .surfaces
+            .iter()
+            .position(|s| s.layer_surface.id() == id)
+        else {
+            return;
+        };
         match event {
             zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
                 proxy.ack_configure(serial);
-                state.update_scale_and_viewport();
-                if let Some(surface) = &state.wl_surface {
-                    surface.commit();
-                }
-                state.is_configured = true;
+                let surface = &mut state.surfaces[index];
+                surface.update_scale_and_viewport();
+                surface.wl_surface.commit();
+                surface.is_configured = true;
 
-                state.try_render_frame(qhandle);
+                state.render_surface(index, qhandle);
             }
+            // Sent when the output goes away, the registry `GlobalRemove` moves the bar elsewhere
             zwlr_layer_surface_v1::Event::Closed => {
-                state.should_exit = true;
+                state.surfaces.remove(index).destroy();
             }
             _ => {}
         }
@@ -349,24 +852,44 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for LayerShellApp {
 impl Dispatch<WpFractionalScaleV1, ()> for LayerShellApp {
     fn event(
         state: &mut Self,
-        _proxy: &WpFractionalScaleV1,
+        proxy: &WpFractionalScaleV1,
         event: wp_fractional_scale_v1::Event,
         _data: &(),
         _conn: &Connection,
         qhandle: &QueueHandle<Self>,
     ) {
+        let id = proxy.id();
+        let Some(index) = state
+            .surfaces
+            .iter()
+            .position(|s| s.fractional.as_ref().is_some_and(|f| f.id() == id))
+        else {
+            return;
+        };
         if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
-            state.cantus.scale_factor = scale as f32 / 120.0;
-
-            if state.is_configured {
-                state.update_scale_and_viewport();
-
-                if let Some(surface) = &state.wl_surface {
-                    surface.commit();
-                }
+            state.rescale_surface(index, scale as f32 / 120.0, qhandle);
+        }
+    }
+}
 
-                state.try_render_frame(qhandle);
-            }
+impl Dispatch<WlSurface, ()> for LayerShellApp {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSurface,
+        event: wl_surface::Event,
+        _data: &(),
+        _conn: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        let id = proxy.id();
+        let Some(index) = state.surfaces.iter().position(|s| s.wl_surface.id() == id) else {
+            return;
+        };
+        // The integer scale to use when there is no fractional scaling, sent by version 6 compositors
+        if let wl_surface::Event::PreferredBufferScale { factor } = event
+            && state.surfaces[index].fractional.is_none()
+        {
+            state.rescale_surface(index, factor as f32, qhandle);
         }
     }
 }
@@ -374,15 +897,21 @@ impl Dispatch<WpFractionalScaleV1, ()> for LayerShellApp {
 impl Dispatch<WlCallback, ()> for LayerShellApp {
     fn event(
         state: &mut Self,
-        _proxy: &WlCallback,
+        proxy: &WlCallback,
         event: wl_callback::Event,
         _data: &(),
         _conn: &Connection,
         qhandle: &QueueHandle<Self>,
     ) {
-        if matches!(event, wl_callback::Event::Done { .. }) && state.frame_callback.take().is_some()
+        let id = proxy.id();
+        if matches!(event, wl_callback::Event::Done { .. })
+            && let Some(index) = state
+                .surfaces
+                .iter()
+                .position(|s| s.frame_callback.as_ref().is_some_and(|c| c.id() == id))
         {
-            state.try_render_frame(qhandle);
+            state.surfaces[index].frame_callback = None;
+            state.render_surface(index, qhandle);
         }
     }
 }
@@ -394,11 +923,19 @@ impl Dispatch<WlOutput, ()> for LayerShellApp {
         event: wl_output::Event,
         _data: &(),
         _conn: &Connection,
-        _qhandle: &QueueHandle<Self>,
+        qhandle: &QueueHandle<Self>,
     ) {
         let id = proxy.id();
         if let Some(info) = state.outputs.iter_mut().find(|info| info.handle.id() == id) {
             match event {
+                wl_output::Event::Scale { factor } => {
+                    info.scale = factor;
+                    state.update_output_scale(proxy, factor, qhandle);
+                }
+                // All the details of a newly plugged in output have arrived
+                wl_output::Event::Done if state.initialized => {
+                    state.update_surfaces(qhandle);
+                }
                 wl_output::Event::Geometry { make, model, .. } => {
                     info.make = Some(make);
                     info.model = Some(model);
@@ -412,7 +949,6 @@ impl Dispatch<WlOutput, ()> for LayerShellApp {
                 _ => {}
             }
         }
-        state.try_select_output();
     }
 }
 
@@ -428,11 +964,37 @@ impl Dispatch<WlSeat, ()> for LayerShellApp {
         if let wl_seat::Event::Capabilities { capabilities } = event
             && let WEnum::Value(caps) = capabilities
         {
-            if caps.contains(wl_seat::Capability::Pointer) && state.pointer.is_none() {
-                state.pointer = Some(proxy.get_pointer(qhandle, ()));
+            if caps.contains(wl_seat::Capability::Pointer) {
+                if state.pointer.is_none() {
+                    let pointer = proxy.get_pointer(qhandle, ());
+                    state.cursor_shape_device = state
+                        .cursor_shape_manager
+                        .as_ref()
+                        .map(|manager| manager.get_pointer(&pointer, qhandle, ()));
+                    state.pointer = Some(pointer);
+                }
             } else if let Some(pointer) = state.pointer.take() {
+                if let Some(device) = state.cursor_shape_device.take() {
+                    device.destroy();
+                }
                 pointer.release();
             }
+            if caps.contains(wl_seat::Capability::Touch) {
+                if state.touch.is_none() {
+                    state.touch = Some(proxy.get_touch(qhandle, ()));
+                }
+            } else if let Some(touch) = state.touch.take() {
+                touch.release();
+                state.active_touch = None;
+            }
+            if caps.contains(wl_seat::Capability::Keyboard) {
+                if state.keyboard.is_none() {
+                    state.keyboard = Some(proxy.get_keyboard(qhandle, ()));
+                }
+            } else if let Some(keyboard) = state.keyboard.take() {
+                keyboard.release();
+                state.keyboard_focus = None;
+            }
         }
     }
 }
@@ -446,28 +1008,45 @@ impl Dispatch<WlPointer, ()> for LayerShellApp {
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        let cantus = &mut state.cantus;
+        INPUT_RECEIVED.store(true, Ordering::Relaxed);
+        if let wl_pointer::Event::Enter {
+            surface, serial, ..
+        } = &event
+        {
+            state.pointer_focus = Some(surface.id());
+            state.pointer_serial = *serial;
+            // The compositor resets the cursor on enter
+            state.cursor_shape = None;
+        }
+        let focus = state.pointer_focus.clone();
+        if matches!(event, wl_pointer::Event::Leave { .. }) {
+            state.pointer_focus = None;
+        }
+        let Some(output_surface) = state.surface_mut(focus.as_ref()) else {
+            return;
+        };
+        let cantus = &mut output_surface.cantus;
         let interaction = &mut cantus.interaction;
 
-        let surface_id = state.wl_surface.as_ref().map(wayland_client::Proxy::id);
         match event {
             wl_pointer::Event::Enter {
-                surface,
                 surface_x,
                 surface_y,
                 ..
-            } if surface_id == Some(surface.id()) => {
-                interaction.mouse_position = Point::new(surface_x as f32, surface_y as f32);
+            } => {
+                interaction.mouse_position = scene_point(&CONFIG.load(), surface_x, surface_y);
                 interaction.mouse_pressure = 1.0;
+                state.update_cursor_shape();
             }
             wl_pointer::Event::Motion {
                 surface_x,
                 surface_y,
                 ..
             } => {
-                interaction.mouse_position = Point::new(surface_x as f32, surface_y as f32);
+                interaction.mouse_position = scene_point(&CONFIG.load(), surface_x, surface_y);
                 interaction.mouse_pressure = if interaction.mouse_down { 2.0 } else { 1.0 };
                 cantus.handle_mouse_drag();
+                state.update_cursor_shape();
             }
             wl_pointer::Event::Leave { .. } => {
                 interaction.mouse_pressure = 0.0;
@@ -486,6 +1065,7 @@ impl Dispatch<WlPointer, ()> for LayerShellApp {
                 (0x111, WEnum::Value(wl_pointer::ButtonState::Pressed)) if interaction.dragging => {
                     cantus.right_click();
                 }
+                (0x112, WEnum::Value(wl_pointer::ButtonState::Pressed)) => cantus.middle_click(),
                 _ => {}
             },
             wl_pointer::Event::AxisDiscrete {
@@ -498,7 +1078,132 @@ impl Dispatch<WlPointer, ()> for LayerShellApp {
                 value120: discrete,
                 ..
             } => {
-                CantusApp::handle_scroll(discrete.signum());
+                cantus.handle_scroll(discrete.signum());
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, ()> for LayerShellApp {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlKeyboard,
+        event: wl_keyboard::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_keyboard::Event::Keymap {
+                format: WEnum::Value(wl_keyboard::KeymapFormat::XkbV1),
+                fd,
+                size,
+            } => {
+                state.xkb_state = read_keymap(fd, size);
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => {
+                if let Some(xkb_state) = &mut state.xkb_state {
+                    xkb_state.update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
+                }
+            }
+            wl_keyboard::Event::Enter { surface, .. } => {
+                state.keyboard_focus = Some(surface.id());
+            }
+            // A search left open would swallow the next `/`, so it closes with the focus
+            wl_keyboard::Event::Leave { .. } => {
+                let focus = state.keyboard_focus.take();
+                if let Some(output_surface) = state.surface_mut(focus.as_ref()) {
+                    output_surface.cantus.interaction.search = None;
+                    INPUT_RECEIVED.store(true, Ordering::Relaxed);
+                }
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: WEnum::Value(wl_keyboard::KeyState::Pressed),
+                ..
+            } => {
+                // Wayland sends evdev codes, xkb's are offset by 8
+                let Some(input) = state
+                    .xkb_state
+                    .as_ref()
+                    .and_then(|xkb_state| key_input(xkb_state.key_get_utf8((key + 8).into())))
+                else {
+                    return;
+                };
+                let focus = state.keyboard_focus.clone();
+                if let Some(output_surface) = state.surface_mut(focus.as_ref()) {
+                    output_surface.cantus.handle_key(input);
+                    INPUT_RECEIVED.store(true, Ordering::Relaxed);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WlTouch, ()> for LayerShellApp {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlTouch,
+        event: wl_touch::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        INPUT_RECEIVED.store(true, Ordering::Relaxed);
+        // Touches follow the same press, drag, release flow as the left mouse button
+        match event {
+            wl_touch::Event::Down {
+                surface, id, x, y, ..
+            } if state.active_touch.is_none() => {
+                let surface_id = surface.id();
+                let Some(output_surface) = state.surface_mut(Some(&surface_id)) else {
+                    return;
+                };
+                let cantus = &mut output_surface.cantus;
+                cantus.interaction.mouse_position = scene_point(&CONFIG.load(), x, y);
+                cantus.left_click();
+                state.active_touch = Some((id, surface_id));
+            }
+            wl_touch::Event::Motion { id, x, y, .. } => {
+                let Some((_, surface_id)) = state.active_touch.clone().filter(|(t, _)| *t == id)
+                else {
+                    return;
+                };
+                if let Some(output_surface) = state.surface_mut(Some(&surface_id)) {
+                    let cantus = &mut output_surface.cantus;
+                    cantus.interaction.mouse_position = scene_point(&CONFIG.load(), x, y);
+                    cantus.handle_mouse_drag();
+                }
+            }
+            wl_touch::Event::Up { id, .. } => {
+                let Some((_, surface_id)) = state.active_touch.take_if(|(t, _)| *t == id) else {
+                    return;
+                };
+                if let Some(output_surface) = state.surface_mut(Some(&surface_id)) {
+                    let cantus = &mut output_surface.cantus;
+                    cantus.left_click_released();
+                    // There is no hover once the finger lifts
+                    cantus.interaction.mouse_pressure = 0.0;
+                }
+            }
+            wl_touch::Event::Cancel => {
+                let Some((_, surface_id)) = state.active_touch.take() else {
+                    return;
+                };
+                if let Some(output_surface) = state.surface_mut(Some(&surface_id)) {
+                    let cantus = &mut output_surface.cantus;
+                    cantus.interaction.mouse_pressure = 0.0;
+                    cantus.interaction.mouse_down = false;
+                    cantus.cancel_drag();
+                }
             }
             _ => {}
         }
@@ -514,7 +1219,22 @@ impl Dispatch<WlRegistry, ()> for LayerShellApp {
         _conn: &Connection,
         qhandle: &QueueHandle<Self>,
     ) {
-        if let wl_registry::Event::Global {
+        if let wl_registry::Event::GlobalRemove { name } = event {
+            let Some(index) = state.outputs.iter().position(|o| o.global_name == name) else {
+                return;
+            };
+            let output = state.outputs.remove(index);
+            info!(
+                "Output {} removed",
+                output.name.as_deref().unwrap_or("unknown")
+            );
+            // Drops the surface that was on it and moves to a surviving output if needed,
+            // with nothing left rendering is parked until a new output's `Done` event
+            state.update_surfaces(qhandle);
+            if output.handle.version() >= 3 {
+                output.handle.release();
+            }
+        } else if let wl_registry::Event::Global {
             name,
             interface,
             version,
@@ -538,6 +1258,14 @@ impl Dispatch<WlRegistry, ()> for LayerShellApp {
                         proxy.bind::<WpFractionalScaleManagerV1, (), Self>(name, 1, qhandle, ()),
                     );
                 }
+                "zwp_idle_inhibit_manager_v1" => {
+                    state.idle_inhibit_manager =
+                        Some(proxy.bind::<ZwpIdleInhibitManagerV1, (), Self>(name, 1, qhandle, ()));
+                }
+                "wp_cursor_shape_manager_v1" => {
+                    state.cursor_shape_manager =
+                        Some(proxy.bind::<WpCursorShapeManagerV1, (), Self>(name, 1, qhandle, ()));
+                }
                 "wl_seat" => {
                     state.seat =
                         Some(proxy.bind::<WlSeat, (), Self>(name, version.min(7), qhandle, ()));
@@ -545,10 +1273,12 @@ impl Dispatch<WlRegistry, ()> for LayerShellApp {
                 "wl_output" => {
                     state.outputs.push(OutputInfo {
                         handle: proxy.bind::<WlOutput, (), Self>(name, version.min(4), qhandle, ()),
+                        global_name: name,
                         name: None,
                         description: None,
                         make: None,
                         model: None,
+                        scale: 1,
                     });
                 }
                 _ => {}
@@ -573,13 +1303,66 @@ macro_rules! impl_noop_dispatch {
     };
 }
 
-impl_noop_dispatch!(WlSurface, wl_surface::Event);
 impl_noop_dispatch!(ZwlrLayerShellV1, zwlr_layer_shell_v1::Event);
 impl_noop_dispatch!(
     WpFractionalScaleManagerV1,
     wp_fractional_scale_manager_v1::Event
 );
 impl_noop_dispatch!(WpViewporter, wp_viewporter::Event);
+impl_noop_dispatch!(ZwpIdleInhibitManagerV1, zwp_idle_inhibit_manager_v1::Event);
+impl_noop_dispatch!(ZwpIdleInhibitorV1, zwp_idle_inhibitor_v1::Event);
+impl_noop_dispatch!(WpCursorShapeManagerV1, wp_cursor_shape_manager_v1::Event);
+impl_noop_dispatch!(WpCursorShapeDeviceV1, wp_cursor_shape_device_v1::Event);
 impl_noop_dispatch!(WpViewport, wp_viewport::Event);
 impl_noop_dispatch!(WlCompositor, wl_compositor::Event);
 impl_noop_dispatch!(WlRegion, wl_region::Event);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Orientation, TimelineDirection};
+
+    fn config(timeline_direction: TimelineDirection, orientation: Orientation) -> Config {
+        Config {
+            timeline_direction,
+            orientation,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn mirrored_rects_map_back_onto_the_scene() {
+        let scene = Rect::new(200.0, 10.0, 320.0, 40.0);
+        for orientation in [Orientation::Horizontal, Orientation::Vertical] {
+            let config = config(TimelineDirection::RightToLeft, orientation);
+            let surface = surface_rect(&config, &scene);
+            let corner_a = scene_point(&config, f64::from(surface.x0), f64::from(surface.y0));
+            let corner_b = scene_point(&config, f64::from(surface.x1), f64::from(surface.y1));
+            let mapped = Rect::new(
+                corner_a.x.min(corner_b.x),
+                corner_a.y.min(corner_b.y),
+                corner_a.x.max(corner_b.x),
+                corner_a.y.max(corner_b.y),
+            );
+            assert_eq!(mapped, scene);
+        }
+    }
+
+    #[test]
+    fn mirrored_click_lands_on_the_same_track() {
+        let ltr = config(TimelineDirection::LeftToRight, Orientation::Horizontal);
+        let rtl = config(TimelineDirection::RightToLeft, Orientation::Horizontal);
+        let track = Rect::new(200.0, 10.0, 320.0, 40.0);
+        let click = (250.0, 20.0);
+
+        let mirrored_click = (f64::from(rtl.width) - click.0, click.1);
+        assert_eq!(
+            scene_point(&rtl, mirrored_click.0, mirrored_click.1),
+            scene_point(&ltr, click.0, click.1)
+        );
+        // The track is drawn on the far side of the surface, under the mirrored click
+        let drawn = surface_rect(&rtl, &track);
+        assert!(drawn.contains(Point::new(mirrored_click.0 as f32, mirrored_click.1 as f32)));
+        assert!(track.contains(scene_point(&rtl, mirrored_click.0, mirrored_click.1)));
+    }
+}
@@ -1,28 +1,50 @@
-use crate::interaction::InteractionState;
-use crate::pipelines::{IMAGE_SIZE, MAX_TEXTURE_LAYERS};
+use crate::backend::BACKEND;
+use crate::config::CONFIG;
+use crate::interaction::{IconEntry, InteractionState};
+use crate::pipelines::{IMAGE_SIZE, MAX_PILLS};
 use crate::render::{
     BackgroundPill, GlobalUniforms, IconInstance, Particle, PlayheadUniforms, RenderState,
+    TrackRender,
 };
 use crate::text_render::TextRenderer;
 use arrayvec::ArrayString;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use dashmap::DashMap;
 use image::RgbaImage;
 use parking_lot::RwLock;
 use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
 use std::collections::HashSet;
 use std::{
     collections::HashMap,
-    sync::{Arc, LazyLock},
-    time::Instant,
+    fs,
+    path::PathBuf,
+    process::Command,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::spawn,
+    time::{Duration, Instant},
 };
+use tracing::{error, info, warn};
 use wgpu::{
-    BindGroup, Buffer, Color, CommandEncoderDescriptor, Device, Instance, LoadOp, Operations,
-    Queue, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline, StoreOp, Surface,
-    SurfaceConfiguration, Texture, TextureViewDescriptor,
+    BindGroup, BindGroupLayout, Buffer, BufferSize, Color, CommandEncoderDescriptor, Device,
+    Instance, LoadOp, Operations, PollType, Queue, RenderPassColorAttachment, RenderPassDescriptor,
+    RenderPipeline, Sampler, StoreOp, Surface, SurfaceConfiguration, SurfaceError, Texture,
+    TextureView, TextureViewDescriptor, util::StagingBelt,
 };
 
+mod backend;
 mod config;
+#[cfg(feature = "debug_overlay")]
+mod debug_overlay;
 mod interaction;
+mod ipc;
 mod layer_shell;
 mod pipelines;
 mod render;
@@ -31,27 +53,73 @@ mod text_render;
 #[cfg(feature = "spotify")]
 mod spotify;
 
-#[cfg(not(feature = "spotify"))]
+#[cfg(feature = "mpris")]
+mod mpris;
+
+#[cfg(feature = "mpris_server")]
+mod mpris_server;
+
+#[cfg(not(any(feature = "spotify", feature = "mpris")))]
 mod spotify_debug;
 
+#[cfg(all(feature = "spotify", feature = "mpris"))]
+compile_error!(
+    "The `spotify` and `mpris` backends are mutually exclusive, build with `--no-default-features --features mpris` to use MPRIS."
+);
+
 const PANEL_START: f32 = 6.0;
 const PANEL_EXTENSION: f32 = 12.0;
 
+// Each flag mirrors a separate part of the service's state
+#[allow(clippy::struct_excessive_bools)]
 struct PlaybackState {
     playing: bool,
+    shuffle: bool,
+    repeat: RepeatMode,
     progress: u32,
     volume: Option<u8>,
+    /// The volume to go back to when unmuting, set while muted.
+    pre_mute_volume: Option<u8>,
     queue: Vec<Track>,
     queue_index: usize,
     playlists: HashMap<PlaylistId, CondensedPlaylist>,
+    /// Devices playback can be moved to, empty for services that play locally.
+    devices: Vec<PlaybackDevice>,
+    /// The service reported nothing playing on any device, the queue shown is stale.
+    no_active_device: bool,
+    /// Total duration in ms and count of the history tracks dropped from the front of the queue.
+    trimmed_history: (u64, usize),
 
     interaction: bool,
     last_interaction: Instant,
+    /// Polls leave `playing` alone until this passes, kept separate from `last_interaction` so
+    /// pausing elsewhere still shows up while a seek settles.
+    last_play_toggle: Instant,
     last_progress_update: Instant,
 }
 
-/// Number of swatches to use in colour palette generation.
-const NUM_SWATCHES: usize = 4;
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum RepeatMode {
+    #[default]
+    Off,
+    Track,
+    Context,
+}
+
+impl RepeatMode {
+    /// The next mode when cycling, in the same order as the Spotify client.
+    const fn next(self) -> Self {
+        match self {
+            Self::Off => Self::Context,
+            Self::Context => Self::Track,
+            Self::Track => Self::Off,
+        }
+    }
+}
+
+/// Most swatches the background shader can blend, the count in use comes from the config.
+const MAX_SWATCHES: usize = 6;
 
 type TrackId = ArrayString<22>;
 type AlbumId = ArrayString<22>;
@@ -66,11 +134,26 @@ struct Track {
     #[serde(deserialize_with = "deserialize_first_artist", rename = "artists")]
     artist: Artist,
     duration_ms: u32,
+    /// Local files have no Spotify id and can't be added to playlists.
+    #[serde(default)]
+    is_local: bool,
+    /// What the service plays the item by, such as `spotify:episode:…`, empty when unknown.
+    #[serde(default)]
+    uri: String,
+}
+
+impl Track {
+    /// Only Spotify tracks can be saved, not local files, episodes or other players' tracks.
+    fn likeable(&self) -> bool {
+        !self.is_local && self.uri.starts_with("spotify:track:")
+    }
 }
 
 #[derive(Deserialize)]
 struct Album {
     id: Option<AlbumId>,
+    #[serde(default)]
+    name: String,
     #[serde(default, deserialize_with = "deserialize_images", rename = "images")]
     image: Option<String>,
 }
@@ -79,16 +162,27 @@ struct Album {
 struct Artist {
     id: Option<ArtistId>,
     name: String,
+    #[cfg_attr(not(feature = "spotify"), allow(dead_code))]
     #[serde(default, deserialize_with = "deserialize_images", rename = "images")]
     image: Option<String>,
 }
 
+#[derive(Deserialize, Clone)]
+#[cfg_attr(not(feature = "spotify"), allow(dead_code))]
+struct PlaybackDevice {
+    /// Restricted devices have no id and can't be controlled.
+    id: Option<String>,
+    name: String,
+    is_active: bool,
+}
+
 struct CondensedPlaylist {
     id: PlaylistId,
     name: String,
     image_url: Option<String>,
     tracks: HashSet<TrackId>,
     rating_index: Option<u8>,
+    #[cfg_attr(not(feature = "spotify"), allow(dead_code))]
     tracks_total: u32,
     #[cfg(feature = "spotify")]
     snapshot_id: ArrayString<32>,
@@ -101,38 +195,199 @@ struct Image {
 }
 
 static PLAYBACK_STATE: LazyLock<RwLock<PlaybackState>> = LazyLock::new(|| {
-    #[cfg(feature = "spotify")]
+    #[cfg(any(feature = "spotify", feature = "mpris"))]
     {
         RwLock::new(PlaybackState {
             playing: false,
+            shuffle: false,
+            repeat: RepeatMode::Off,
             progress: 0,
             volume: None,
+            pre_mute_volume: None,
             queue: Vec::new(),
             queue_index: 0,
             playlists: HashMap::new(),
+            devices: Vec::new(),
+            no_active_device: false,
+            trimmed_history: (0, 0),
 
             interaction: false,
             last_interaction: Instant::now(),
+            last_play_toggle: Instant::now(),
             last_progress_update: Instant::now(),
         })
     }
-    #[cfg(not(feature = "spotify"))]
+    #[cfg(not(any(feature = "spotify", feature = "mpris")))]
     RwLock::new(spotify_debug::debug_playbackstate())
 });
 
+/// Delay between playback polls while playing or being interacted with.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Delay between playback polls while paused and left alone, the bar stops animating meanwhile.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(3);
+/// How long after the last interaction polling stays at the full rate.
+const IDLE_AFTER: Duration = Duration::from_secs(10);
+
+/// How long backends should sleep before polling playback again.
+fn poll_interval() -> Duration {
+    let state = PLAYBACK_STATE.read();
+    if state.playing || state.last_interaction.elapsed() < IDLE_AFTER {
+        POLL_INTERVAL
+    } else {
+        IDLE_POLL_INTERVAL
+    }
+}
+
 fn update_playback_state<F>(update: F)
 where
     F: FnOnce(&mut PlaybackState),
 {
     let mut state = PLAYBACK_STATE.write();
+    let previous_id = state
+        .queue
+        .get(state.queue_index)
+        .and_then(|track| track.id);
     update(&mut state);
+    if let Some(track) = state.queue.get(state.queue_index)
+        && track.id != previous_id
+    {
+        run_track_change_command(track);
+    }
+    drop(state);
+    layer_shell::request_redraw();
+}
+
+/// Run the `on_track_change` command in the background with the new track in its environment.
+fn run_track_change_command(track: &Track) {
+    let Some(command) = CONFIG.load().on_track_change.clone() else {
+        return;
+    };
+    let envs = [
+        ("CANTUS_TITLE", track.name.clone()),
+        ("CANTUS_ARTIST", track.artist.name.clone()),
+        ("CANTUS_ALBUM", track.album.name.clone()),
+        ("CANTUS_DURATION_MS", track.duration_ms.to_string()),
+        (
+            "CANTUS_TRACK_ID",
+            track.id.map(|id| id.to_string()).unwrap_or_default(),
+        ),
+        (
+            "CANTUS_ART_URL",
+            track.album.image.clone().unwrap_or_default(),
+        ),
+    ];
+    spawn(move || {
+        match Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .envs(envs)
+            .status()
+        {
+            Ok(status) if !status.success() => warn!("`on_track_change` exited with {status}"),
+            Ok(_) => {}
+            Err(err) => warn!("Failed to run `on_track_change`: {err}"),
+        }
+    });
 }
 
+/// Loaded images by url, `None` while a download is in flight.
 static IMAGES_CACHE: LazyLock<DashMap<String, Option<Arc<RgbaImage>>>> =
     LazyLock::new(DashMap::new);
-static ALBUM_PALETTE_CACHE: LazyLock<DashMap<AlbumId, Option<[u32; NUM_SWATCHES]>>> =
+/// Images whose download failed, with how many times and when it last did.
+static IMAGE_FAILURES: LazyLock<DashMap<String, (u8, Instant)>> = LazyLock::new(DashMap::new);
+static ALBUM_PALETTE_CACHE: LazyLock<DashMap<AlbumId, Option<Vec<u32>>>> =
     LazyLock::new(DashMap::new);
 static ARTIST_DATA_CACHE: LazyLock<DashMap<ArtistId, Option<String>>> = LazyLock::new(DashMap::new);
+/// Whether tracks are in the user's liked songs, `None` while unknown or unsupported by the backend.
+static LIKED_CACHE: LazyLock<DashMap<TrackId, Option<bool>>> = LazyLock::new(DashMap::new);
+
+/// Hash an arbitrary string into the fixed size ids used by the state, for items without a usable id.
+fn hashed_id(key: &str) -> ArrayString<22> {
+    let encoded = URL_SAFE_NO_PAD.encode(Sha256::digest(key.as_bytes()));
+    ArrayString::from(&encoded[..22]).unwrap_or_default()
+}
+
+/// Location of the resized thumbnail for an image url, stored as raw RGBA bytes.
+fn image_cache_path(url: &str) -> Option<PathBuf> {
+    let hash = URL_SAFE_NO_PAD.encode(Sha256::digest(url.as_bytes()));
+    dirs::config_dir().map(|dir| dir.join("cantus").join("images").join(hash))
+}
+
+/// Read a thumbnail saved by a previous run.
+fn read_image_cache(url: &str) -> Option<RgbaImage> {
+    let bytes = fs::read(image_cache_path(url)?).ok()?;
+    RgbaImage::from_raw(*IMAGE_SIZE, *IMAGE_SIZE, bytes)
+}
+
+/// Save an already resized thumbnail so it doesn't need downloading next launch.
+fn write_image_cache(url: &str, image: &RgbaImage) {
+    let Some(path) = image_cache_path(url) else {
+        return;
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| fs::write(&path, image.as_raw()));
+    if let Err(err) = result {
+        warn!("Failed to write image cache {}: {err}", path.display());
+    }
+}
+
+/// Downloads tried for an image before giving up on it.
+const MAX_IMAGE_ATTEMPTS: u8 = 5;
+/// Wait after a failed download before trying the image again.
+const IMAGE_RETRY_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Whether an image needs fetching, it isn't loaded or downloading and isn't waiting out a failure.
+fn should_fetch_image(url: &str) -> bool {
+    !IMAGES_CACHE.contains_key(url)
+        && IMAGE_FAILURES.get(url).is_none_or(|failure| {
+            let (attempts, last_failed) = *failure;
+            attempts < MAX_IMAGE_ATTEMPTS && last_failed.elapsed() >= IMAGE_RETRY_COOLDOWN
+        })
+}
+
+/// Forget a failed download so it's tried again after a cooldown, up to `MAX_IMAGE_ATTEMPTS`.
+fn image_fetch_failed(url: &str, err: &str) {
+    IMAGES_CACHE.remove(url);
+    let mut failure = IMAGE_FAILURES
+        .entry(url.to_owned())
+        .or_insert((0, Instant::now()));
+    failure.0 += 1;
+    failure.1 = Instant::now();
+    if failure.0 < MAX_IMAGE_ATTEMPTS {
+        warn!("Failed to cache image {url}, retrying later: {err}");
+    } else {
+        warn!("Failed to cache image {url}, giving up: {err}");
+    }
+}
+
+/// Decode a fetched image into a thumbnail, saving it to the disk and memory caches before updating
+/// the palettes that were waiting on it.
+fn store_fetched_image(url: String, bytes: Result<Vec<u8>, String>) {
+    let img =
+        match bytes.and_then(|bytes| image::load_from_memory(&bytes).map_err(|e| e.to_string())) {
+            Ok(img) => img,
+            Err(err) => {
+                image_fetch_failed(&url, &err);
+                return;
+            }
+        };
+    let img = if img.width() != *IMAGE_SIZE || img.height() != *IMAGE_SIZE {
+        img.resize_to_fill(
+            *IMAGE_SIZE,
+            *IMAGE_SIZE,
+            image::imageops::FilterType::Lanczos3,
+        )
+    } else {
+        img
+    }
+    .to_rgba8();
+    write_image_cache(&url, &img);
+    IMAGE_FAILURES.remove(&url);
+    IMAGES_CACHE.insert(url, Some(Arc::new(img)));
+    render::update_color_palettes();
+}
 
 struct CantusApp {
     // Core Graphics
@@ -146,6 +401,8 @@ struct CantusApp {
     particles: [Particle; 64],
     particles_accumulator: f32,
     scale_factor: f32,
+    /// Keep drawing until this time even if nothing seems to be moving.
+    redraw_until: Instant,
 
     // Scene & Resources
     text_renderer: Option<TextRenderer>,
@@ -153,6 +410,11 @@ struct CantusApp {
     background_pills: Vec<BackgroundPill>,
     icon_pills: Vec<IconInstance>,
     playhead_info: PlayheadUniforms,
+
+    // Per frame scratch buffers, kept around to reuse their allocations
+    track_renders: Vec<TrackRender>,
+    icon_entries: Vec<IconEntry>,
+    icon_data: Vec<(IconEntry, bool, f32)>,
 }
 
 impl Default for CantusApp {
@@ -167,12 +429,17 @@ impl Default for CantusApp {
             particles: [Particle::default(); 64],
             particles_accumulator: 0.0,
             scale_factor: 1.0,
+            redraw_until: Instant::now(),
 
             text_renderer: None,
             global_uniforms: GlobalUniforms::default(),
             background_pills: Vec::new(),
             icon_pills: Vec::new(),
             playhead_info: PlayheadUniforms::default(),
+
+            track_renders: Vec::new(),
+            icon_entries: Vec::new(),
+            icon_data: Vec::new(),
         }
     }
 }
@@ -203,8 +470,22 @@ struct GpuResources {
     particle_bind_group: BindGroup,
 
     // Image Management
+    image_layout: BindGroupLayout,
+    sampler: Sampler,
     texture_array: Texture,
-    url_to_image_index: HashMap<String, (i32, bool)>, // (index, used_this_frame)
+    url_to_image_index: HashMap<String, (i32, u64)>, // (index, frame it was last used)
+    frame: u64,
+
+    staging_belt: StagingBelt,
+    /// Frames in a row the surface was outdated or lost, reconfiguring backs off as it grows.
+    surface_failures: u32,
+    /// Set from wgpu's callback when the device goes away, usually over a suspend, so the next
+    /// frame builds everything again on a new one.
+    device_lost: Arc<AtomicBool>,
+    /// The frame drawn into and resolved to the surface when `msaa_samples` is above 1.
+    msaa_view: Option<TextureView>,
+    /// `msaa_samples` when this was created, changing it rebuilds the pipelines to match.
+    requested_samples: u32,
 }
 
 fn main() {
@@ -215,71 +496,105 @@ fn main() {
         .with_writer(std::io::stderr)
         .init();
 
+    // Read the config before anything else starts, so mistakes in it stop startup straight away
+    LazyLock::force(&CONFIG);
+
     #[cfg(feature = "spotify")]
     spotify::init();
 
+    #[cfg(feature = "mpris")]
+    mpris::init();
+
+    config::watch();
+    ipc::listen();
+    #[cfg(feature = "mpris_server")]
+    mpris_server::serve();
+    handle_signals();
     layer_shell::run();
+
+    // Save what was built up this run so the next start doesn't fetch it all again
+    BACKEND.persist();
+    render::persist_palette_cache();
+    ipc::remove_socket();
+}
+
+/// Quit cleanly on SIGINT and SIGTERM, so caches are saved like any other exit.
+fn handle_signals() {
+    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(err) => {
+            warn!("Failed to listen for signals: {err}");
+            return;
+        }
+    };
+    spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!("Received signal {signal}, shutting down");
+            layer_shell::request_exit();
+        }
+    });
 }
 
+/// Frames in a row the surface can stay lost before each further reconfigure is logged.
+const SURFACE_FAILURE_WARN: u32 = 64;
+
 impl CantusApp {
+    /// Draw a frame.
     fn render(&mut self) {
-        if self.gpu_resources.is_none() {
+        // Nothing submitted to a lost device gets drawn, wait for the surface to be recreated
+        if self
+            .gpu_resources
+            .as_ref()
+            .is_none_or(|gpu| gpu.device_lost.load(Ordering::Relaxed))
+        {
             return;
         }
 
         self.background_pills.clear();
         self.icon_pills.clear();
 
-        // Reset image usage
+        // Images stay uploaded until their slot is needed, so count frames to find the stalest
         if let Some(gpu) = self.gpu_resources.as_mut() {
-            for (_, used) in gpu.url_to_image_index.values_mut() {
-                *used = false;
-            }
+            gpu.frame += 1;
         }
 
         self.create_scene();
+        // Writing past the storage buffers would fail validation and lose the device
+        self.background_pills.truncate(MAX_PILLS);
+        self.icon_pills.truncate(MAX_PILLS);
 
-        // Prune unused images
-        if let Some(gpu) = self.gpu_resources.as_mut() {
-            gpu.url_to_image_index.retain(|_, (_, used)| *used);
-        }
-
-        // Write the buffers
         let gpu = self.gpu_resources.as_mut().unwrap();
-        gpu.queue.write_buffer(
-            &gpu.uniform_buffer,
-            0,
-            bytemuck::bytes_of(&self.global_uniforms),
-        );
-        gpu.queue.write_buffer(
-            &gpu.particles_buffer,
-            0,
-            bytemuck::cast_slice(&self.particles),
-        );
-        gpu.queue.write_buffer(
-            &gpu.playhead_buffer,
-            0,
-            bytemuck::bytes_of(&self.playhead_info),
-        );
-
-        if !self.background_pills.is_empty() {
-            gpu.queue.write_buffer(
-                &gpu.background_storage_buffer,
-                0,
-                bytemuck::cast_slice(&self.background_pills),
-            );
-        }
-        if !self.icon_pills.is_empty() {
-            gpu.queue.write_buffer(
-                &gpu.icon_storage_buffer,
-                0,
-                bytemuck::cast_slice(&self.icon_pills),
-            );
-        }
-
-        let Ok(surface_texture) = gpu.surface.get_current_texture() else {
-            gpu.surface.configure(&gpu.device, &gpu.surface_config);
-            return;
+        let surface_texture = match gpu.surface.get_current_texture() {
+            Ok(surface_texture) => {
+                gpu.surface_failures = 0;
+                surface_texture
+            }
+            Err(SurfaceError::Outdated | SurfaceError::Lost) => {
+                // Common after resuming from suspend, reconfigure straight away then back off to
+                // every power of two frames so a surface that won't come back isn't hammered
+                gpu.surface_failures += 1;
+                if gpu.surface_failures.is_power_of_two() {
+                    if gpu.surface_failures >= SURFACE_FAILURE_WARN {
+                        warn!(
+                            "The surface is still lost after {} frames, reconfiguring it again",
+                            gpu.surface_failures
+                        );
+                    }
+                    gpu.surface.configure(&gpu.device, &gpu.surface_config);
+                }
+                return;
+            }
+            // The compositor is holding on to the frames, try again next time
+            Err(SurfaceError::Timeout) => return,
+            Err(SurfaceError::OutOfMemory) => {
+                error!("Out of memory for the next frame, skipping it");
+                gpu.shrink_texture_array();
+                return;
+            }
+            Err(err @ SurfaceError::Other) => {
+                warn!("Failed to get the next frame: {err}");
+                return;
+            }
         };
         let surface_view = surface_texture
             .texture
@@ -288,15 +603,47 @@ impl CantusApp {
             .device
             .create_command_encoder(&CommandEncoderDescriptor::default());
 
+        // Write the buffers, staged so every upload goes out with the frame's single submission
+        for (buffer, data) in [
+            (
+                &gpu.uniform_buffer,
+                bytemuck::bytes_of(&self.global_uniforms),
+            ),
+            (&gpu.particles_buffer, bytemuck::cast_slice(&self.particles)),
+            (
+                &gpu.playhead_buffer,
+                bytemuck::bytes_of(&self.playhead_info),
+            ),
+            (
+                &gpu.background_storage_buffer,
+                bytemuck::cast_slice(&self.background_pills),
+            ),
+            (
+                &gpu.icon_storage_buffer,
+                bytemuck::cast_slice(&self.icon_pills),
+            ),
+        ] {
+            if let Some(size) = BufferSize::new(data.len() as u64) {
+                gpu.staging_belt
+                    .write_buffer(&mut encoder, buffer, 0, size, &gpu.device)
+                    .copy_from_slice(data);
+            }
+        }
+
         {
             let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Main Render Pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &surface_view,
-                    resolve_target: None,
+                    view: gpu.msaa_view.as_ref().unwrap_or(&surface_view),
+                    resolve_target: gpu.msaa_view.as_ref().map(|_| &surface_view),
                     ops: Operations {
                         load: LoadOp::Clear(Color::TRANSPARENT),
-                        store: StoreOp::Store,
+                        // Only the resolved frame is kept, the samples are thrown away
+                        store: if gpu.msaa_view.is_some() {
+                            StoreOp::Discard
+                        } else {
+                            StoreOp::Store
+                        },
                     },
                     depth_slice: None,
                 })],
@@ -328,17 +675,23 @@ impl CantusApp {
                 rpass.draw(0..4, 0..self.icon_pills.len() as u32);
             }
 
-            rpass.set_pipeline(&gpu.particle_pipeline);
-            rpass.set_bind_group(0, &gpu.particle_bind_group, &[]);
-            rpass.draw(0..4, 0..64);
+            if CONFIG.load().show_particles() {
+                rpass.set_pipeline(&gpu.particle_pipeline);
+                rpass.set_bind_group(0, &gpu.particle_bind_group, &[]);
+                rpass.draw(0..4, 0..64);
+            }
 
             rpass.set_pipeline(&gpu.playhead_pipeline);
             rpass.set_bind_group(0, &gpu.playhead_bind_group, &[]);
             rpass.draw(0..4, 0..1);
         }
 
+        gpu.staging_belt.finish();
         gpu.queue.submit([encoder.finish()]);
         surface_texture.present();
+        gpu.staging_belt.recall();
+        // Hand finished staging chunks back to the belt without waiting on the GPU
+        let _ = gpu.device.poll(PollType::Poll);
     }
 
     fn get_image_index(&mut self, url: &str) -> i32 {
@@ -347,19 +700,36 @@ impl CantusApp {
         };
 
         if let Some(entry) = gpu.url_to_image_index.get_mut(url) {
-            entry.1 = true;
+            entry.1 = gpu.frame;
             return entry.0;
         }
 
         if let Some(img_ref) = IMAGES_CACHE.get(url)
             && let Some(image) = img_ref.as_ref()
         {
-            let mut used_slots = vec![false; MAX_TEXTURE_LAYERS as usize];
+            let mut used_slots = vec![false; gpu.texture_array.depth_or_array_layers() as usize];
             for (idx, _) in gpu.url_to_image_index.values() {
                 used_slots[*idx as usize] = true;
             }
 
-            if let Some(slot) = used_slots.iter().position(|&used| !used) {
+            // Evict the least recently used image once full, as long as it isn't on screen this frame
+            let free_slot = used_slots.iter().position(|&used| !used).or_else(|| {
+                let (lru_url, (slot, _)) = gpu
+                    .url_to_image_index
+                    .iter()
+                    .filter(|(_, (_, last_used))| *last_used < gpu.frame)
+                    .min_by_key(|(_, (_, last_used))| *last_used)?;
+                let (lru_url, slot) = (lru_url.clone(), *slot as usize);
+                gpu.url_to_image_index.remove(&lru_url);
+                Some(slot)
+            });
+            // Everything is on screen at once, make room for more
+            let free_slot = free_slot.or_else(|| {
+                let layers = gpu.texture_array.depth_or_array_layers();
+                gpu.grow_texture_array().then_some(layers as usize)
+            });
+
+            if let Some(slot) = free_slot {
                 gpu.queue.write_texture(
                     wgpu::TexelCopyTextureInfo {
                         texture: &gpu.texture_array,
@@ -374,18 +744,18 @@ impl CantusApp {
                     image.as_raw(),
                     wgpu::TexelCopyBufferLayout {
                         offset: 0,
-                        bytes_per_row: Some(4 * IMAGE_SIZE),
-                        rows_per_image: Some(IMAGE_SIZE),
+                        bytes_per_row: Some(4 * *IMAGE_SIZE),
+                        rows_per_image: Some(*IMAGE_SIZE),
                     },
                     wgpu::Extent3d {
-                        width: IMAGE_SIZE,
-                        height: IMAGE_SIZE,
+                        width: *IMAGE_SIZE,
+                        height: *IMAGE_SIZE,
                         depth_or_array_layers: 1,
                     },
                 );
 
                 gpu.url_to_image_index
-                    .insert(url.to_owned(), (slot as i32, true));
+                    .insert(url.to_owned(), (slot as i32, gpu.frame));
                 return slot as i32;
             }
         }
@@ -398,10 +768,14 @@ where
     D: Deserializer<'de>,
 {
     let images: Vec<Image> = Vec::deserialize(deserializer)?;
-    Ok(images
-        .into_iter()
+    // The smallest image still sharp at the art resolution, otherwise the biggest, unknown sizes last
+    let best = images
+        .iter()
+        .filter(|img| img.width.is_some_and(|width| width >= *IMAGE_SIZE))
         .min_by_key(|img| img.width)
-        .map(|img| img.url))
+        .or_else(|| images.iter().max_by_key(|img| img.width))
+        .map(|img| img.url.clone());
+    Ok(best)
 }
 
 fn deserialize_first_artist<'de, D>(deserializer: D) -> Result<Artist, D::Error>
@@ -409,5 +783,41 @@ where
     D: Deserializer<'de>,
 {
     let artists: Vec<Artist> = Vec::deserialize(deserializer)?;
-    Ok(artists.into_iter().next().unwrap())
+    // Local files and some catalog entries come back without any artists
+    Ok(artists.into_iter().next().unwrap_or_else(|| Artist {
+        id: None,
+        name: String::new(),
+        image: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Artists {
+        #[serde(deserialize_with = "deserialize_first_artist")]
+        artists: Artist,
+    }
+
+    #[test]
+    fn missing_artists_give_an_empty_one() {
+        let artist = serde_json::from_str::<Artists>(r#"{"artists":[]}"#)
+            .unwrap()
+            .artists;
+        assert!(artist.name.is_empty());
+        assert!(artist.id.is_none());
+        assert!(artist.image.is_none());
+    }
+
+    #[test]
+    fn first_artist_is_kept() {
+        let artist = serde_json::from_str::<Artists>(
+            r#"{"artists":[{"id":null,"name":"First"},{"id":null,"name":"Second"}]}"#,
+        )
+        .unwrap()
+        .artists;
+        assert_eq!(artist.name, "First");
+    }
 }
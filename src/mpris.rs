@@ -0,0 +1,309 @@
+use crate::{
+    Album, Artist, IMAGES_CACHE, PLAYBACK_STATE, RepeatMode, Track, backend::PlaybackBackend,
+    config::CONFIG, hashed_id, poll_interval, read_image_cache, render::update_color_palettes,
+    should_fetch_image, store_fetched_image, update_playback_state,
+};
+use parking_lot::RwLock;
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, LazyLock},
+    thread::{sleep, spawn},
+    time::Instant,
+};
+use tracing::{error, info, warn};
+use url::Url;
+use zbus::{
+    blocking::{Connection, Proxy, fdo::DBusProxy, proxy::Builder},
+    proxy::CacheProperties,
+    zvariant::{OwnedObjectPath, OwnedValue},
+};
+
+const BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// How many previously played tracks to keep in the queue, MPRIS has no history of its own.
+const MAX_HISTORY: usize = 10;
+
+struct MprisState {
+    player: Option<Proxy<'static>>,
+    track_path: Option<OwnedObjectPath>,
+}
+
+static MPRIS_STATE: LazyLock<RwLock<MprisState>> = LazyLock::new(|| {
+    RwLock::new(MprisState {
+        player: None,
+        track_path: None,
+    })
+});
+
+/// Playback controls routed through the `org.mpris.MediaPlayer2.Player` interface.
+pub struct MprisBackend;
+
+impl PlaybackBackend for MprisBackend {
+    fn play(&self) {
+        call_player("Play", &());
+    }
+
+    fn pause(&self) {
+        call_player("Pause", &());
+    }
+
+    fn seek_ms(&self, ms: u32) {
+        let Some(track_path) = MPRIS_STATE.read().track_path.clone() else {
+            warn!("Unable to seek, the MPRIS player did not report a track id");
+            return;
+        };
+        call_player("SetPosition", &(track_path, i64::from(ms) * 1000));
+    }
+
+    fn set_volume(&self, pct: u8) {
+        set_player_property("Volume", f64::from(pct) / 100.0);
+    }
+
+    fn skip_next(&self) {
+        call_player("Next", &());
+    }
+
+    fn skip_prev(&self) {
+        call_player("Previous", &());
+    }
+
+    fn set_shuffle(&self, shuffle: bool) {
+        set_player_property("Shuffle", shuffle);
+    }
+
+    fn set_repeat(&self, repeat: RepeatMode) {
+        let loop_status = match repeat {
+            RepeatMode::Off => "None",
+            RepeatMode::Track => "Track",
+            RepeatMode::Context => "Playlist",
+        };
+        set_player_property("LoopStatus", loop_status);
+    }
+}
+
+fn call_player<B>(method: &str, body: &B)
+where
+    B: serde::Serialize + zbus::zvariant::DynamicType,
+{
+    let Some(player) = MPRIS_STATE.read().player.clone() else {
+        warn!("No MPRIS player available to {method}");
+        return;
+    };
+    if let Err(err) = player.call_method(method, body) {
+        error!("Failed to call {method} on MPRIS player: {err}");
+    }
+}
+
+fn set_player_property<T>(property: &str, value: T)
+where
+    T: Into<zbus::zvariant::Value<'static>> + 'static,
+{
+    let Some(player) = MPRIS_STATE.read().player.clone() else {
+        warn!("No MPRIS player available to set {property}");
+        return;
+    };
+    if let Err(err) = player.set_property(property, value) {
+        error!("Failed to set {property} on MPRIS player: {err}");
+    }
+}
+
+pub fn init() {
+    spawn(|| {
+        let connection = match Connection::session() {
+            Ok(connection) => connection,
+            Err(err) => {
+                error!("Failed to connect to the D-Bus session bus: {err}");
+                return;
+            }
+        };
+        loop {
+            get_mpris_playback(&connection);
+            sleep(poll_interval());
+        }
+    });
+}
+
+/// Find the player to control, preferring the configured `mpris_player`.
+fn find_player(connection: &Connection) -> Option<Proxy<'static>> {
+    let names = DBusProxy::new(connection)
+        .and_then(|dbus| dbus.list_names().map_err(Into::into))
+        .map_err(|e| error!("Failed to list D-Bus names: {e}"))
+        .ok()?;
+    let players = names
+        .iter()
+        .map(ToString::to_string)
+        .filter(|name| name.starts_with(BUS_PREFIX))
+        // Cantus's own player from the `mpris_server` feature, controlling it would go in circles
+        .filter(|name| name != "org.mpris.MediaPlayer2.cantus")
+        .collect::<Vec<_>>();
+    let player = CONFIG
+        .load()
+        .mpris_player
+        .as_ref()
+        .and_then(|target| {
+            players
+                .iter()
+                .find(|name| name[BUS_PREFIX.len()..].contains(target.as_str()))
+        })
+        .or_else(|| players.first())?
+        .clone();
+
+    info!("Using MPRIS player {player}");
+    Builder::new(connection)
+        .destination(player)
+        .and_then(|b| b.path(PLAYER_PATH))
+        .and_then(|b| b.interface(PLAYER_INTERFACE))
+        .map(|b| b.cache_properties(CacheProperties::No))
+        .and_then(Builder::build)
+        .map_err(|e| error!("Failed to create MPRIS proxy: {e}"))
+        .ok()
+}
+
+fn get_mpris_playback(connection: &Connection) {
+    let now = Instant::now();
+    if now < PLAYBACK_STATE.read().last_interaction {
+        return;
+    }
+
+    let existing = MPRIS_STATE.read().player.clone();
+    let Some(player) = existing.or_else(|| find_player(connection)) else {
+        return;
+    };
+
+    let (Ok(status), Ok(metadata)) = (
+        player.get_property::<String>("PlaybackStatus"),
+        player.get_property::<HashMap<String, OwnedValue>>("Metadata"),
+    ) else {
+        // The player most likely quit, look for a new one next poll
+        let mut mpris_state = MPRIS_STATE.write();
+        mpris_state.player = None;
+        mpris_state.track_path = None;
+        drop(mpris_state);
+        return;
+    };
+    // Position, Volume, Shuffle and LoopStatus are optional for players to implement
+    let position = player.get_property::<i64>("Position").unwrap_or_default();
+    let volume = player.get_property::<f64>("Volume").ok();
+    let shuffle = player.get_property::<bool>("Shuffle").unwrap_or_default();
+    let repeat = match player.get_property::<String>("LoopStatus").as_deref() {
+        Ok("Track") => RepeatMode::Track,
+        Ok("Playlist") => RepeatMode::Context,
+        _ => RepeatMode::Off,
+    };
+
+    let Some(track) = metadata_track(&metadata) else {
+        return;
+    };
+    let image = track.album.image.clone();
+
+    {
+        let mut mpris_state = MPRIS_STATE.write();
+        mpris_state.player = Some(player);
+        mpris_state.track_path = metadata_value::<OwnedObjectPath>(&metadata, "mpris:trackid");
+    }
+
+    let now = Instant::now();
+    update_playback_state(|state| {
+        let current_id = state.queue.get(state.queue_index).and_then(|t| t.id);
+        if current_id != track.id {
+            if let Some(index) = state.queue.iter().position(|t| t.id == track.id) {
+                state.queue_index = index;
+            } else {
+                state.queue.truncate(state.queue_index + 1);
+                state.queue.push(track);
+                let excess = state.queue.len().saturating_sub(MAX_HISTORY + 1);
+                state.queue.drain(..excess);
+                state.queue_index = state.queue.len() - 1;
+            }
+        }
+
+        state.volume = volume.map(|v| (v * 100.0).round().clamp(0.0, 100.0) as u8);
+        if now >= state.last_interaction {
+            state.playing = status == "Playing";
+            state.shuffle = shuffle;
+            state.repeat = repeat;
+            state.progress = (position / 1000).max(0) as u32;
+        }
+        state.last_progress_update = now;
+    });
+
+    if let Some(image) = image {
+        ensure_image_cached(&image);
+    }
+}
+
+fn metadata_value<T>(metadata: &HashMap<String, OwnedValue>, key: &str) -> Option<T>
+where
+    T: TryFrom<OwnedValue>,
+{
+    metadata
+        .get(key)
+        .and_then(|value| value.try_clone().ok())
+        .and_then(|value| T::try_from(value).ok())
+}
+
+/// Map the `xesam`/`mpris` metadata fields into a `Track`.
+fn metadata_track(metadata: &HashMap<String, OwnedValue>) -> Option<Track> {
+    let name = metadata_value::<String>(metadata, "xesam:title")?;
+    let artist_name = metadata_value::<Vec<String>>(metadata, "xesam:artist")
+        .and_then(|artists| artists.into_iter().next())
+        .unwrap_or_default();
+    let album_name = metadata_value::<String>(metadata, "xesam:album").unwrap_or_default();
+    let art_url = metadata_value::<String>(metadata, "mpris:artUrl");
+    let duration_us = metadata_value::<i64>(metadata, "mpris:length")
+        .or_else(|| metadata_value::<u64>(metadata, "mpris:length").map(|v| v as i64))
+        .unwrap_or_default();
+
+    let track_key = metadata_value::<OwnedObjectPath>(metadata, "mpris:trackid")
+        .map_or_else(|| format!("{name}{artist_name}"), |path| path.to_string());
+    let album_key = art_url.clone().unwrap_or_else(|| album_name.clone());
+
+    Some(Track {
+        id: Some(hashed_id(&track_key)),
+        name,
+        album: Album {
+            id: Some(hashed_id(&album_key)),
+            name: album_name,
+            image: art_url,
+        },
+        artist: Artist {
+            id: None,
+            name: artist_name,
+            image: None,
+        },
+        duration_ms: (duration_us / 1000).max(0) as u32,
+        is_local: false,
+        uri: String::new(),
+    })
+}
+
+fn ensure_image_cached(url: &str) {
+    if !should_fetch_image(url) {
+        return;
+    }
+    if let Some(img) = read_image_cache(url) {
+        IMAGES_CACHE.insert(url.to_owned(), Some(Arc::new(img)));
+        update_color_palettes();
+        return;
+    }
+    IMAGES_CACHE.insert(url.to_owned(), None);
+
+    let url = url.to_owned();
+    spawn(move || {
+        // Players commonly hand out local files for cover art
+        let bytes = match Url::parse(&url) {
+            Ok(parsed) if parsed.scheme() == "file" => parsed
+                .to_file_path()
+                .map_err(|()| "not a local path".to_owned())
+                .and_then(|path| fs::read(path).map_err(|e| e.to_string())),
+            _ => ureq::get(&url)
+                .call()
+                .and_then(|mut resp| resp.body_mut().read_to_vec())
+                .map_err(|e| e.to_string()),
+        };
+        store_fetched_image(url, bytes);
+    });
+}
@@ -0,0 +1,351 @@
+use crate::{
+    PLAYBACK_STATE, RepeatMode, Track, TrackId,
+    interaction::{seek_by, seek_to, skip_by, toggle_playing},
+    layer_shell,
+};
+use std::{
+    collections::HashMap,
+    thread::{sleep, spawn},
+    time::Duration,
+};
+use tracing::{error, info};
+use zbus::{
+    block_on,
+    blocking::{Connection, connection::Builder},
+    interface,
+    zvariant::{ObjectPath, OwnedValue, Value},
+};
+
+/// The name Cantus is published under, the `mpris` backend skips it so it never controls itself.
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.cantus";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+/// Track id reported while the queue is empty, as the MPRIS spec asks.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+/// How often the playback state is checked for changes to announce.
+const CHANGE_POLL: Duration = Duration::from_millis(500);
+
+/// The `org.mpris.MediaPlayer2` root interface, Cantus has no window to raise.
+struct Root;
+
+// zbus calls every method through `&self`, most answers are fixed
+#[allow(clippy::unused_self, clippy::missing_const_for_fn)]
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl Root {
+    fn raise(&self) {}
+
+    fn quit(&self) {
+        layer_shell::request_exit();
+    }
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &'static str {
+        "Cantus"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// The `org.mpris.MediaPlayer2.Player` interface, mirroring `PLAYBACK_STATE`.
+///
+/// Controls run on their own thread like clicks do, so a slow backend never holds up the bus.
+struct Player;
+
+// zbus calls every method through `&self` and hands over owned arguments
+#[allow(
+    clippy::unused_self,
+    clippy::missing_const_for_fn,
+    clippy::needless_pass_by_value
+)]
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl Player {
+    fn play(&self) {
+        spawn(|| toggle_playing(true));
+    }
+
+    fn pause(&self) {
+        spawn(|| toggle_playing(false));
+    }
+
+    fn play_pause(&self) {
+        spawn(|| toggle_playing(!PLAYBACK_STATE.read().playing));
+    }
+
+    fn stop(&self) {
+        spawn(|| toggle_playing(false));
+    }
+
+    fn next(&self) {
+        spawn(|| skip_by(1));
+    }
+
+    fn previous(&self) {
+        spawn(|| skip_by(-1));
+    }
+
+    /// Move by `offset` microseconds.
+    fn seek(&self, offset: i64) {
+        spawn(move || seek_by(offset / 1000));
+    }
+
+    /// Jump to `position` microseconds, ignored when the track has changed since it was asked for.
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        if track_id.as_str() != current_track_path().as_str() {
+            return;
+        }
+        let milliseconds = u32::try_from(position / 1000).unwrap_or_default();
+        spawn(move || seek_to(milliseconds));
+    }
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &'static str {
+        let state = PLAYBACK_STATE.read();
+        if state.queue.is_empty() {
+            "Stopped"
+        } else if state.playing {
+            "Playing"
+        } else {
+            "Paused"
+        }
+    }
+
+    #[zbus(property)]
+    fn loop_status(&self) -> &'static str {
+        let repeat = PLAYBACK_STATE.read().repeat;
+        match repeat {
+            RepeatMode::Off => "None",
+            RepeatMode::Track => "Track",
+            RepeatMode::Context => "Playlist",
+        }
+    }
+
+    #[zbus(property)]
+    fn shuffle(&self) -> bool {
+        PLAYBACK_STATE.read().shuffle
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let track_path = current_track_path();
+        let mut metadata = HashMap::new();
+        let mut insert = |key: &str, value: Value<'_>| {
+            if let Ok(value) = OwnedValue::try_from(value) {
+                metadata.insert(key.to_owned(), value);
+            }
+        };
+        insert("mpris:trackid", Value::from(track_path));
+        let state = PLAYBACK_STATE.read();
+        if let Some(track) = state.queue.get(state.queue_index) {
+            insert(
+                "mpris:length",
+                Value::from(i64::from(track.duration_ms) * 1000),
+            );
+            insert("xesam:title", Value::from(track.name.as_str()));
+            insert(
+                "xesam:artist",
+                Value::from(vec![track.artist.name.as_str()]),
+            );
+            if !track.album.name.is_empty() {
+                insert("xesam:album", Value::from(track.album.name.as_str()));
+            }
+            if let Some(image) = &track.album.image {
+                insert("mpris:artUrl", Value::from(image.as_str()));
+            }
+        }
+        drop(state);
+        metadata
+    }
+
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        PLAYBACK_STATE
+            .read()
+            .volume
+            .map_or(1.0, |volume| f64::from(volume) / 100.0)
+    }
+
+    /// Microseconds into the current track, asked for when needed rather than announced.
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn position(&self) -> i64 {
+        let state = PLAYBACK_STATE.read();
+        let elapsed = if state.playing {
+            state.last_progress_update.elapsed().as_millis() as i64
+        } else {
+            0
+        };
+        (i64::from(state.progress) + elapsed) * 1000
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// An object path for the current track, MPRIS ids can't hold the `-` some hashed ids have.
+fn current_track_path() -> ObjectPath<'static> {
+    let state = PLAYBACK_STATE.read();
+    state
+        .queue
+        .get(state.queue_index)
+        .and_then(|track| track.id)
+        .and_then(|id| {
+            let id = id.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+            ObjectPath::try_from(format!("/org/cantus/track/{id}")).ok()
+        })
+        .unwrap_or_else(|| ObjectPath::from_static_str_unchecked(NO_TRACK))
+}
+
+/// What the announced properties were read from, compared each poll to find what changed.
+#[derive(PartialEq)]
+struct Snapshot {
+    track: Option<(Option<TrackId>, String)>,
+    playing: bool,
+    volume: Option<u8>,
+    shuffle: bool,
+    repeat: RepeatMode,
+}
+
+impl Snapshot {
+    fn take() -> Self {
+        let state = PLAYBACK_STATE.read();
+        Self {
+            track: state
+                .queue
+                .get(state.queue_index)
+                .map(|track: &Track| (track.id, track.name.clone())),
+            playing: state.playing,
+            volume: state.volume,
+            shuffle: state.shuffle,
+            repeat: state.repeat,
+        }
+    }
+}
+
+/// Publish Cantus as an MPRIS player, so status bars and notification daemons can show and
+/// control what the bar shows whichever backend drives it.
+pub fn serve() {
+    let connection = match Builder::session()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(PLAYER_PATH, Root))
+        .and_then(|builder| builder.serve_at(PLAYER_PATH, Player))
+        .and_then(Builder::build)
+    {
+        Ok(connection) => connection,
+        Err(err) => {
+            error!("Failed to publish the MPRIS service: {err}");
+            return;
+        }
+    };
+    info!("Publishing playback over MPRIS as {BUS_NAME}");
+    spawn(move || {
+        let mut last = Snapshot::take();
+        loop {
+            sleep(CHANGE_POLL);
+            let snapshot = Snapshot::take();
+            if snapshot != last {
+                announce_changes(&connection, &last, &snapshot);
+                last = snapshot;
+            }
+        }
+    });
+}
+
+/// Emit `PropertiesChanged` for whatever differs between two snapshots.
+fn announce_changes(connection: &Connection, last: &Snapshot, snapshot: &Snapshot) {
+    let player = match connection
+        .object_server()
+        .interface::<_, Player>(PLAYER_PATH)
+    {
+        Ok(player) => player,
+        Err(err) => {
+            error!("Failed to find the MPRIS player interface: {err}");
+            return;
+        }
+    };
+    let emitter = player.signal_emitter();
+    let iface = player.get();
+    let result = block_on(async {
+        if snapshot.track != last.track {
+            iface.metadata_changed(emitter).await?;
+        }
+        if snapshot.track.is_some() != last.track.is_some() || snapshot.playing != last.playing {
+            iface.playback_status_changed(emitter).await?;
+        }
+        if snapshot.volume != last.volume {
+            iface.volume_changed(emitter).await?;
+        }
+        if snapshot.shuffle != last.shuffle {
+            iface.shuffle_changed(emitter).await?;
+        }
+        if snapshot.repeat != last.repeat {
+            iface.loop_status_changed(emitter).await?;
+        }
+        zbus::Result::Ok(())
+    });
+    if let Err(err) = result {
+        error!("Failed to announce MPRIS changes: {err}");
+    }
+}
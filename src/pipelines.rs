@@ -1,22 +1,40 @@
+use crate::config::CONFIG;
 use crate::render::{BackgroundPill, GlobalUniforms, IconInstance, Particle, PlayheadUniforms};
 use crate::text_render::TextRenderer;
-use crate::{CantusApp, GpuResources};
-use std::collections::HashMap;
+use crate::{CantusApp, GpuResources, layer_shell};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+use tracing::{info, warn};
 use wgpu::{
-    BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, BufferBindingType,
-    BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CompositeAlphaMode,
-    DeviceDescriptor, ExperimentalFeatures, Extent3d, Features, FilterMode, FragmentState, Limits,
-    MemoryHints, MultisampleState, PipelineCompilationOptions, PipelineLayoutDescriptor,
-    PowerPreference, PresentMode, PrimitiveState, PrimitiveTopology, RenderPipelineDescriptor,
-    RequestAdapterOptions, SamplerBindingType, SamplerDescriptor, ShaderModule,
-    ShaderModuleDescriptor, ShaderSource, ShaderStages, Surface, SurfaceConfiguration,
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferBindingType,
+    BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites, CommandEncoderDescriptor,
+    CompositeAlphaMode, Device, DeviceDescriptor, DeviceLostReason, ExperimentalFeatures, Extent3d,
+    Features, FilterMode, FragmentState, Limits, MemoryHints, MultisampleState, Origin3d,
+    PipelineCompilationOptions, PipelineLayoutDescriptor, PowerPreference, PresentMode,
+    PrimitiveState, PrimitiveTopology, RenderPipelineDescriptor, RequestAdapterOptions, Sampler,
+    SamplerBindingType, SamplerDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderSource,
+    ShaderStages, Surface, SurfaceConfiguration, TexelCopyTextureInfo, Texture, TextureAspect,
     TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
-    TextureViewDescriptor, TextureViewDimension, Trace, VertexState,
+    TextureView, TextureViewDescriptor, TextureViewDimension, Trace, VertexState,
+    util::StagingBelt,
 };
 
-pub const MAX_TEXTURE_LAYERS: u32 = 48;
-pub const IMAGE_SIZE: u32 = 64;
+/// Images the texture array starts with room for.
+const INITIAL_TEXTURE_LAYERS: u32 = 48;
+/// The array doubles up to this when more images are on screen at once, the downlevel limit.
+const MAX_TEXTURE_LAYERS: u32 = 256;
+/// Background and icon pills the storage buffers have room for, any more in a frame are dropped.
+pub const MAX_PILLS: usize = 256;
+/// Width and height of each image in the texture array, `art_resolution` read once at startup.
+pub static IMAGE_SIZE: LazyLock<u32> = LazyLock::new(|| CONFIG.load().art_resolution);
+/// Staging buffer size for per frame uploads, enough for every buffer at full capacity.
+const STAGING_CHUNK_SIZE: u64 = 64 * 1024;
 
 impl CantusApp {
     pub fn configure_render_surface(&mut self, surface: Surface<'static>, width: u32, height: u32) {
@@ -40,6 +58,18 @@ impl CantusApp {
         }))
         .expect("No device");
 
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost_flag = Arc::clone(&device_lost);
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the old device when recreating reports it as destroyed, that's expected
+            if reason == DeviceLostReason::Destroyed {
+                return;
+            }
+            warn!("Lost the GPU device, recreating it: {message}");
+            lost_flag.store(true, Ordering::Relaxed);
+            layer_shell::request_redraw();
+        });
+
         let capabilities = surface.get_capabilities(&adapter);
         let alpha_mode = [
             CompositeAlphaMode::PreMultiplied,
@@ -49,6 +79,8 @@ impl CantusApp {
         .find(|m| capabilities.alpha_modes.contains(m))
         .unwrap_or(CompositeAlphaMode::Auto);
 
+        // Not an sRGB format, colours are written out still gamma encoded, as the album art and
+        // palettes already are, so nothing is converted twice
         let format = TextureFormat::Rgba8Unorm;
         let surface_config = SurfaceConfiguration {
             usage: TextureUsages::RENDER_ATTACHMENT,
@@ -62,7 +94,38 @@ impl CantusApp {
         };
         surface.configure(&device, &surface_config);
 
-        self.text_renderer = Some(TextRenderer::new(&device, format));
+        // Everything in the pass, text included, draws into the same multisampled frame
+        let requested_samples = CONFIG.load().msaa_samples;
+        let sample_count = if adapter
+            .get_texture_format_features(format)
+            .flags
+            .sample_count_supported(requested_samples)
+        {
+            requested_samples
+        } else {
+            warn!("{requested_samples}x MSAA isn't supported by this adapter, drawing without it");
+            1
+        };
+        let msaa_view = (sample_count > 1).then(|| {
+            device
+                .create_texture(&TextureDescriptor {
+                    label: Some("Multisampled frame"),
+                    size: Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&TextureViewDescriptor::default())
+        });
+
+        self.text_renderer = Some(TextRenderer::new(&device, format, sample_count));
 
         let create_shader = |label, source: &str| {
             device.create_shader_module(ShaderModuleDescriptor {
@@ -153,7 +216,10 @@ impl CantusApp {
                     ..Default::default()
                 },
                 depth_stencil: None,
-                multisample: MultisampleState::default(),
+                multisample: MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
                 multiview: None,
                 cache: None,
             })
@@ -190,29 +256,16 @@ impl CantusApp {
         );
         let background_storage_buffer = mk_buf(
             "BG Pills",
-            (std::mem::size_of::<BackgroundPill>() * 256) as u64,
+            (std::mem::size_of::<BackgroundPill>() * MAX_PILLS) as u64,
             BufferUsages::STORAGE,
         );
         let icon_storage_buffer = mk_buf(
             "Icons",
-            (std::mem::size_of::<IconInstance>() * 256) as u64,
+            (std::mem::size_of::<IconInstance>() * MAX_PILLS) as u64,
             BufferUsages::STORAGE,
         );
 
-        let texture_array = device.create_texture(&TextureDescriptor {
-            label: Some("Images"),
-            size: Extent3d {
-                width: IMAGE_SIZE,
-                height: IMAGE_SIZE,
-                depth_or_array_layers: MAX_TEXTURE_LAYERS,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        let texture_array = create_texture_array(&device, INITIAL_TEXTURE_LAYERS);
         let image_view = texture_array.create_view(&TextureViewDescriptor {
             dimension: Some(TextureViewDimension::D2Array),
             ..Default::default()
@@ -260,49 +313,23 @@ impl CantusApp {
                 },
             ],
         );
-        let background_bind_group = mk_bg(
+        let background_bind_group = create_image_bind_group(
+            &device,
             "Background",
             &std_layout,
-            &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: background_storage_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::TextureView(&image_view),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: BindingResource::Sampler(&sampler),
-                },
-            ],
+            &uniform_buffer,
+            &background_storage_buffer,
+            &image_view,
+            &sampler,
         );
-        let icon_bind_group = mk_bg(
+        let icon_bind_group = create_image_bind_group(
+            &device,
             "Icon",
             &std_layout,
-            &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: uniform_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: icon_storage_buffer.as_entire_binding(),
-                },
-                BindGroupEntry {
-                    binding: 2,
-                    resource: BindingResource::TextureView(&image_view),
-                },
-                BindGroupEntry {
-                    binding: 3,
-                    resource: BindingResource::Sampler(&sampler),
-                },
-            ],
+            &uniform_buffer,
+            &icon_storage_buffer,
+            &image_view,
+            &sampler,
         );
 
         self.gpu_resources = Some(GpuResources {
@@ -323,8 +350,140 @@ impl CantusApp {
             background_bind_group,
             icon_bind_group,
             particle_bind_group,
+            image_layout: std_layout,
+            sampler,
             texture_array,
             url_to_image_index: HashMap::new(),
+            frame: 0,
+            staging_belt: StagingBelt::new(STAGING_CHUNK_SIZE),
+            surface_failures: 0,
+            device_lost,
+            msaa_view,
+            requested_samples,
         });
     }
 }
+
+fn create_texture_array(device: &Device, layers: u32) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Images"),
+        size: Extent3d {
+            width: *IMAGE_SIZE,
+            height: *IMAGE_SIZE,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
+
+fn create_image_bind_group(
+    device: &Device,
+    label: &str,
+    layout: &BindGroupLayout,
+    uniform_buffer: &Buffer,
+    storage_buffer: &Buffer,
+    image_view: &TextureView,
+    sampler: &Sampler,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: storage_buffer.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::TextureView(image_view),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+impl GpuResources {
+    /// Double the image texture array, copying the uploaded images across and rebinding it.
+    ///
+    /// Returns false once the array is at `MAX_TEXTURE_LAYERS`.
+    pub fn grow_texture_array(&mut self) -> bool {
+        let layers = self.texture_array.depth_or_array_layers();
+        if layers >= MAX_TEXTURE_LAYERS {
+            return false;
+        }
+        let new_layers = (layers * 2).min(MAX_TEXTURE_LAYERS);
+        info!("Growing the image texture array to {new_layers} layers");
+
+        let texture_array = create_texture_array(&self.device, new_layers);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor::default());
+        let copy_info = |texture| TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        };
+        encoder.copy_texture_to_texture(
+            copy_info(&self.texture_array),
+            copy_info(&texture_array),
+            self.texture_array.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+        self.bind_texture_array(texture_array);
+        true
+    }
+
+    /// Swap a grown image texture array back for one at `INITIAL_TEXTURE_LAYERS`, to give memory
+    /// back. The images are dropped and uploaded again as they're drawn.
+    ///
+    /// Returns false if the array never grew.
+    pub fn shrink_texture_array(&mut self) -> bool {
+        if self.texture_array.depth_or_array_layers() <= INITIAL_TEXTURE_LAYERS {
+            return false;
+        }
+        info!("Shrinking the image texture array to {INITIAL_TEXTURE_LAYERS} layers");
+        self.url_to_image_index.clear();
+        self.bind_texture_array(create_texture_array(&self.device, INITIAL_TEXTURE_LAYERS));
+        true
+    }
+
+    /// Rebind the bind groups sampling the image texture array to a new one.
+    fn bind_texture_array(&mut self, texture_array: Texture) {
+        let image_view = texture_array.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        self.background_bind_group = create_image_bind_group(
+            &self.device,
+            "Background",
+            &self.image_layout,
+            &self.uniform_buffer,
+            &self.background_storage_buffer,
+            &image_view,
+            &self.sampler,
+        );
+        self.icon_bind_group = create_image_bind_group(
+            &self.device,
+            "Icon",
+            &self.image_layout,
+            &self.uniform_buffer,
+            &self.icon_storage_buffer,
+            &image_view,
+            &self.sampler,
+        );
+        self.texture_array = texture_array;
+    }
+}
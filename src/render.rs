@@ -1,11 +1,26 @@
 use crate::{
-    ALBUM_PALETTE_CACHE, ARTIST_DATA_CACHE, CantusApp, CondensedPlaylist, IMAGES_CACHE,
-    NUM_SWATCHES, PANEL_EXTENSION, PANEL_START, PLAYBACK_STATE, PlaylistId, Track, config::CONFIG,
+    ALBUM_PALETTE_CACHE, ARTIST_DATA_CACHE, AlbumId, CantusApp, CondensedPlaylist, IMAGE_FAILURES,
+    IMAGES_CACHE, MAX_IMAGE_ATTEMPTS, MAX_SWATCHES, PANEL_EXTENSION, PANEL_START, PLAYBACK_STATE,
+    PlaylistId, Track, TrackId,
+    config::{CONFIG, Config, DisplayMode, HistoryClick, PaletteAlgorithm},
+    interaction::{TrackHitbox, search_match},
+    layer_shell,
+    text_render::{TIME_LABEL_MIN_HEIGHT, TOOLTIP_PADDING},
 };
 use bytemuck::{Pod, Zeroable};
 use image::RgbaImage;
 use palette::IntoColor;
-use std::{collections::HashMap, ops::Range, time::Instant};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    ops::Range,
+    path::PathBuf,
+    sync::Once,
+    time::{Duration, Instant},
+};
+use tracing::warn;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Point {
@@ -35,6 +50,25 @@ impl Rect {
     pub fn contains(&self, p: Point) -> bool {
         p.x >= self.x0 && p.x <= self.x1 && p.y >= self.y0 && p.y <= self.y1
     }
+
+    pub const fn width(&self) -> f32 {
+        self.x1 - self.x0
+    }
+
+    pub fn center(&self) -> Point {
+        Point::new((self.x0 + self.x1) * 0.5, (self.y0 + self.y1) * 0.5)
+    }
+
+    /// The overlap of two rects, `None` unless it has some area.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let r = Self::new(
+            self.x0.max(other.x0),
+            self.y0.max(other.y0),
+            self.x1.min(other.x1),
+            self.y1.min(other.y1),
+        );
+        (r.x0 < r.x1 && r.y0 < r.y1).then_some(r)
+    }
 }
 
 #[repr(C)]
@@ -49,6 +83,13 @@ pub struct GlobalUniforms {
     expansion_time: f32,
     time: f32,
     scale_factor: f32,
+    vertical: f32, // 1 when the scene is rotated onto a vertical surface
+    mirrored: f32, // 1 when the timeline runs right to left
+    corner_radius: f32,
+    expansion_duration: f32, // Seconds the click ripple lasts
+    opacity: f32,            // Everything drawn is faded by this, lowered while idle
+    fade_edges: [f32; 2],    // Start and end x the tracks fade out towards
+    square_edge: f32,        // 1 to square the top corners of each track, -1 the bottom, 0 neither
     _padding: [f32; 3],
 }
 
@@ -59,6 +100,10 @@ pub struct PlayheadUniforms {
     bar_lerp: f32,
     play_lerp: f32,
     pause_lerp: f32,
+    /// Packed RGB of the line and of the play and pause icons, from the config.
+    playhead_color: u32,
+    icon_color: u32,
+    _padding: [u32; 2],
 }
 
 #[repr(C)]
@@ -74,9 +119,51 @@ pub struct Particle {
 #[derive(Copy, Clone, Debug, Default, Pod, Zeroable)]
 pub struct BackgroundPill {
     rect: [f32; 2], // pos x, width
-    colors: [u32; 4],
+    colors: [u32; MAX_SWATCHES],
     alpha: f32,
     image_index: i32,
+    swatch_count: u32,
+    /// How much of the pill has been played, from 0 to 1, only set on the current track.
+    progress: f32,
+    /// 1 while the art or palette is still on its way, the pill shimmers until it eases to 0.
+    loading: f32,
+    _padding: f32,
+}
+
+/// `image_index` marking an outline, its first colours hold the y extent and outline colour.
+const OUTLINE_PILL: i32 = -2;
+/// `image_index` marking a time tick, a faint line across the bar rather than a track background.
+const TICK_PILL: i32 = -3;
+
+impl BackgroundPill {
+    /// A time tick centred on `x`.
+    fn tick(x: f32, alpha: f32) -> Self {
+        Self {
+            rect: [x - 0.5, 1.0],
+            alpha,
+            image_index: TICK_PILL,
+            swatch_count: 1,
+            ..Self::default()
+        }
+    }
+
+    /// A translucent outline around any rect rather than a track background.
+    pub fn outline(rect: Rect, color: u32) -> Self {
+        let mut colors = [0; MAX_SWATCHES];
+        colors[0] = rect.y0.to_bits();
+        colors[1] = (rect.y1 - rect.y0).to_bits();
+        colors[2] = color;
+        Self {
+            rect: [rect.x0, rect.x1 - rect.x0],
+            colors,
+            alpha: 1.0,
+            image_index: OUTLINE_PILL,
+            swatch_count: 1,
+            progress: 0.0,
+            loading: 0.0,
+            _padding: 0.0,
+        }
+    }
 }
 
 #[repr(C)]
@@ -85,15 +172,12 @@ pub struct IconInstance {
     pub pos: [f32; 2],
     // Packed 2 u16s
     // First is alpha 0-1
-    // Second is 0 for dimmed icon 1 for bright icon, 2 for empty star, 3 for half star, 4 for filled star
+    // Second is 0 for a bright icon, 0.2 for a dimmed icon, 0.4 for a compact dot, and 0.5-1 for stars
     pub data: u32,
+    // Texture layer, or -1 shuffle, -2 repeat, -3 repeat track for the playback controls
     pub image_index: i32,
 }
 
-/// Spacing between tracks in ms
-const TRACK_SPACING_MS: f32 = 4000.0;
-/// Particles emitted per second when playback is active.
-const SPARK_EMISSION: f32 = 20.0;
 /// Horizontal velocity range applied at spawn.
 const SPARK_VELOCITY_X: Range<usize> = 40..60;
 /// Vertical velocity range applied at spawn.
@@ -101,14 +185,48 @@ const SPARK_VELOCITY_Y: f32 = 5.0;
 /// Lifetime range for individual particles, in seconds.
 const SPARK_LIFETIME: Range<f32> = 1.2..1.5;
 
-/// Duration for animation events
+/// Duration for animation events at the default `animation_speed`
 const ANIMATION_DURATION: f32 = 2.0;
+/// Frames the timeline speed is averaged over for particle emission.
+const SPEED_SAMPLES: usize = 8;
+/// Opacity of the track backgrounds while no device is playing.
+const NO_DEVICE_ALPHA: f32 = 0.4;
+/// How long the mouse rests on a track before its full title is shown.
+const TOOLTIP_DELAY: Duration = Duration::from_millis(500);
+/// Outline colour of the track the queue search matched.
+const SEARCH_HIGHLIGHT: [u8; 3] = [255, 255, 255];
+/// Minutes between time ticks, the first that leaves `MIN_TICK_SPACING` between them is used.
+const TICK_MINUTES: [u32; 7] = [1, 2, 5, 10, 15, 30, 60];
+/// Narrowest gap between time ticks in pixels, so their labels don't run together.
+const MIN_TICK_SPACING: f32 = 48.0;
+/// How long a pill takes to fade from the loading shimmer to its art once everything arrives.
+const LOADING_FADE: Duration = Duration::from_millis(400);
+/// Narrowest the timeline is laid out, when the bar leaves no room for it.
+const MIN_TIMELINE_WIDTH: f32 = 1.0;
 
 pub struct RenderState {
     pub last_update: Instant,
     pub track_offset: f32,
-    pub recent_speeds: [f32; 8],
+    pub recent_speeds: [f32; SPEED_SAMPLES],
     pub speed_idx: usize,
+    /// Nothing is playing on any device, the bar is drawn subdued.
+    pub no_active_device: bool,
+    /// Snap the timeline back to the playhead on the next frame instead of easing.
+    pub recenter: bool,
+    /// Frame rate shown by the debug overlay.
+    #[cfg(feature = "debug_overlay")]
+    pub debug_fps: f32,
+    /// The dropped history last accounted for, see `PlaybackState::trimmed_history`.
+    pub trimmed_history: (u64, usize),
+    /// How opaque the whole bar is drawn, eased towards `idle_opacity` while left alone.
+    pub opacity: f32,
+    /// When the pointer was last over the bar.
+    pub last_hovered: Instant,
+    /// Art urls of pills drawn loading, with when everything arrived (`None` while still pending)
+    /// and the `last_update` of the frame they were last drawn on.
+    pub loading: HashMap<String, (Option<Instant>, Instant)>,
+    /// How far the timeline is zoomed in, eased towards `hover_zoom` while hovered.
+    pub zoom: f32,
 }
 
 impl Default for RenderState {
@@ -116,14 +234,34 @@ impl Default for RenderState {
         Self {
             last_update: Instant::now(),
             track_offset: 0.0,
-            recent_speeds: [0.0; 8],
+            recent_speeds: [0.0; SPEED_SAMPLES],
             speed_idx: 0,
+            no_active_device: false,
+            recenter: false,
+            #[cfg(feature = "debug_overlay")]
+            debug_fps: 0.0,
+            trimmed_history: (0, 0),
+            opacity: 1.0,
+            last_hovered: Instant::now(),
+            loading: HashMap::new(),
+            zoom: 1.0,
         }
     }
 }
 
-pub struct TrackRender<'a> {
-    pub track: &'a Track,
+/// Text drawn over the bar for whatever the mouse rests on.
+struct Tooltip {
+    /// The track it belongs to, the pill takes its colours.
+    queue_index: usize,
+    x0: f32,
+    x1: f32,
+    /// A short label for a hovered icon, `None` shows the track's full title and artist.
+    label: Option<String>,
+}
+
+pub struct TrackRender {
+    /// Index of the track in the playback queue.
+    pub queue_index: usize,
     pub is_current: bool,
     pub seconds_until_start: f32,
     pub start_x: f32,
@@ -134,32 +272,79 @@ pub struct TrackRender<'a> {
 
 /// Build the scene for rendering.
 impl CantusApp {
+    /// Whether anything on screen is still moving, frames stop being requested once settled.
+    pub fn needs_redraw(&self) -> bool {
+        let time = self.start_time.elapsed().as_secs_f32();
+        let config = CONFIG.load();
+        Instant::now() < self.redraw_until
+            || self.interaction.last_toggle_playing.elapsed().as_secs_f32()
+                < ANIMATION_DURATION / config.animation_speed // Play/pause morph
+            || self.interaction.last_expansion.0.elapsed().as_secs_f32() < config.expansion_duration
+            || PLAYBACK_STATE.read().playing
+            || self.interaction.dragging
+            || self.interaction.mouse_pressure > 0.0
+            || self.render_state.opacity > config.idle_opacity // Still fading out, or back in
+            || !self.render_state.loading.is_empty()
+            || self.render_state.zoom > 1.0 // Zooming back out after the pointer left
+            || self.particles.iter().any(|p| p.end_time > time)
+            || self
+                .render_state
+                .recent_speeds
+                .iter()
+                .any(|speed| speed.abs() > 0.0001)
+    }
+
     pub fn create_scene(&mut self) {
         let now = Instant::now();
         let dt = now
             .duration_since(self.render_state.last_update)
             .as_secs_f32();
+        // Forget loading pills that scrolled out of view last frame
+        let last_frame = self.render_state.last_update;
+        self.render_state
+            .loading
+            .retain(|_, (_, drawn)| *drawn == last_frame);
         self.render_state.last_update = now;
 
         self.background_pills.clear();
-        let history_width = CONFIG.history_width;
-        let total_width = CONFIG.width - history_width - 16.0;
-        let total_height = CONFIG.height;
-        let timeline_duration_ms = CONFIG.timeline_future_minutes * 60_000.0;
-        let timeline_start_ms = -CONFIG.timeline_past_minutes * 60_000.0;
-
-        let px_per_ms = total_width / timeline_duration_ms;
-        let playhead_x = history_width - timeline_start_ms * px_per_ms;
+        let config = CONFIG.load();
+        let history_width = config.history_width();
+        let total_height = config.height;
+        // Zoom in around the playhead while hovered, everything below including the hitboxes
+        // works from the zoomed scale
+        move_towards(
+            &mut self.render_state.zoom,
+            if self.interaction.mouse_pressure > 0.0 {
+                config.hover_zoom
+            } else {
+                1.0
+            },
+            transition_speed((config.hover_zoom - 1.0).max(1.0) * 3.0 * dt),
+        );
+        let TimelineScale {
+            width: total_width,
+            start_ms: timeline_start_ms,
+            duration_ms: timeline_duration_ms,
+            px_per_ms,
+        } = timeline_scale(&config, self.render_state.zoom);
+        // Spacing between tracks in ms, so the gap stays the same size whatever the timeline scale
+        let track_spacing_ms = config.track_gap_px / px_per_ms;
+        let now_playing = config.mode == DisplayMode::NowPlaying;
 
         let playback_state = PLAYBACK_STATE.read();
         if playback_state.queue.is_empty() {
             return;
         }
 
+        // Icons are laid out along with their tracks, so the one hovered comes from the last frame
+        let icon_label = self.hovered_icon_label(&playback_state.playlists);
         self.interaction.icon_hitboxes.clear();
         self.interaction.track_hitboxes.clear();
 
-        let drag_offset_ms = if let Some(origin_pos) = self.interaction.drag_origin {
+        // Dragging scrubs along the track in now playing mode, rather than scrolling the timeline
+        let drag_offset_ms = if let Some(origin_pos) = self.interaction.drag_origin
+            && !now_playing
+        {
             (self.interaction.mouse_position.x - origin_pos.x) / px_per_ms
         } else {
             0.0
@@ -168,25 +353,43 @@ impl CantusApp {
             .queue_index
             .min(playback_state.queue.len() - 1);
 
+        // Lerp the progress based on when the data was last updated, get the start time of the current track
+        let playback_elapsed = playback_state.progress as f32
+            + if playback_state.playing {
+                playback_state.last_progress_update.elapsed().as_millis() as f32
+            } else {
+                0.0
+            };
+
+        // Now playing mode spans the current track across the bar, the playhead runs along it
+        let now_playing_span = (
+            config.track_gap_px,
+            config.width - config.track_gap_px * 2.0,
+        );
+        let playhead_x = if now_playing {
+            let (start_x, width) = now_playing_span;
+            let fraction = if self.interaction.dragging {
+                (self.interaction.mouse_position.x - start_x) / width
+            } else {
+                playback_elapsed / (playback_state.queue[cur_idx].duration_ms as f32).max(1.0)
+            };
+            start_x + width * fraction.clamp(0.0, 1.0)
+        } else {
+            history_width - timeline_start_ms * px_per_ms
+        };
+
         if playback_state.playing != self.interaction.playing {
             self.interaction.playing = playback_state.playing;
             self.interaction.last_expansion = (
                 Instant::now(),
-                Point::new(playhead_x, PANEL_START + CONFIG.height * 0.5),
+                Point::new(playhead_x, PANEL_START + config.height * 0.5),
             );
             self.interaction.last_toggle_playing = Instant::now();
         }
         if self.interaction.dragging {
             self.interaction.drag_track = None;
         }
-
-        // Lerp the progress based on when the data was last updated, get the start time of the current track
-        let playback_elapsed = playback_state.progress as f32
-            + if playback_state.playing {
-                playback_state.last_progress_update.elapsed().as_millis() as f32
-            } else {
-                0.0
-            };
+        self.render_state.no_active_device = playback_state.no_active_device;
 
         // Lerp track start based on the target and current start time
         let past_tracks_duration: f32 = playback_state
@@ -196,29 +399,94 @@ impl CantusApp {
             .map(|t| t.duration_ms as f32)
             .sum();
 
-        let mut current_ms = -playback_elapsed - past_tracks_duration + drag_offset_ms
-            - TRACK_SPACING_MS * cur_idx as f32;
+        // Dropped history moves every track along by the same amount, shift with it rather than scroll
+        let (trimmed_ms, trimmed_count) = playback_state.trimmed_history;
+        let (seen_ms, seen_count) = self.render_state.trimmed_history;
+        self.render_state.track_offset += trimmed_ms.saturating_sub(seen_ms) as f32
+            + track_spacing_ms * trimmed_count.saturating_sub(seen_count) as f32;
+        self.render_state.trimmed_history = playback_state.trimmed_history;
+
+        // While searching, a match out of view is scrolled to the middle of the timeline
+        let search_index = self
+            .interaction
+            .search
+            .as_deref()
+            .and_then(|query| search_match(&playback_state.queue, cur_idx, query));
+        let search_offset_ms = match search_index {
+            Some(index) if !now_playing && index != cur_idx => {
+                let tracks_before: f32 = playback_state
+                    .queue
+                    .iter()
+                    .take(index)
+                    .map(|t| t.duration_ms as f32)
+                    .sum();
+                let start = tracks_before - past_tracks_duration - playback_elapsed
+                    + track_spacing_ms * (index as f32 - cur_idx as f32);
+                let end = start + playback_state.queue[index].duration_ms as f32;
+                if start >= timeline_start_ms && end <= timeline_start_ms + timeline_duration_ms {
+                    0.0
+                } else {
+                    timeline_start_ms + timeline_duration_ms * 0.5 - (start + end) * 0.5
+                }
+            }
+            _ => 0.0,
+        };
+
+        let mut current_ms =
+            -playback_elapsed - past_tracks_duration + drag_offset_ms + search_offset_ms
+                - track_spacing_ms * cur_idx as f32;
         let diff = current_ms - self.render_state.track_offset;
         self.interaction.last_expansion.1.x += diff * px_per_ms * dt; // Offset the expansion so it moves with the tracks
-        if !self.interaction.dragging && diff.abs() > 200.0 {
-            current_ms = self.render_state.track_offset + diff * 3.5 * dt;
+        let recenter = std::mem::take(&mut self.render_state.recenter);
+        if !self.interaction.dragging
+            && !recenter
+            && !config.reduced_motion
+            && diff.abs() > config.scroll_snap_threshold_ms
+        {
+            current_ms =
+                self.render_state.track_offset + diff * (config.scroll_catch_up * dt).min(1.0);
         }
 
         // Add the new move speed to the array move_speeds, trim the previous ones
-        let frame_move_speed = (current_ms - self.render_state.track_offset) * dt;
+        // A recenter jumps straight there, so it shouldn't burst out particles
+        let frame_move_speed = if recenter {
+            0.0
+        } else {
+            (current_ms - self.render_state.track_offset) * dt
+        };
         self.render_state.track_offset = current_ms;
         let s_idx = self.render_state.speed_idx;
         self.render_state.recent_speeds[s_idx] = frame_move_speed;
-        self.render_state.speed_idx = (s_idx + 1) % 8;
-        let avg_speed = self.render_state.recent_speeds.iter().sum::<f32>() / 8.0;
+        self.render_state.speed_idx = (s_idx + 1) % SPEED_SAMPLES;
+        let avg_speed = self.render_state.recent_speeds.iter().sum::<f32>() / SPEED_SAMPLES as f32;
 
         // Iterate over the tracks within the timeline.
-        let mut track_renders = Vec::with_capacity(playback_state.queue.len());
+        let mut track_renders = std::mem::take(&mut self.track_renders);
+        track_renders.clear();
         let mut cur_ms = current_ms;
-        for track in &playback_state.queue {
+        let max_index = if now_playing {
+            0
+        } else {
+            cur_idx
+                .saturating_add(config.max_visible_tracks)
+                .max(search_index.map_or(0, |index| index + 1))
+        };
+        if now_playing {
+            let (start_x, width) = now_playing_span;
+            track_renders.push(TrackRender {
+                queue_index: cur_idx,
+                is_current: true,
+                seconds_until_start: playback_elapsed / 1000.0,
+                start_x,
+                width,
+                hitbox_range: (start_x, start_x + width),
+                art_only: false,
+            });
+        }
+        for (queue_index, track) in playback_state.queue.iter().enumerate().take(max_index) {
             let start = cur_ms;
             let end = start + track.duration_ms as f32;
-            cur_ms = end + TRACK_SPACING_MS;
+            cur_ms = end + track_spacing_ms;
             if start > timeline_start_ms + timeline_duration_ms {
                 break;
             }
@@ -226,7 +494,7 @@ impl CantusApp {
             let v_start = start.max(timeline_start_ms) * px_per_ms;
             let v_end = end.min(timeline_start_ms + timeline_duration_ms) * px_per_ms;
             track_renders.push(TrackRender {
-                track,
+                queue_index,
                 is_current: start <= 0.0 && end >= 0.0,
                 seconds_until_start: (start / 1000.0).abs(),
                 start_x: (v_start - timeline_start_ms * px_per_ms) + history_width,
@@ -242,7 +510,7 @@ impl CantusApp {
         // Sort out past tracks so they get a fixed width and stack
         let mut current_px = 0.0;
         let mut first_found = false;
-        let track_spacing = TRACK_SPACING_MS * px_per_ms;
+        let track_spacing = config.track_gap_px;
         for track_render in track_renders.iter_mut().rev() {
             // If the end of the track (minus album width) is before the cropping zone
             let distance_before =
@@ -269,10 +537,45 @@ impl CantusApp {
         // Screen uniforms
         self.global_uniforms.time = self.start_time.elapsed().as_secs_f32();
         self.global_uniforms.screen_size =
-            [CONFIG.width, CONFIG.height + PANEL_START + PANEL_EXTENSION];
-        self.global_uniforms.bar_height = [PANEL_START, CONFIG.height];
+            [config.width, config.height + PANEL_START + PANEL_EXTENSION];
+        self.global_uniforms.bar_height = [PANEL_START, config.height];
         self.global_uniforms.playhead_x = playhead_x;
         self.global_uniforms.scale_factor = self.scale_factor;
+        self.global_uniforms.vertical = if config.vertical() { 1.0 } else { 0.0 };
+        self.global_uniforms.mirrored = if config.mirrored() { 1.0 } else { 0.0 };
+        self.global_uniforms.corner_radius = config.corner_radius;
+        // Vertical bars turn the scene clockwise, so its top faces the right edge
+        self.global_uniforms.square_edge = if !config.square_edge_corners {
+            0.0
+        } else if matches!(config.layer_anchor.as_str(), "top" | "right") {
+            1.0
+        } else {
+            -1.0
+        };
+        self.global_uniforms.expansion_duration = config.expansion_duration;
+        // Tracks fade out towards the ends of the timeline, the now playing track fills the bar
+        self.global_uniforms.fade_edges = if now_playing {
+            [-config.width, config.width * 2.0]
+        } else {
+            [0.0, history_width + total_width]
+        };
+
+        // Fade to `idle_opacity` once the pointer has been away and nothing was changed for a while
+        if self.interaction.mouse_pressure > 0.0 {
+            self.render_state.last_hovered = now;
+        }
+        let idle_after = Duration::from_secs_f32(config.idle_opacity_delay);
+        let idle = self.render_state.last_hovered.elapsed() >= idle_after
+            && playback_state.last_interaction.elapsed() >= idle_after;
+        move_towards(
+            &mut self.render_state.opacity,
+            if idle { config.idle_opacity } else { 1.0 },
+            transition_speed(3.0 * dt),
+        );
+        self.global_uniforms.opacity = self.render_state.opacity;
+        if let Some(text_renderer) = &mut self.text_renderer {
+            text_renderer.set_opacity(self.render_state.opacity);
+        }
 
         // Mouse uniforms
         self.global_uniforms.mouse_pos = [
@@ -282,62 +585,138 @@ impl CantusApp {
         move_towards(
             &mut self.global_uniforms.mouse_pressure,
             self.interaction.mouse_pressure,
-            5.0 * dt,
+            transition_speed(5.0 * dt),
         );
 
         // Get expansion animation variables
         let (interaction_inst, interaction_point) = self.interaction.last_expansion;
         self.global_uniforms.expansion_xy = [interaction_point.x, interaction_point.y];
-        self.global_uniforms.expansion_time = interaction_inst
-            .duration_since(self.start_time)
-            .as_secs_f32();
+        self.global_uniforms.expansion_time = if config.reduced_motion {
+            // A ripple that finished before the first frame
+            -config.expansion_duration
+        } else {
+            interaction_inst
+                .duration_since(self.start_time)
+                .as_secs_f32()
+        };
 
-        // Render the tracks
+        // Render the tracks, leaving out the text of any the tooltip covers
+        let tooltip = match self.interaction.search.clone() {
+            Some(query) => self.search_tooltip(
+                &track_renders,
+                search_index.unwrap_or(cur_idx),
+                playhead_x,
+                &query,
+            ),
+            None => self.update_tooltip(&track_renders, &playback_state.queue, icon_label),
+        };
         let mut current_track = None;
         for track_render in &track_renders {
             if track_render.width <= 0.0 || track_render.start_x + track_render.width <= 0.0 {
                 continue;
             }
-            self.draw_track(track_render, playhead_x, &playback_state.playlists);
+            // Without room for a single cover the stacked history would only poke out of the edge
+            if track_render.art_only && history_width < total_height {
+                continue;
+            }
+            let track = &playback_state.queue[track_render.queue_index];
+            let covered = tooltip.as_ref().is_some_and(|tooltip| {
+                track_render.start_x < tooltip.x1
+                    && track_render.start_x + track_render.width > tooltip.x0
+            });
+            self.draw_track(
+                track_render,
+                track,
+                playhead_x,
+                &playback_state.playlists,
+                !covered,
+            );
             if playhead_x >= track_render.start_x
                 && playhead_x <= track_render.start_x + track_render.width
             {
-                current_track = Some(track_render.track);
+                current_track = Some(track);
             }
         }
+        if let Some(index) = search_index
+            && let Some(track_render) = track_renders
+                .iter()
+                .find(|r| r.queue_index == index && r.width > 0.0)
+        {
+            let rect = Rect::new(
+                track_render.start_x,
+                PANEL_START,
+                track_render.start_x + track_render.width,
+                PANEL_START + total_height,
+            );
+            let [r, g, b] = SEARCH_HIGHLIGHT;
+            self.background_pills.push(BackgroundPill::outline(
+                rect,
+                u32::from_le_bytes([r, g, b, 0]),
+            ));
+        }
+        self.track_renders = track_renders;
+        if config.show_time_ticks && !now_playing {
+            self.draw_time_ticks(playhead_x, px_per_ms, history_width + total_width);
+        }
+        if let Some(tooltip) = &tooltip {
+            self.draw_tooltip(&playback_state.queue[tooltip.queue_index], tooltip);
+        }
 
         // Draw the particles
+        let current_track = current_track.unwrap_or(&playback_state.queue[cur_idx]);
         self.render_playhead_particles(
             dt,
-            current_track.unwrap_or(&playback_state.queue[cur_idx]),
+            current_track,
             playhead_x,
             avg_speed,
             playback_state.volume,
         );
+        self.draw_playback_controls(
+            current_track,
+            playhead_x,
+            playback_state.shuffle,
+            playback_state.repeat,
+        );
+        if let Some(text_renderer) = &mut self.text_renderer
+            && total_height >= TIME_LABEL_MIN_HEIGHT
+        {
+            text_renderer.render_time_label(
+                &playback_state.queue[cur_idx],
+                playback_elapsed,
+                playhead_x,
+            );
+        }
+
+        #[cfg(feature = "debug_overlay")]
+        self.draw_debug_overlay(dt, cur_idx, playback_state.queue.len());
     }
 
     fn draw_track(
         &mut self,
         track_render: &TrackRender,
+        track: &Track,
         origin_x: f32,
         playlists: &HashMap<PlaylistId, CondensedPlaylist>,
+        show_text: bool,
     ) {
         let width = track_render.width;
-        let track = track_render.track;
         let start_x = track_render.start_x;
         let hitbox = Rect::new(
             start_x,
             PANEL_START,
             start_x + width,
-            PANEL_START + CONFIG.height,
+            PANEL_START + CONFIG.load().height,
         );
 
         // Add hitbox
         let (hit_start, hit_end) = track_render.hitbox_range;
         let full_width = hit_end - hit_start;
-        self.interaction
-            .track_hitboxes
-            .push((track.id, hitbox, track_render.hitbox_range));
+        self.interaction.track_hitboxes.push(TrackHitbox {
+            rect: hitbox,
+            track_id: track.id,
+            range: track_render.hitbox_range,
+            clickable: !track_render.art_only || CONFIG.load().history_click == HistoryClick::Seek,
+        });
         // If dragging, set the drag target to this track, and the position within the track
         if self.interaction.dragging && track_render.is_current {
             self.interaction.drag_track = Some((
@@ -347,38 +726,48 @@ impl CantusApp {
         }
 
         // --- BACKGROUND ---
-        let fade_alpha = if width < CONFIG.height {
-            ((width / CONFIG.height) - 0.9).max(0.0) * 10.0
+        let fade_alpha = if width < CONFIG.load().height {
+            ((width / CONFIG.load().height) - 0.9).max(0.0) * 10.0
         } else {
             1.0
         };
 
-        let image_index = track_render
-            .track
+        let image_index = track
             .album
             .image
             .as_deref()
-            .map(|path| self.get_image_index(path))
-            .unwrap_or_default();
+            .map_or(-1, |path| self.get_image_index(path));
+        let (colors, swatch_count) = pill_colors(track);
+        let loading = self.loading_amount(track, image_index);
+        let progress = if track_render.is_current {
+            ((origin_x - start_x) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let dim = if self.render_state.no_active_device {
+            NO_DEVICE_ALPHA
+        } else {
+            1.0
+        };
         self.background_pills.push(BackgroundPill {
             rect: [start_x, width],
-            colors: track
-                .album
-                .id
-                .and_then(|id| ALBUM_PALETTE_CACHE.get(&id))
-                .and_then(|data_ref| data_ref.as_ref().copied())
-                .unwrap_or_default(),
-            alpha: fade_alpha,
+            colors,
+            alpha: fade_alpha * dim,
             image_index,
+            swatch_count,
+            progress,
+            loading,
+            _padding: 0.0,
         });
 
         // --- TEXT ---
         if let Some(text_renderer) = &mut self.text_renderer
+            && show_text
             && !track_render.art_only
             && fade_alpha >= 1.0
-            && width > CONFIG.height
+            && width > CONFIG.load().height
         {
-            text_renderer.render(track_render);
+            text_renderer.render(track_render, track, self.global_uniforms.time);
         }
 
         // Expand the hitbox vertically so it includes the playlist buttons
@@ -391,6 +780,210 @@ impl CantusApp {
         }
     }
 
+    /// How strongly a track's pill shimmers, 1 until its art and palette are in, then easing out
+    /// over `LOADING_FADE`.
+    fn loading_amount(&mut self, track: &Track, image_index: i32) -> f32 {
+        let Some(url) = track.album.image.as_deref() else {
+            return 0.0;
+        };
+        let frame = self.render_state.last_update;
+        if assets_pending(track, image_index) {
+            match self.render_state.loading.get_mut(url) {
+                Some(entry) => *entry = (None, frame),
+                None => {
+                    self.render_state
+                        .loading
+                        .insert(url.to_owned(), (None, frame));
+                }
+            }
+            return 1.0;
+        }
+        let Some((arrived, drawn)) = self.render_state.loading.get_mut(url) else {
+            return 0.0;
+        };
+        *drawn = frame;
+        let fade =
+            1.0 - arrived.get_or_insert(frame).elapsed().as_secs_f32() / LOADING_FADE.as_secs_f32();
+        if fade <= 0.0 {
+            self.render_state.loading.remove(url);
+        }
+        fade.max(0.0)
+    }
+
+    /// Name the playlist or rating under the mouse, with the track the icon belongs to and its x.
+    fn hovered_icon_label(
+        &self,
+        playlists: &HashMap<PlaylistId, CondensedPlaylist>,
+    ) -> Option<(TrackId, f32, String)> {
+        if self.interaction.mouse_pressure <= 0.0 || self.interaction.dragging {
+            return None;
+        }
+        let mouse = self.interaction.mouse_position;
+        let hitbox = self
+            .interaction
+            .icon_hitboxes
+            .iter()
+            .find(|hitbox| hitbox.rect.contains(mouse))?;
+        let center_x = (hitbox.rect.x0 + hitbox.rect.x1) * 0.5;
+        let label = if let Some(playlist_id) = hitbox.playlist_id {
+            playlists.get(&playlist_id)?.name.clone()
+        } else {
+            // Name the rating playlist a click would add the track to
+            let config = CONFIG.load();
+            let fraction = (mouse.x - hitbox.rect.x0) / hitbox.rect.width();
+            let slot = config.rating_slot(hitbox.rating_index?, fraction);
+            config.rating_playlist_names.get(usize::from(slot))?.clone()
+        };
+        Some((hitbox.track_id, center_x, label))
+    }
+
+    /// Pick what the tooltip shows. A hovered icon is labelled straight away, otherwise once a track
+    /// has been hovered long enough and its text doesn't fit, it shows the full title, artist and
+    /// album.
+    fn update_tooltip(
+        &mut self,
+        track_renders: &[TrackRender],
+        queue: &[Track],
+        icon_label: Option<(TrackId, f32, String)>,
+    ) -> Option<Tooltip> {
+        let config = CONFIG.load();
+        let icon_tooltip = icon_label.and_then(|(track_id, center, label)| {
+            let queue_index = track_renders
+                .iter()
+                .map(|r| r.queue_index)
+                .find(|&index| queue[index].id == Some(track_id))?;
+            let width = self.text_renderer.as_mut()?.label_width(&label) + TOOLTIP_PADDING * 2.0;
+            let (x0, x1) = tooltip_span(width, center);
+            Some(Tooltip {
+                queue_index,
+                x0,
+                x1,
+                label: Some(label),
+            })
+        });
+        let mouse = self.interaction.mouse_position;
+        // Stacked history tracks overlap, the last drawn is on top
+        let hovered = track_renders
+            .iter()
+            .rev()
+            .filter(|_| self.interaction.mouse_pressure > 0.0 && !self.interaction.dragging)
+            .find(|r| {
+                r.width > 0.0
+                    && Rect::new(
+                        r.start_x,
+                        PANEL_START,
+                        r.start_x + r.width,
+                        PANEL_START + config.height,
+                    )
+                    .contains(mouse)
+            });
+        let Some(track_render) = hovered else {
+            self.interaction.hover_start = None;
+            return icon_tooltip;
+        };
+        let track = &queue[track_render.queue_index];
+        let since = match self.interaction.hover_start {
+            Some((id, since)) if id == track.id => since,
+            _ => {
+                let now = Instant::now();
+                self.interaction.hover_start = Some((track.id, now));
+                now
+            }
+        };
+        // Stacked art has no text at all, so it's labelled straight away
+        if icon_tooltip.is_some() || (!track_render.art_only && since.elapsed() < TOOLTIP_DELAY) {
+            return icon_tooltip;
+        }
+
+        let text_width = self.text_renderer.as_mut()?.tooltip_width(track);
+        let available_width = if track_render.art_only {
+            0.0
+        } else {
+            track_render.width - config.height - 20.0
+        };
+        if text_width <= available_width {
+            return None;
+        }
+        let (x0, x1) = tooltip_span(
+            text_width + TOOLTIP_PADDING * 2.0,
+            track_render.start_x + track_render.width * 0.5,
+        );
+        Some(Tooltip {
+            queue_index: track_render.queue_index,
+            x0,
+            x1,
+            label: None,
+        })
+    }
+
+    /// Show what's been typed into the queue search over the matched track, or the playhead when
+    /// nothing matches.
+    fn search_tooltip(
+        &mut self,
+        track_renders: &[TrackRender],
+        queue_index: usize,
+        playhead_x: f32,
+        query: &str,
+    ) -> Option<Tooltip> {
+        let label = format!("/{query}");
+        let width = self.text_renderer.as_mut()?.label_width(&label) + TOOLTIP_PADDING * 2.0;
+        let center = track_renders
+            .iter()
+            .find(|r| r.queue_index == queue_index && r.width > 0.0)
+            .map_or(playhead_x, |r| r.start_x + r.width * 0.5);
+        let (x0, x1) = tooltip_span(width, center);
+        Some(Tooltip {
+            queue_index,
+            x0,
+            x1,
+            label: Some(label),
+        })
+    }
+
+    /// Lines every few minutes ahead of the playhead, fading out as they reach the end of the
+    /// timeline at `end_x`.
+    fn draw_time_ticks(&mut self, playhead_x: f32, px_per_ms: f32, end_x: f32) {
+        let Some(step) = TICK_MINUTES
+            .into_iter()
+            .find(|&minutes| minutes as f32 * 60_000.0 * px_per_ms >= MIN_TICK_SPACING)
+        else {
+            return;
+        };
+        let labelled = CONFIG.load().height >= TIME_LABEL_MIN_HEIGHT;
+        for minutes in (step..).step_by(step as usize) {
+            let x = playhead_x + minutes as f32 * 60_000.0 * px_per_ms;
+            if x >= end_x {
+                break;
+            }
+            let alpha = ((end_x - x) / MIN_TICK_SPACING).min(1.0);
+            self.background_pills.push(BackgroundPill::tick(x, alpha));
+            if labelled && let Some(text_renderer) = &mut self.text_renderer {
+                text_renderer.render_tick_label(&format!("+{minutes}m"), x, alpha);
+            }
+        }
+    }
+
+    /// Draw the tooltip on a pill of its track's colours.
+    fn draw_tooltip(&mut self, track: &Track, tooltip: &Tooltip) {
+        let (colors, swatch_count) = pill_colors(track);
+        self.background_pills.push(BackgroundPill {
+            rect: [tooltip.x0, tooltip.x1 - tooltip.x0],
+            colors,
+            alpha: 1.0,
+            image_index: -1,
+            swatch_count,
+            progress: 0.0,
+            loading: 0.0,
+            _padding: 0.0,
+        });
+        if let Some(text_renderer) = &mut self.text_renderer {
+            match &tooltip.label {
+                Some(label) => text_renderer.render_label(track, label, tooltip.x0, tooltip.x1),
+                None => text_renderer.render_tooltip(track, tooltip.x0, tooltip.x1),
+            }
+        }
+    }
+
     fn render_playhead_particles(
         &mut self,
         dt: f32,
@@ -403,12 +996,17 @@ impl CantusApp {
             .album
             .id
             .and_then(|id| ALBUM_PALETTE_CACHE.get(&id))
-            .and_then(|data_ref| data_ref.as_ref().copied())
-            .unwrap_or_default();
+            .and_then(|data_ref| data_ref.clone())
+            .filter(|palette| !palette.is_empty())
+            .unwrap_or_else(|| vec![0]);
 
         // Emit new particles while playing
-        let mut emit_count = if avg_speed.abs() > 0.00001 {
-            self.particles_accumulator += dt * SPARK_EMISSION;
+        let config = CONFIG.load();
+        let mut emit_count = if config.show_particles()
+            && !self.render_state.no_active_device
+            && avg_speed.abs() > 0.00001
+        {
+            self.particles_accumulator += dt * config.particle_emission_rate;
             let count = self.particles_accumulator.floor() as u8;
             self.particles_accumulator -= f32::from(count);
             count
@@ -428,7 +1026,7 @@ impl CantusApp {
 
                 particle.spawn_pos = [
                     playhead_x,
-                    PANEL_START + CONFIG.height * (0.1 + (y_fraction * 0.85)), // Map to 0.1..0.95 range
+                    PANEL_START + config.height * (0.1 + (y_fraction * 0.85)), // Map to 0.1..0.95 range
                 ];
                 particle.spawn_vel = [
                     fastrand::usize(SPARK_VELOCITY_X) as f32 * horizontal_bias,
@@ -436,7 +1034,10 @@ impl CantusApp {
                 ];
                 let duration = lerpf32(fastrand::f32(), SPARK_LIFETIME.start, SPARK_LIFETIME.end);
                 let packed_duration = (duration * 100.0).min(255.0) as u8;
-                let base_color = palette[fastrand::usize(0..palette.len())];
+                let base_color = config.particle_color.map_or_else(
+                    || palette[fastrand::usize(0..palette.len())],
+                    |[r, g, b]| u32::from_le_bytes([r, g, b, 0]),
+                );
                 particle.color = (base_color & 0x00FF_FFFF) | (u32::from(packed_duration) << 24);
                 particle.end_time = time + duration;
                 emit_count -= 1;
@@ -446,19 +1047,27 @@ impl CantusApp {
         // Playhead
         let interaction = &mut self.interaction;
         self.playhead_info.volume = f32::from(volume.unwrap_or(100)) / 100.0;
-        let playbutton_hsize = CONFIG.height * 0.25;
-        let speed = 2.2 * dt;
+        let [r, g, b] = config.playhead_color;
+        self.playhead_info.playhead_color = u32::from_le_bytes([r, g, b, 0]);
+        let [r, g, b] = config.icon_color;
+        self.playhead_info.icon_color = u32::from_le_bytes([r, g, b, 0]);
+        let playbutton_hsize = config.height * 0.25;
+        let speed = transition_speed(2.2 * dt * config.animation_speed);
         interaction.play_hitbox = Rect::new(
             playhead_x - playbutton_hsize,
             PANEL_START,
             playhead_x + playbutton_hsize,
-            PANEL_START + CONFIG.height,
+            PANEL_START + config.height,
         );
         // Get playhead states
         let playhead_hovered = interaction.play_hitbox.contains(interaction.mouse_position)
             && interaction.mouse_pressure > 0.0;
-        let last_toggle =
-            interaction.last_toggle_playing.elapsed().as_secs_f32() / ANIMATION_DURATION;
+        let last_toggle = if config.reduced_motion {
+            1.0 // Skip the morph after toggling
+        } else {
+            interaction.last_toggle_playing.elapsed().as_secs_f32() * config.animation_speed
+                / ANIMATION_DURATION
+        };
 
         // Determine the intended state for the bar
         let bar_target =
@@ -497,6 +1106,56 @@ impl CantusApp {
     }
 }
 
+/// How the visible stretch of time maps onto the timeline's pixels.
+struct TimelineScale {
+    width: f32,
+    start_ms: f32,
+    duration_ms: f32,
+    px_per_ms: f32,
+}
+
+/// Scale the timeline at the given hover zoom.
+///
+/// The durations are validated with the config, but a bar narrower than the end padding leaves the
+/// timeline no width, which would make `px_per_ms` zero or negative and spread NaNs through the
+/// hitboxes. It's clamped to a sliver instead, logged whenever the bad width changes.
+fn timeline_scale(config: &Config, zoom: f32) -> TimelineScale {
+    static LAST_CLAMPED: Mutex<Option<u32>> = Mutex::new(None);
+    let width = config.timeline_width();
+    let width = if width >= MIN_TIMELINE_WIDTH {
+        width
+    } else {
+        let previous = LAST_CLAMPED.lock().replace(width.to_bits());
+        if previous != Some(width.to_bits()) {
+            warn!("Timeline of {width}px leaves nothing to draw, using {MIN_TIMELINE_WIDTH}px");
+        }
+        MIN_TIMELINE_WIDTH
+    };
+
+    let duration_ms = config.timeline_future_minutes * 60_000.0 / zoom;
+    TimelineScale {
+        width,
+        start_ms: -config.timeline_past_minutes * 60_000.0 / zoom,
+        duration_ms,
+        px_per_ms: width / duration_ms,
+    }
+}
+
+/// Where a tooltip `width` wide centred on `center` goes, kept on the bar.
+fn tooltip_span(width: f32, center: f32) -> (f32, f32) {
+    let x0 = (center - width * 0.5).clamp(0.0, (CONFIG.load().width - width).max(0.0));
+    (x0, x0 + width)
+}
+
+/// How far a `move_towards` transition steps this frame, reduced motion jumps straight there.
+fn transition_speed(speed: f32) -> f32 {
+    if CONFIG.load().reduced_motion {
+        f32::INFINITY
+    } else {
+        speed
+    }
+}
+
 fn move_towards(current: &mut f32, target: f32, speed: f32) {
     let delta = target - *current;
     if delta.abs() <= speed {
@@ -512,6 +1171,7 @@ pub fn lerpf32(t: f32, v0: f32, v1: f32) -> f32 {
 
 fn extract_lab_pixels(img: &RgbaImage) -> (Vec<palette::Lab>, bool) {
     let saturation_threshold = 30u8;
+    // Image bytes are gamma encoded, `Srgb` is too, so converting to Lab goes through linear light
     let srgb_to_lab = |p: &image::Rgba<u8>| {
         palette::FromColor::from_color(palette::Srgb::new(
             f32::from(p[0]) / 255.0,
@@ -537,27 +1197,259 @@ fn extract_lab_pixels(img: &RgbaImage) -> (Vec<palette::Lab>, bool) {
     }
 }
 
-fn do_kmeans(pixels: &[palette::Lab]) -> Vec<palette::Lab> {
-    kmeans_colors::get_kmeans_hamerly(NUM_SWATCHES, 20, 5.0, false, pixels, 0).centroids
+fn extract_palette(
+    pixels: &[palette::Lab],
+    algorithm: PaletteAlgorithm,
+    count: usize,
+) -> Vec<palette::Lab> {
+    let centroids = match algorithm {
+        PaletteAlgorithm::Kmeans => do_kmeans(pixels, count),
+        PaletteAlgorithm::MedianCut => do_median_cut(pixels, count),
+    };
+    distinct_swatches(centroids, pixels, count)
 }
 
+/// Centroids ordered from the biggest cluster down, so merging near duplicates keeps the main colour.
+fn do_kmeans(pixels: &[palette::Lab], count: usize) -> Vec<palette::Lab> {
+    let result = kmeans_colors::get_kmeans_hamerly(count, 20, 5.0, false, pixels, 0);
+    let mut weights = vec![0usize; result.centroids.len()];
+    for &index in &result.indices {
+        if let Some(weight) = weights.get_mut(usize::from(index)) {
+            *weight += 1;
+        }
+    }
+    let mut centroids = result
+        .centroids
+        .into_iter()
+        .zip(weights)
+        .collect::<Vec<_>>();
+    centroids.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
+    centroids
+        .into_iter()
+        .map(|(centroid, _)| centroid)
+        .collect()
+}
+
+fn lab_distance(a: &palette::Lab, b: &palette::Lab) -> f32 {
+    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+/// Drop swatches that look the same as an earlier one, then top back up to `count` with the
+/// pixels furthest from everything kept, so covers dominated by one colour still get a gradient.
+fn distinct_swatches(
+    centroids: Vec<palette::Lab>,
+    pixels: &[palette::Lab],
+    count: usize,
+) -> Vec<palette::Lab> {
+    let mut swatches: Vec<palette::Lab> = Vec::with_capacity(count);
+    for centroid in centroids {
+        if swatches
+            .iter()
+            .all(|kept| lab_distance(kept, &centroid) >= MIN_SWATCH_DISTANCE)
+        {
+            swatches.push(centroid);
+        }
+    }
+    while swatches.len() < count {
+        let furthest = pixels
+            .iter()
+            .map(|pixel| {
+                let distance = swatches
+                    .iter()
+                    .map(|kept| lab_distance(kept, pixel))
+                    .fold(f32::INFINITY, f32::min);
+                (pixel, distance)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match furthest {
+            Some((pixel, _)) => swatches.push(*pixel),
+            None => break,
+        }
+    }
+    swatches.truncate(count);
+    swatches
+}
+
+/// Repeatedly split the box with the widest channel at its median, then average each box.
+fn do_median_cut(pixels: &[palette::Lab], count: usize) -> Vec<palette::Lab> {
+    let channel = |c: &palette::Lab, axis: usize| match axis {
+        0 => c.l,
+        1 => c.a,
+        _ => c.b,
+    };
+    let widest_axis = |pixels: &[palette::Lab]| {
+        (0..3)
+            .map(|axis| {
+                let (min, max) = pixels.iter().fold((f32::MAX, f32::MIN), |(min, max), c| {
+                    let v = channel(c, axis);
+                    (min.min(v), max.max(v))
+                });
+                (axis, max - min)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or_default()
+    };
+
+    let mut boxes = vec![pixels.to_vec()];
+    while boxes.len() < count {
+        let Some((index, axis)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest_axis(b)))
+            .max_by(|a, b| a.1.1.total_cmp(&b.1.1))
+            .map(|(i, (axis, _))| (i, axis))
+        else {
+            break;
+        };
+        let mut split = boxes.swap_remove(index);
+        split.sort_unstable_by(|a, b| channel(a, axis).total_cmp(&channel(b, axis)));
+        let upper = split.split_off(split.len() / 2);
+        boxes.push(split);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .filter(|b| !b.is_empty())
+        .map(|b| {
+            let n = b.len() as f32;
+            let (l, a, bb) = b.iter().fold((0.0, 0.0, 0.0), |(l, a, bb), c| {
+                (l + c.l, a + c.a, bb + c.b)
+            });
+            palette::Lab::new(l / n, a / n, bb / n)
+        })
+        .collect()
+}
+
+/// Back to gamma encoded sRGB bytes. The surface is `Rgba8Unorm`, so shaders work with encoded
+/// colours and draw them as is, the same as the album art.
 fn convert_to_swatches(centroids: &[palette::Lab]) -> Vec<[u8; 3]> {
+    // Centroids can land outside the sRGB gamut, clamp rather than let the cast wrap or truncate
+    let to_byte = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
     centroids
         .iter()
         .map(|c: &palette::Lab| {
             let rgb: palette::Srgb = (*c).into_color();
-            [
-                (rgb.red * 255.0) as u8,
-                (rgb.green * 255.0) as u8,
-                (rgb.blue * 255.0) as u8,
-            ]
+            [to_byte(rgb.red), to_byte(rgb.green), to_byte(rgb.blue)]
         })
         .collect()
 }
 
+/// Lab distance under which two swatches read as the same colour.
+const MIN_SWATCH_DISTANCE: f32 = 12.0;
+
+/// Bump when the way palettes are computed changes, to throw away stale caches.
+const PALETTE_CACHE_VERSION: u32 = 3;
+/// Shortest time between writes of the palette cache while running, the rest is saved on exit.
+const PALETTE_PERSIST_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize)]
+struct PaletteCache {
+    version: u32,
+    swatches: usize,
+    #[serde(default)]
+    algorithm: PaletteAlgorithm,
+    palettes: HashMap<AlbumId, Vec<u32>>,
+}
+
+fn palette_cache_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("cantus").join("palettes.json"))
+}
+
+/// The album palette of a track packed for a background pill, with how many swatches it has.
+fn pill_colors(track: &Track) -> ([u32; MAX_SWATCHES], u32) {
+    let palette = track
+        .album
+        .id
+        .and_then(|id| ALBUM_PALETTE_CACHE.get(&id))
+        .and_then(|data_ref| data_ref.clone())
+        .unwrap_or_default();
+    let mut colors = [0; MAX_SWATCHES];
+    for (slot, color) in colors.iter_mut().zip(&palette) {
+        *slot = *color;
+    }
+    (colors, palette.len().clamp(1, MAX_SWATCHES) as u32)
+}
+
+/// Whether a track's art hasn't reached the GPU or its palette is still being worked out, tracks
+/// without art or whose download was given up on never count as pending.
+fn assets_pending(track: &Track, image_index: i32) -> bool {
+    let Some(url) = track.album.image.as_deref() else {
+        return false;
+    };
+    let given_up = IMAGE_FAILURES
+        .get(url)
+        .is_some_and(|failure| failure.0 >= MAX_IMAGE_ATTEMPTS);
+    let palette_ready = track.album.id.is_none_or(|id| {
+        ALBUM_PALETTE_CACHE
+            .get(&id)
+            .is_some_and(|palette| palette.is_some())
+    });
+    !given_up && (image_index < 0 || !palette_ready)
+}
+
+/// Fill `ALBUM_PALETTE_CACHE` with the palettes computed by previous runs.
+fn load_palette_cache() {
+    let Some(cache) = palette_cache_path()
+        .and_then(|path| fs::read(path).ok())
+        .and_then(|bytes| {
+            serde_json::from_slice::<PaletteCache>(&bytes)
+                .map_err(|e| warn!("Failed to parse palette cache: {e}"))
+                .ok()
+        })
+    else {
+        return;
+    };
+    if cache.version != PALETTE_CACHE_VERSION
+        || cache.swatches != CONFIG.load().swatch_count()
+        || cache.algorithm != CONFIG.load().palette_algorithm
+    {
+        return;
+    }
+    for (album_id, palette) in cache.palettes {
+        ALBUM_PALETTE_CACHE.entry(album_id).or_insert(Some(palette));
+    }
+}
+
+/// Write every computed palette to disk, placeholders for in progress albums are skipped.
+pub fn persist_palette_cache() {
+    let Some(path) = palette_cache_path() else {
+        return;
+    };
+    let cache = PaletteCache {
+        version: PALETTE_CACHE_VERSION,
+        swatches: CONFIG.load().swatch_count(),
+        algorithm: CONFIG.load().palette_algorithm,
+        palettes: ALBUM_PALETTE_CACHE
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .as_ref()
+                    .map(|palette| (*entry.key(), palette.clone()))
+            })
+            .collect(),
+    };
+    if let Ok(ser) = serde_json::to_vec(&cache) {
+        let _ = path.parent().map(fs::create_dir_all);
+        let _ = fs::write(path, ser);
+    }
+}
+
 /// Gathers the 4 primary colours for each album image.
 pub fn update_color_palettes() {
-    for track in &PLAYBACK_STATE.read().queue {
+    static LOAD_CACHE: Once = Once::new();
+    static LAST_PERSIST: Mutex<Option<Instant>> = Mutex::new(None);
+    LOAD_CACHE.call_once(load_palette_cache);
+
+    let (algorithm, swatch_count) = {
+        let config = CONFIG.load();
+        (config.palette_algorithm, config.swatch_count())
+    };
+    let mut computed = false;
+    let state = PLAYBACK_STATE.read();
+    for track in &state.queue {
         let album_id = track.album.id.unwrap_or_default();
         let artist_id = track.artist.id.unwrap_or_default();
         if ALBUM_PALETTE_CACHE.contains_key(&album_id) {
@@ -573,7 +1465,7 @@ pub fn update_color_palettes() {
         ALBUM_PALETTE_CACHE.insert(album_id, None);
 
         let (album_pixels, album_is_colourful) = extract_lab_pixels(album_image);
-        let mut result = do_kmeans(&album_pixels);
+        let mut result = extract_palette(&album_pixels, algorithm, swatch_count);
 
         if !album_is_colourful {
             let artist_img = ARTIST_DATA_CACHE
@@ -585,7 +1477,7 @@ pub fn update_color_palettes() {
             if let Some(img) = artist_img {
                 let (artist_pixels, artist_is_colourful) = extract_lab_pixels(&img);
                 if artist_is_colourful {
-                    result = do_kmeans(&artist_pixels);
+                    result = extract_palette(&artist_pixels, algorithm, swatch_count);
                 }
             } else {
                 ALBUM_PALETTE_CACHE.remove(&album_id);
@@ -593,13 +1485,73 @@ pub fn update_color_palettes() {
             }
         }
 
-        let primary_colors: [u32; 4] = convert_to_swatches(&result)
+        let primary_colors = convert_to_swatches(&result)
             .iter()
-            .take(4)
+            .take(swatch_count)
             .map(|c| u32::from_le_bytes([c[0], c[1], c[2], 255]))
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap_or_default();
+            .collect::<Vec<_>>();
         ALBUM_PALETTE_CACHE.insert(album_id, Some(primary_colors));
+        computed = true;
+    }
+    drop(state);
+
+    // Download threads finish together, so only write once in a while rather than per palette
+    if computed {
+        let mut last_persist = LAST_PERSIST.lock();
+        if last_persist.is_none_or(|last| last.elapsed() >= PALETTE_PERSIST_INTERVAL) {
+            *last_persist = Some(Instant::now());
+            drop(last_persist);
+            persist_palette_cache();
+        }
+    }
+    // New images and palettes come from background threads
+    layer_shell::request_redraw();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeline_without_future_is_rejected() {
+        let config = Config {
+            timeline_future_minutes: 0.0,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn timeline_without_width_falls_back_to_a_safe_scale() {
+        // Valid, but narrower than the padding after the timeline
+        let config = Config {
+            width: 10.0,
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        let scale = timeline_scale(&config, 1.0);
+        assert!(scale.px_per_ms.is_finite() && scale.px_per_ms > 0.0);
+        assert!(scale.width > 0.0);
+        assert!(scale.duration_ms > 0.0);
+    }
+
+    #[test]
+    fn flat_colour_round_trips_through_the_palette() {
+        for colour in [[200, 60, 30], [40, 120, 220], [128, 128, 128]] {
+            let image =
+                RgbaImage::from_pixel(8, 8, image::Rgba([colour[0], colour[1], colour[2], 255]));
+            let (pixels, _) = extract_lab_pixels(&image);
+            let swatches =
+                convert_to_swatches(&extract_palette(&pixels, PaletteAlgorithm::MedianCut, 4));
+            assert!(!swatches.is_empty());
+            for swatch in swatches {
+                for (channel, expected) in swatch.into_iter().zip(colour) {
+                    assert!(
+                        channel.abs_diff(expected) <= 2,
+                        "{swatch:?} drifted from {colour:?}"
+                    );
+                }
+            }
+        }
     }
 }
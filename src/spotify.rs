@@ -1,11 +1,15 @@
 use crate::{
-    ARTIST_DATA_CACHE, Artist, CondensedPlaylist, IMAGES_CACHE, PLAYBACK_STATE, PlaylistId, Track,
-    TrackId, config::CONFIG, deserialize_images, render::update_color_palettes,
-    update_playback_state,
+    ARTIST_DATA_CACHE, Album, Artist, CondensedPlaylist, IMAGE_FAILURES, IMAGES_CACHE,
+    PLAYBACK_STATE, PlaybackDevice, PlaylistId, RepeatMode, Track, TrackId,
+    backend::{PlaybackBackend, step_through_queue},
+    config::CONFIG,
+    deserialize_images, hashed_id, poll_interval, read_image_cache,
+    render::update_color_palettes,
+    should_fetch_image, store_fetched_image, update_playback_state,
 };
 use arrayvec::ArrayString;
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
 use sha2::{Digest, Sha256};
 use std::{
@@ -14,14 +18,20 @@ use std::{
     io::{BufRead, BufReader, Write},
     net::TcpListener,
     path::PathBuf,
-    sync::{Arc, LazyLock},
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, Ordering},
+    },
     thread::{sleep, spawn},
     time::{Duration, Instant},
 };
 use thiserror::Error;
 use time::{Duration as TimeDuration, OffsetDateTime};
 use tracing::{error, info, warn};
-use ureq::Agent;
+use ureq::{
+    Agent, Body,
+    http::{Response, StatusCode},
+};
 use url::Url;
 
 struct SpotifyState {
@@ -29,6 +39,7 @@ struct SpotifyState {
     context_updated: bool,
     last_grabbed_playback: Instant,
     last_grabbed_queue: Instant,
+    last_grabbed_devices: Instant,
 }
 
 static SPOTIFY_STATE: LazyLock<RwLock<SpotifyState>> = LazyLock::new(|| {
@@ -38,25 +49,55 @@ static SPOTIFY_STATE: LazyLock<RwLock<SpotifyState>> = LazyLock::new(|| {
         context_updated: false,
         last_grabbed_playback: one_min_ago,
         last_grabbed_queue: one_min_ago,
+        last_grabbed_devices: one_min_ago,
     })
 });
 
 // --- RSPOTIFY LOGIC ---
+const API_URL: &str = "https://api.spotify.com/v1";
+const ACCOUNTS_URL: &str = "https://accounts.spotify.com";
 const VERIFIER_BYTES: usize = 43;
 const REDIRECT_HOST: &str = "127.0.0.1";
 const REDIRECT_PORT: u16 = 7474;
+/// Attempts made for a request that keeps getting rate limited.
+const RATE_LIMIT_ATTEMPTS: u32 = 4;
+/// Longest `Retry-After` worth blocking on, anything above gives up straight away.
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(30);
+/// Minimum time between browser authorization prompts.
+const REAUTH_COOLDOWN: Duration = Duration::from_secs(60);
+/// Minimum time between refreshes of the available devices.
+const DEVICES_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A request couldn't reach its server, image downloads wait until an API call gets through again.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Note whether a request got a response, retrying failed images straight away once back online.
+fn set_online(online: bool) {
+    if online && OFFLINE.swap(false, Ordering::Relaxed) {
+        info!("Reconnected, retrying failed image downloads");
+        IMAGE_FAILURES.clear();
+    } else if !online {
+        OFFLINE.store(true, Ordering::Relaxed);
+    }
+}
 
 #[derive(Debug)]
 pub struct SpotifyClient {
     client_id: String,
     cache_path: PathBuf,
+    scopes: HashSet<String>,
     token: RwLock<Token>,
+    last_prompt: Mutex<Option<Instant>>,
     http: Agent,
+    /// Web API requests are made relative to this, without a trailing slash.
+    api_url: String,
+    /// Where refresh tokens are exchanged, without a trailing slash.
+    accounts_url: String,
 }
 
 #[derive(Deserialize)]
 struct PartialTrack {
-    id: TrackId,
+    id: Option<TrackId>,
 }
 
 #[derive(Deserialize)]
@@ -72,6 +113,8 @@ struct Playlist {
 
 #[derive(Deserialize)]
 struct PlaylistItem {
+    #[serde(default)]
+    is_local: bool,
     track: PartialTrack,
 }
 
@@ -87,13 +130,84 @@ struct CurrentPlaybackContext {
     #[serde(default)]
     progress_ms: u32,
     is_playing: bool,
-    item: Option<Track>,
+    #[serde(default)]
+    shuffle_state: bool,
+    #[serde(default)]
+    repeat_state: RepeatMode,
+    item: Option<PlayableItem>,
 }
 
 #[derive(Deserialize)]
 struct CurrentUserQueue {
-    currently_playing: Option<Track>,
-    queue: Vec<Track>,
+    currently_playing: Option<PlayableItem>,
+    queue: Vec<PlayableItem>,
+}
+
+/// The player and queue hold podcast episodes as well as tracks.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum PlayableItem {
+    Track(Track),
+    Episode(Episode),
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Deserialize)]
+struct Episode {
+    id: Option<TrackId>,
+    name: String,
+    #[serde(default, deserialize_with = "deserialize_images", rename = "images")]
+    image: Option<String>,
+    show: Show,
+    duration_ms: u32,
+    #[serde(default)]
+    uri: String,
+}
+
+#[derive(Deserialize)]
+struct Show {
+    name: String,
+    #[serde(default, deserialize_with = "deserialize_images", rename = "images")]
+    image: Option<String>,
+}
+
+impl PlayableItem {
+    /// Episodes are shown as a track by the show, using the episode art as the album.
+    fn into_track(self) -> Option<Track> {
+        match self {
+            // Local files have no id, generate one so they can still be seeked to
+            Self::Track(mut track) => {
+                if track.id.is_none() {
+                    track.id = Some(hashed_id(&format!(
+                        "{}{}{}",
+                        track.name, track.artist.name, track.duration_ms
+                    )));
+                }
+                Some(track)
+            }
+            Self::Episode(episode) => Some(Track {
+                id: episode.id,
+                name: episode.name,
+                album: Album {
+                    // Palettes are keyed by album, each episode has its own art
+                    id: episode.id,
+                    name: episode.show.name.clone(),
+                    image: episode.image.or_else(|| episode.show.image.clone()),
+                },
+                artist: Artist {
+                    // Shows can't be looked up through the artists endpoint
+                    id: None,
+                    name: episode.show.name,
+                    image: episode.show.image,
+                },
+                duration_ms: episode.duration_ms,
+                is_local: false,
+                uri: episode.uri,
+            }),
+            Self::Unknown => None,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -101,6 +215,11 @@ struct Device {
     volume_percent: Option<u32>,
 }
 
+#[derive(Deserialize)]
+struct Devices {
+    devices: Vec<PlaybackDevice>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 struct Token {
     #[serde(rename = "access_token")]
@@ -143,40 +262,42 @@ fn read_token_cache(
     }
 }
 
+/// Run the authorization code flow in the browser, waiting for the redirect on `REDIRECT_PORT`.
 fn prompt_for_token(
-    url: &str,
-    cache_path: &PathBuf,
-    scopes: &HashSet<String>,
     client_id: &str,
-    verifier: &str,
+    scopes: &HashSet<String>,
     http: &Agent,
-) -> Token {
-    if let Ok(Some(cached)) = read_token_cache(true, cache_path, scopes) {
-        return cached;
-    }
-    match webbrowser::open(url) {
+    accounts_url: &str,
+) -> ClientResult<Token> {
+    let state = generate_random_string(
+        16,
+        b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
+    );
+    let (verifier, url) = get_authorize_url(client_id, scopes, &state, accounts_url)?;
+    match webbrowser::open(&url) {
         Ok(()) => println!("Opened {url} in your browser."),
         Err(err) => eprintln!(
             "Error when trying to open an URL in your browser: {err:?}. Please navigate here manually: {url}"
         ),
     }
 
-    let listener = TcpListener::bind((REDIRECT_HOST, REDIRECT_PORT)).unwrap();
-    let mut stream = listener.incoming().flatten().next().unwrap();
+    let listener = TcpListener::bind((REDIRECT_HOST, REDIRECT_PORT))?;
+    let mut stream = listener
+        .incoming()
+        .flatten()
+        .next()
+        .ok_or(ClientError::InvalidToken)?;
     let mut request_line = String::new();
-    BufReader::new(&stream)
-        .read_line(&mut request_line)
-        .unwrap();
+    BufReader::new(&stream).read_line(&mut request_line)?;
 
     let code = Url::parse(&format!(
         "http://{REDIRECT_HOST}:{REDIRECT_PORT}/callback{}",
-        request_line.split_whitespace().nth(1).unwrap()
-    ))
-    .unwrap()
+        request_line.split_whitespace().nth(1).unwrap_or_default()
+    ))?
     .query_pairs()
     .find(|(key, _)| key == "code")
     .map(|(_, value)| value.into_owned())
-    .unwrap();
+    .ok_or(ClientError::InvalidToken)?;
 
     let message = "Cantus connected successfully, this tab can be closed.";
     write!(
@@ -184,11 +305,10 @@ fn prompt_for_token(
         "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
         message.len(),
         message
-    )
-    .unwrap();
+    )?;
 
     let response = http
-        .post("https://accounts.spotify.com/api/token")
+        .post(format!("{accounts_url}/api/token"))
         .send_form([
             ("grant_type", "authorization_code"),
             ("code", &code),
@@ -197,139 +317,280 @@ fn prompt_for_token(
                 &format!("http://{REDIRECT_HOST}:{REDIRECT_PORT}/callback"),
             ),
             ("client_id", client_id),
-            ("code_verifier", verifier),
+            ("code_verifier", &verifier),
         ])
-        .unwrap()
+        .map_err(ClientError::from)
+        .and_then(error_for_status)?
         .into_body()
-        .read_to_string()
-        .unwrap();
-    let mut token = serde_json::from_str::<Token>(&response).unwrap();
+        .read_to_string()?;
+    let mut token = serde_json::from_str::<Token>(&response)?;
     token.set_expiration();
-    token
+    Ok(token)
 }
 
 impl SpotifyClient {
     fn auth_headers(&self) -> ClientResult<String> {
         if self.token.read().is_expired() {
-            let token = self.refetch_token()?;
+            let token = match self.refetch_token() {
+                // The refresh token was revoked or has expired, only the browser flow can recover
+                Err(ClientError::InvalidToken | ClientError::ParseJson(_)) => self.reauthorize()?,
+                result => result?,
+            };
             *self.token.write() = token;
             self.write_token_cache();
         }
         Ok(format!("Bearer {}", self.token.read().access))
     }
 
+    /// Prompt for authorization again, at most once per `REAUTH_COOLDOWN`.
+    fn reauthorize(&self) -> ClientResult<Token> {
+        // Other threads fail fast while a prompt is already open
+        let Some(mut last_prompt) = self.last_prompt.try_lock() else {
+            return Err(ClientError::InvalidToken);
+        };
+        if last_prompt.is_some_and(|instant| instant.elapsed() < REAUTH_COOLDOWN) {
+            return Err(ClientError::InvalidToken);
+        }
+        *last_prompt = Some(Instant::now());
+        warn!("Spotify rejected the refresh token, reauthorizing in the browser");
+        let token = prompt_for_token(
+            &self.client_id,
+            &self.scopes,
+            &self.http,
+            &self.accounts_url,
+        );
+        drop(last_prompt);
+        token
+    }
+
+    /// Send a request built by `send`, sleeping out `429` responses before trying again.
+    ///
+    /// Uses the `Retry-After` header when present, otherwise backs off exponentially.
+    fn call_with_retry(
+        &self,
+        send: impl Fn(String) -> Result<Response<Body>, ureq::Error>,
+    ) -> ClientResult<Response<Body>> {
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            let response = send(self.auth_headers()?).inspect_err(|_| set_online(false))?;
+            set_online(true);
+            if response.status() != StatusCode::TOO_MANY_REQUESTS || attempt >= RATE_LIMIT_ATTEMPTS
+            {
+                return error_for_status(response);
+            }
+            let wait = response
+                .headers()
+                .get("retry-after")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok())
+                .map_or(backoff, Duration::from_secs);
+            if wait > RATE_LIMIT_MAX_WAIT {
+                return Err(ClientError::Http(format!(
+                    "rate limited, retry after {}s",
+                    wait.as_secs()
+                )));
+            }
+            warn!(
+                "Rate limited by Spotify, retrying in {:.1}s (attempt {attempt}/{RATE_LIMIT_ATTEMPTS})",
+                wait.as_secs_f32()
+            );
+            sleep(wait);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
     pub fn api_get(&self, url: &str) -> ClientResult<String> {
-        let response = self
-            .http
-            .get(format!("https://api.spotify.com/v1/{url}"))
-            .header("authorization", self.auth_headers()?)
-            .call()?;
+        let response = self.call_with_retry(|auth| {
+            self.http
+                .get(format!("{}/{url}", self.api_url))
+                .header("authorization", auth)
+                .call()
+        })?;
         Ok(response.into_body().read_to_string()?)
     }
 
     pub fn api_get_payload(&self, url: &str, payload: &[(&str, &str)]) -> ClientResult<String> {
-        let response = self
-            .http
-            .get(format!("https://api.spotify.com/v1/{url}"))
-            .header("authorization", self.auth_headers()?)
-            .query_pairs(payload.iter().copied())
-            .call()?;
+        let response = self.call_with_retry(|auth| {
+            self.http
+                .get(format!("{}/{url}", self.api_url))
+                .header("authorization", auth)
+                .query_pairs(payload.iter().copied())
+                .call()
+        })?;
         Ok(response.into_body().read_to_string()?)
     }
 
     pub fn api_post(&self, url: &str) -> ClientResult<()> {
-        self.http
-            .post(format!("https://api.spotify.com/v1/{url}"))
-            .header("authorization", self.auth_headers()?)
-            .send_empty()?;
+        self.call_with_retry(|auth| {
+            self.http
+                .post(format!("{}/{url}", self.api_url))
+                .header("authorization", auth)
+                .send_empty()
+        })?;
         Ok(())
     }
 
     pub fn api_post_payload(&self, url: &str, payload: &str) -> ClientResult<()> {
-        self.http
-            .post(format!("https://api.spotify.com/v1/{url}"))
-            .header("Content-Type", "application/json; charset=utf-8")
-            .header("authorization", self.auth_headers()?)
-            .send(payload)?;
+        self.call_with_retry(|auth| {
+            self.http
+                .post(format!("{}/{url}", self.api_url))
+                .header("Content-Type", "application/json; charset=utf-8")
+                .header("authorization", auth)
+                .send(payload)
+        })?;
+        Ok(())
+    }
+
+    pub fn api_put_payload(&self, url: &str, payload: &str) -> ClientResult<()> {
+        self.call_with_retry(|auth| {
+            self.http
+                .put(format!("{}/{url}", self.api_url))
+                .header("Content-Type", "application/json; charset=utf-8")
+                .header("authorization", auth)
+                .send(payload)
+        })?;
         Ok(())
     }
 
     pub fn api_put(&self, url: &str) -> ClientResult<()> {
-        self.http
-            .put(format!("https://api.spotify.com/v1/{url}"))
-            .header("authorization", self.auth_headers()?)
-            .send_empty()?;
+        self.call_with_retry(|auth| {
+            self.http
+                .put(format!("{}/{url}", self.api_url))
+                .header("authorization", auth)
+                .send_empty()
+        })?;
         Ok(())
     }
 
     pub fn api_delete(&self, url: &str) -> ClientResult<()> {
-        self.http
-            .delete(format!("https://api.spotify.com/v1/{url}"))
-            .header("authorization", self.auth_headers()?)
-            .call()?;
+        self.call_with_retry(|auth| {
+            self.http
+                .delete(format!("{}/{url}", self.api_url))
+                .header("authorization", auth)
+                .call()
+        })?;
         Ok(())
     }
 
     pub fn api_delete_payload(&self, url: &str, payload: &str) -> ClientResult<()> {
-        self.http
-            .delete(format!("https://api.spotify.com/v1/{url}"))
-            .header("Content-Type", "application/json; charset=utf-8")
-            .header("authorization", self.auth_headers()?)
-            .force_send_body()
-            .send(payload)?;
+        self.call_with_retry(|auth| {
+            self.http
+                .delete(format!("{}/{url}", self.api_url))
+                .header("Content-Type", "application/json; charset=utf-8")
+                .header("authorization", auth)
+                .force_send_body()
+                .send(payload)
+        })?;
         Ok(())
     }
 
+    /// Download a file from outside the Web API, such as album art, without authorization.
+    pub fn download(&self, url: &str) -> ClientResult<Vec<u8>> {
+        let response = self
+            .http
+            .get(url)
+            .call()
+            .inspect_err(|_| set_online(false))?;
+        set_online(true);
+        Ok(error_for_status(response)?.into_body().read_to_vec()?)
+    }
+
     fn write_token_cache(&self) {
-        fs::write(
-            &self.cache_path,
-            serde_json::to_string(&*self.token.read()).unwrap(),
-        )
-        .unwrap();
+        let result = serde_json::to_string(&*self.token.read())
+            .map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&self.cache_path, json).map_err(|e| e.to_string()));
+        if let Err(err) = result {
+            warn!(
+                "Failed to write token cache {}: {err}",
+                self.cache_path.display()
+            );
+        }
     }
 
     fn refetch_token(&self) -> ClientResult<Token> {
-        let Some(refresh_token) = &self.token.read().refresh else {
+        let Some(refresh_token) = self.token.read().refresh.clone() else {
             return Err(ClientError::InvalidToken);
         };
         let response = self
             .http
-            .post("https://accounts.spotify.com/api/token")
+            .post(format!("{}/api/token", self.accounts_url))
             .send_form([
                 ("grant_type", "refresh_token"),
-                ("refresh_token", refresh_token),
+                ("refresh_token", &refresh_token),
                 ("client_id", &self.client_id),
-            ])?
-            .into_body()
-            .read_to_string()?;
+            ])?;
+        // Spotify answers a revoked refresh token with `400 invalid_grant`
+        if matches!(
+            response.status(),
+            StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED
+        ) {
+            return Err(ClientError::InvalidToken);
+        }
+        let response = error_for_status(response)?.into_body().read_to_string()?;
         let mut token = serde_json::from_str::<Token>(&response)?;
         token.set_expiration();
+        // The refresh token is only sent back when it has been rotated
+        token.refresh.get_or_insert(refresh_token);
         Ok(token)
     }
 
-    pub fn new(client_id: String, scopes: &HashSet<String>, cache_path: PathBuf) -> Self {
-        let state = generate_random_string(
-            16,
-            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789",
-        );
-        let (verifier, url) = get_authorize_url(&client_id, scopes, &state).unwrap();
-        let agent = Agent::new_with_defaults();
-        let token = prompt_for_token(&url, &cache_path, scopes, &client_id, &verifier, &agent);
-        let spotify_client = Self {
+    pub fn new(client_id: String, scopes: HashSet<String>, cache_path: PathBuf) -> Self {
+        // Status codes are checked by hand so rate limit headers can be read
+        let agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .new_agent();
+        let token = match read_token_cache(true, &cache_path, &scopes) {
+            Ok(Some(cached)) => cached,
+            _ => prompt_for_token(&client_id, &scopes, &agent, ACCOUNTS_URL)
+                .expect("Failed to authorize with Spotify"),
+        };
+        let spotify_client = Self::with_agent(
             client_id,
+            scopes,
             cache_path,
-            token: RwLock::new(token),
-            http: agent,
-        };
+            token,
+            agent,
+            API_URL,
+            ACCOUNTS_URL,
+        );
         spotify_client.write_token_cache();
         spotify_client
     }
+
+    /// Build a client around an existing token and agent, without authorizing or touching the cache.
+    ///
+    /// The URLs let requests go somewhere other than Spotify, such as a local mock server. The agent
+    /// should have `http_status_as_error` turned off so rate limits can be retried.
+    fn with_agent(
+        client_id: String,
+        scopes: HashSet<String>,
+        cache_path: PathBuf,
+        token: Token,
+        http: Agent,
+        api_url: &str,
+        accounts_url: &str,
+    ) -> Self {
+        Self {
+            client_id,
+            cache_path,
+            scopes,
+            token: RwLock::new(token),
+            last_prompt: Mutex::new(None),
+            http,
+            api_url: api_url.trim_end_matches('/').to_owned(),
+            accounts_url: accounts_url.trim_end_matches('/').to_owned(),
+        }
+    }
 }
 
 fn get_authorize_url(
     client_id: &str,
     scopes: &HashSet<String>,
     state: &str,
+    accounts_url: &str,
 ) -> ClientResult<(String, String)> {
     let verifier = generate_random_string(
         VERIFIER_BYTES,
@@ -341,7 +602,7 @@ fn get_authorize_url(
     let challenge = URL_SAFE_NO_PAD.encode(hasher.finalize());
 
     let parsed = Url::parse_with_params(
-        "https://accounts.spotify.com/authorize",
+        &format!("{accounts_url}/authorize"),
         &[
             ("client_id", client_id),
             ("response_type", "code"),
@@ -395,6 +656,17 @@ impl From<ureq::Error> for ClientError {
 
 type ClientResult<T> = Result<T, ClientError>;
 
+fn error_for_status(response: Response<Body>) -> ClientResult<Response<Body>> {
+    if response.status().is_success() {
+        Ok(response)
+    } else {
+        Err(ClientError::Http(format!(
+            "http status: {}",
+            response.status().as_u16()
+        )))
+    }
+}
+
 fn deserialize_scopes<'de, D>(d: D) -> Result<HashSet<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -439,10 +711,6 @@ struct Page<T: DeserializeOwned> {
 }
 
 // --- SPOTIFY LOGIC ---
-const RATING_PLAYLISTS: [&str; 10] = [
-    "0.5", "1.0", "1.5", "2.0", "2.5", "3.0", "3.5", "4.0", "4.5", "5.0",
-];
-
 pub static SPOTIFY_CLIENT: LazyLock<SpotifyClient> = LazyLock::new(|| {
     let scopes = [
         "user-read-playback-state",
@@ -460,10 +728,10 @@ pub static SPOTIFY_CLIENT: LazyLock<SpotifyClient> = LazyLock::new(|| {
     .collect();
 
     SpotifyClient::new(
-        CONFIG.spotify_client_id.clone().expect(
+        CONFIG.load().spotify_client_id.clone().expect(
             "Spotify client ID not set, set it in the config file under key `spotify_client_id`.",
         ),
-        &scopes,
+        scopes,
         dirs::config_dir()
             .unwrap()
             .join("cantus")
@@ -471,6 +739,184 @@ pub static SPOTIFY_CLIENT: LazyLock<SpotifyClient> = LazyLock::new(|| {
     )
 });
 
+/// Playback controls routed through the Spotify Web API.
+pub struct SpotifyBackend;
+
+impl PlaybackBackend for SpotifyBackend {
+    fn play(&self) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/start-a-users-playback
+        if let Err(err) = SPOTIFY_CLIENT.api_put("me/player/play") {
+            error!("Failed to play playback: {err}");
+        }
+    }
+
+    fn pause(&self) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/pause-a-users-playback
+        if let Err(err) = SPOTIFY_CLIENT.api_put("me/player/pause") {
+            error!("Failed to pause playback: {err}");
+        }
+    }
+
+    fn seek_ms(&self, ms: u32) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/seek-to-position-in-currently-playing-track
+        if let Err(err) = SPOTIFY_CLIENT.api_put(&format!("me/player/seek?position_ms={ms}")) {
+            error!("Failed to seek track: {err}");
+        }
+    }
+
+    fn set_volume(&self, pct: u8) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/set-volume-for-users-playback
+        if let Err(err) = SPOTIFY_CLIENT.api_put(&format!("me/player/volume?volume_percent={pct}"))
+        {
+            error!("Failed to set volume: {err}");
+        }
+    }
+
+    fn skip_next(&self) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/skip-users-playback-to-next-track
+        if let Err(err) = SPOTIFY_CLIENT.api_post("me/player/next") {
+            error!("Failed to skip to next track: {err}");
+        }
+    }
+
+    fn skip_prev(&self) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/skip-users-playback-to-previous-track
+        if let Err(err) = SPOTIFY_CLIENT.api_post("me/player/previous") {
+            error!("Failed to skip to previous track: {err}");
+        }
+    }
+
+    fn set_shuffle(&self, shuffle: bool) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/toggle-shuffle-for-users-playback
+        if let Err(err) = SPOTIFY_CLIENT.api_put(&format!("me/player/shuffle?state={shuffle}")) {
+            error!("Failed to set shuffle: {err}");
+        }
+    }
+
+    fn set_repeat(&self, repeat: RepeatMode) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/set-repeat-mode-on-users-playback
+        let state = match repeat {
+            RepeatMode::Off => "off",
+            RepeatMode::Track => "track",
+            RepeatMode::Context => "context",
+        };
+        if let Err(err) = SPOTIFY_CLIENT.api_put(&format!("me/player/repeat?state={state}")) {
+            error!("Failed to set repeat mode: {err}");
+        }
+    }
+
+    fn jump_to_track(&self, track_id: &TrackId, offset: isize) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/start-a-users-playback
+        // Episodes have their own kind of URI, and local files can't be started through the API
+        let track_uri = PLAYBACK_STATE
+            .read()
+            .queue
+            .iter()
+            .find(|track| track.id == Some(*track_id) && !track.is_local)
+            .map(|track| track.uri.clone())
+            .filter(|uri| !uri.is_empty());
+        let Some(track_uri) = track_uri else {
+            step_through_queue(self, offset);
+            return;
+        };
+        let context = SPOTIFY_STATE.read().current_context.clone();
+        // Start the track within the current context so the rest of it keeps playing afterwards,
+        // tracks queued by hand aren't part of the context so fall back to playing just the track
+        if let Some(context) = context {
+            let payload = serde_json::json!({
+                "context_uri": context,
+                "offset": { "uri": track_uri },
+            });
+            match SPOTIFY_CLIENT.api_put_payload("me/player/play", &payload.to_string()) {
+                Ok(()) => return,
+                Err(err) => warn!("Failed to play track {track_id} within its context: {err}"),
+            }
+        }
+        let payload = serde_json::json!({ "uris": [track_uri] });
+        if let Err(err) = SPOTIFY_CLIENT.api_put_payload("me/player/play", &payload.to_string()) {
+            error!("Failed to play track {track_id}: {err}");
+        }
+    }
+
+    fn add_to_playlist(&self, track_id: &TrackId, playlist_id: &PlaylistId) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/add-tracks-to-playlist
+        if let Err(err) = SPOTIFY_CLIENT.api_post_payload(
+            &format!("playlists/{playlist_id}/tracks"),
+            &format!(r#"{{"uris": ["spotify:track:{track_id}"]}}"#),
+        ) {
+            error!("Failed to add track {track_id} to playlist {playlist_id}: {err}");
+        }
+    }
+
+    fn remove_from_playlist(&self, track_id: &TrackId, playlist_id: &PlaylistId) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/remove-tracks-playlist
+        if let Err(err) = SPOTIFY_CLIENT.api_delete_payload(
+            &format!("playlists/{playlist_id}/tracks"),
+            &format!(r#"{{"tracks": [ {{"uri": "spotify:track:{track_id}"}} ]}}"#),
+        ) {
+            error!("Failed to remove track {track_id} from playlist {playlist_id}: {err}");
+        }
+    }
+
+    fn is_liked(&self, track_id: &TrackId) -> Option<bool> {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/check-users-saved-tracks
+        SPOTIFY_CLIENT
+            .api_get(&format!("me/tracks/contains/?ids={track_id}"))
+            .map(|liked| liked == "[true]")
+            .map_err(|err| error!("Failed to check if track {track_id} is already liked: {err}"))
+            .ok()
+    }
+
+    fn set_liked(&self, track_id: &TrackId, liked: bool) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/save-tracks-user
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/remove-tracks-user
+        let result = if liked {
+            SPOTIFY_CLIENT.api_put(&format!("me/tracks/?ids={track_id}"))
+        } else {
+            SPOTIFY_CLIENT.api_delete(&format!("me/tracks/?ids={track_id}"))
+        };
+        if let Err(err) = result {
+            error!(
+                "Failed to {} track {track_id} {} liked songs: {err}",
+                if liked { "add" } else { "remove" },
+                if liked { "to" } else { "from" }
+            );
+        }
+    }
+
+    fn list_devices(&self) -> Vec<PlaybackDevice> {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/get-a-users-available-devices
+        SPOTIFY_CLIENT
+            .api_get("me/player/devices")
+            .map_err(|err| error!("Failed to get available devices: {err}"))
+            .ok()
+            .and_then(|res| {
+                serde_json::from_str::<Devices>(&res)
+                    .map_err(|err| error!("Failed to parse available devices: {err}"))
+                    .ok()
+            })
+            .map(|res| res.devices)
+            .unwrap_or_default()
+    }
+
+    fn transfer_playback(&self, device_id: &str) {
+        // https://developer.spotify.com/documentation/web-api/reference/#/operations/transfer-a-users-playback
+        let payload = serde_json::json!({ "device_ids": [device_id], "play": true });
+        if let Err(err) = SPOTIFY_CLIENT.api_put_payload("me/player", &payload.to_string()) {
+            error!("Failed to transfer playback to device {device_id}: {err}");
+        }
+    }
+
+    fn persist(&self) {
+        persist_playlist_cache();
+        // Only a client that finished authorizing has a token, forcing one here would start the
+        // browser flow or wait on its redirect while shutting down
+        if let Some(client) = LazyLock::get(&SPOTIFY_CLIENT) {
+            client.write_token_cache();
+        }
+    }
+}
+
 type PlaylistCache = HashMap<PlaylistId, (ArrayString<32>, HashSet<TrackId>)>;
 
 fn load_cached_playlist_tracks() -> PlaylistCache {
@@ -508,8 +954,8 @@ fn persist_playlist_cache() {
 
 pub fn init() {
     let cantus_dir = dirs::config_dir().unwrap().join("cantus");
-    if !cantus_dir.exists() {
-        fs::create_dir(&cantus_dir).unwrap();
+    if let Err(err) = fs::create_dir_all(&cantus_dir) {
+        warn!("Failed to create {}: {err}", cantus_dir.display());
     }
     let _ = &*SPOTIFY_CLIENT;
     spawn(poll_playlists);
@@ -517,29 +963,37 @@ pub fn init() {
         loop {
             get_spotify_playback();
             get_spotify_queue();
-            sleep(Duration::from_millis(500));
+            get_spotify_devices();
+            sleep(poll_interval());
         }
     });
 }
 
 fn get_spotify_playback() {
     let now = Instant::now();
-    if now < PLAYBACK_STATE.read().last_interaction
-        || now < SPOTIFY_STATE.read().last_grabbed_playback + Duration::from_secs(1)
-    {
+    if now < SPOTIFY_STATE.read().last_grabbed_playback + Duration::from_secs(1) {
         return;
     }
 
-    let current_playback_opt = SPOTIFY_CLIENT
-        .api_get("me/player")
+    let Ok(res) = SPOTIFY_CLIENT.api_get("me/player?additional_types=episode") else {
+        return;
+    };
+    // Spotify answers with no content when there is no active device
+    if res.is_empty() {
+        SPOTIFY_STATE.write().last_grabbed_playback = now;
+        if !PLAYBACK_STATE.read().no_active_device {
+            info!("No active Spotify device");
+            update_playback_state(|state| {
+                state.no_active_device = true;
+                state.playing = false;
+            });
+        }
+        return;
+    }
+    let Some(current_playback) = serde_json::from_str::<CurrentPlaybackContext>(&res)
+        .map_err(|e| error!("Failed to parse playback: {e}"))
         .ok()
-        .filter(|res| !res.is_empty())
-        .and_then(|res| {
-            serde_json::from_str::<CurrentPlaybackContext>(&res)
-                .map_err(|e| error!("Failed to parse playback: {e}"))
-                .ok()
-        });
-    let Some(current_playback) = current_playback_opt else {
+    else {
         return;
     };
 
@@ -555,11 +1009,14 @@ fn get_spotify_playback() {
             spotify_state.last_grabbed_queue = queue_deadline;
         }
 
-        if let Some(track) = current_playback.item {
-            state.queue_index = state
-                .queue
-                .iter()
-                .position(|t| t.name == track.name)
+        // Until a local skip or seek settles the service may still report where playback was
+        let settled = now >= state.last_interaction;
+        if let Some(track) = current_playback
+            .item
+            .filter(|_| settled)
+            .and_then(PlayableItem::into_track)
+        {
+            state.queue_index = playing_position(&state.queue, state.queue_index, &track)
                 .unwrap_or_else(|| {
                     spotify_state.last_grabbed_queue = queue_deadline;
                     0
@@ -567,15 +1024,79 @@ fn get_spotify_playback() {
         }
 
         state.volume = current_playback.device.volume_percent.map(|v| v as u8);
-        if now >= state.last_interaction {
+        state.no_active_device = false;
+        if now >= state.last_play_toggle && state.playing != current_playback.is_playing {
+            // Keep the playhead where it got to, the reported progress may not be trusted yet
+            if state.playing {
+                state.progress += state.last_progress_update.elapsed().as_millis() as u32;
+            }
+            state.last_progress_update = now;
             state.playing = current_playback.is_playing;
+        }
+        if settled {
+            state.shuffle = current_playback.shuffle_state;
+            state.repeat = current_playback.repeat_state;
             state.progress = current_playback.progress_ms;
+            state.last_progress_update = now;
         }
-        state.last_progress_update = now;
         spotify_state.last_grabbed_playback = now;
     });
 }
 
+fn get_spotify_devices() {
+    let now = Instant::now();
+    if now < SPOTIFY_STATE.read().last_grabbed_devices + DEVICES_INTERVAL {
+        return;
+    }
+    SPOTIFY_STATE.write().last_grabbed_devices = now;
+    let devices = SpotifyBackend.list_devices();
+    update_playback_state(|state| state.devices = devices);
+}
+
+/// Find a track in the queue by id, a song queued more than once resolves to the copy at or after
+/// the current index since playback moves forwards.
+fn queue_position(
+    queue: &[Track],
+    current_index: usize,
+    track_id: Option<TrackId>,
+) -> Option<usize> {
+    track_id?;
+    let start = current_index.min(queue.len());
+    queue[start..]
+        .iter()
+        .position(|t| t.id == track_id)
+        .map(|index| start + index)
+        .or_else(|| queue[..start].iter().rposition(|t| t.id == track_id))
+}
+
+/// Find the playing track in the queue by id, falling back to its name only when the id isn't
+/// there, as relinked tracks can come back with a different id to the queue's copy.
+fn playing_position(queue: &[Track], current_index: usize, track: &Track) -> Option<usize> {
+    queue_position(queue, current_index, track.id)
+        .or_else(|| queue.iter().position(|t| t.name == track.name))
+}
+
+/// Splice a fresh queue from the service on after the history, returning the new current index.
+///
+/// The history is only kept when asked to and the first of the new tracks is found in the queue,
+/// otherwise the queue is replaced outright.
+fn merge_queue(
+    queue: &mut Vec<Track>,
+    current_index: usize,
+    new_queue: Vec<Track>,
+    keep_history: bool,
+) -> usize {
+    let current_id = new_queue.first().and_then(|track| track.id);
+    if keep_history && let Some(index) = queue_position(queue, current_index, current_id) {
+        queue.truncate(index);
+        queue.extend(new_queue);
+        index
+    } else {
+        *queue = new_queue;
+        0
+    }
+}
+
 fn get_spotify_queue() {
     let now = Instant::now();
     if now < PLAYBACK_STATE.read().last_interaction
@@ -593,12 +1114,19 @@ fn get_spotify_queue() {
                 .map_err(|e| error!("Failed to parse queue: {e}"))
                 .ok()
         });
-    let Some(queue) = queue_data.and_then(|q| q.currently_playing.map(|cp| (cp, q.queue))) else {
+    let Some(queue) = queue_data.and_then(|q| {
+        q.currently_playing
+            .and_then(PlayableItem::into_track)
+            .map(|cp| (cp, q.queue))
+    }) else {
         return;
     };
 
-    let new_queue: Vec<Track> = std::iter::once(queue.0).chain(queue.1).collect();
-    let current_title = new_queue[0].name.clone();
+    let max_tracks = CONFIG.load().max_visible_tracks;
+    let new_queue: Vec<Track> = std::iter::once(queue.0)
+        .chain(queue.1.into_iter().filter_map(PlayableItem::into_track))
+        .take(max_tracks)
+        .collect();
 
     let mut missing_artists = HashSet::new();
     for track in &new_queue {
@@ -648,73 +1176,127 @@ fn get_spotify_queue() {
 
     let mut spotify_state = SPOTIFY_STATE.write();
     update_playback_state(|state| {
-        if !spotify_state.context_updated
-            && let Some(new_index) = state.queue.iter().position(|t| t.name == current_title)
-        {
-            state.queue_index = new_index;
-            state.queue.truncate(new_index);
-            state.queue.extend(new_queue);
-        } else {
-            spotify_state.context_updated = false;
-            state.queue = new_queue;
-            state.queue_index = 0;
+        state.queue_index = merge_queue(
+            &mut state.queue,
+            state.queue_index,
+            new_queue,
+            !spotify_state.context_updated,
+        );
+        spotify_state.context_updated = false;
+        // Drop the oldest history so long listening sessions don't grow the queue forever
+        let excess = state.queue_index.saturating_sub(max_tracks);
+        for track in state.queue.drain(..excess) {
+            state.trimmed_history.0 += u64::from(track.duration_ms);
         }
+        state.trimmed_history.1 += excess;
+        state.queue_index -= excess;
         spotify_state.last_grabbed_queue = Instant::now();
     });
+    drop(spotify_state);
+
+    // Thumbnails loaded from disk skip the download thread, so build their palettes here
+    update_color_palettes();
 }
 
 fn ensure_image_cached(url: &str) {
-    if IMAGES_CACHE.contains_key(url) {
+    if !should_fetch_image(url) {
+        return;
+    }
+    if let Some(img) = read_image_cache(url) {
+        IMAGES_CACHE.insert(url.to_owned(), Some(Arc::new(img)));
+        return;
+    }
+    if OFFLINE.load(Ordering::Relaxed) {
         return;
     }
     IMAGES_CACHE.insert(url.to_owned(), None);
 
     let url = url.to_owned();
     spawn(move || {
-        if let Ok(mut resp) = SPOTIFY_CLIENT.http.get(&url).call()
-            && let Ok(img) = image::load_from_memory(&resp.body_mut().read_to_vec().unwrap())
-        {
-            let img = if img.width() != 64 || img.height() != 64 {
-                img.resize_to_fill(64, 64, image::imageops::FilterType::Lanczos3)
-            } else {
-                img
-            };
-            IMAGES_CACHE.insert(url, Some(Arc::new(img.to_rgba8())));
-            update_color_palettes();
-        }
+        let bytes = SPOTIFY_CLIENT.download(&url).map_err(|e| e.to_string());
+        store_fetched_image(url, bytes);
     });
 }
 
 fn poll_playlists() {
-    let targets = CONFIG
-        .playlists
-        .iter()
-        .map(String::as_str)
-        .collect::<HashSet<_>>();
     let mut cached = load_cached_playlist_tracks();
+    let mut tracked_context: Option<PlaylistId> = None;
 
     loop {
-        let playlists = SPOTIFY_CLIENT
+        // Read the favourites every time round so config reloads are picked up
+        let config = CONFIG.load_full();
+        let targets = config
+            .playlists
+            .iter()
+            .map(String::as_str)
+            .collect::<HashSet<_>>();
+        let mut playlists = SPOTIFY_CLIENT
             .api_get_payload("me/playlists", &[("limit", "50")])
             .ok()
             .and_then(|res| serde_json::from_str::<Page<Playlist>>(&res).ok())
             .map(|p| p.items)
             .unwrap_or_default();
 
+        // The playlist being played from, tracked alongside the favourites when enabled
+        let context_id = if config.track_current_context {
+            SPOTIFY_STATE
+                .read()
+                .current_context
+                .as_deref()
+                .and_then(|uri| uri.strip_prefix("spotify:playlist:"))
+                .and_then(|id| PlaylistId::from(id).ok())
+        } else {
+            None
+        };
+        if let Some(context_id) = context_id
+            && !playlists.iter().any(|playlist| playlist.id == context_id)
+        {
+            // Playlists the user doesn't follow aren't in their list, look it up directly
+            let playlist = SPOTIFY_CLIENT
+                .api_get_payload(
+                    &format!("playlists/{context_id}"),
+                    &[("fields", "id,name,images,snapshot_id,tracks(total)")],
+                )
+                .ok()
+                .and_then(|res| {
+                    serde_json::from_str::<Playlist>(&res)
+                        .map_err(|e| error!("Failed to parse playlist {context_id}: {e}"))
+                        .ok()
+                });
+            playlists.extend(playlist);
+        }
+        // Drop the previous context's button once something else plays, unless it's wanted anyway
+        if tracked_context != context_id {
+            if let Some(previous) = tracked_context {
+                update_playback_state(|state| {
+                    if state.playlists.get(&previous).is_some_and(|playlist| {
+                        playlist.rating_index.is_none() && !targets.contains(playlist.name.as_str())
+                    }) {
+                        state.playlists.remove(&previous);
+                    }
+                });
+            }
+            tracked_context = context_id;
+        }
+
         for playlist in playlists {
             let is_rating =
-                CONFIG.ratings_enabled && RATING_PLAYLISTS.contains(&playlist.name.as_str());
-            if !targets.contains(playlist.name.as_str()) && !is_rating {
+                config.ratings_enabled && config.rating_playlist_names.contains(&playlist.name);
+            if !targets.contains(playlist.name.as_str())
+                && !is_rating
+                && Some(playlist.id) != context_id
+            {
                 continue;
             }
             if let Some(image) = &playlist.image {
                 ensure_image_cached(image);
             }
 
-            let rating_index = if CONFIG.ratings_enabled {
-                RATING_PLAYLISTS
+            let rating_index = if config.ratings_enabled {
+                config
+                    .rating_playlist_names
                     .iter()
-                    .position(|&p| p == playlist.name)
+                    .position(|p| *p == playlist.name)
                     .map(|i| i as u8)
             } else {
                 None
@@ -775,7 +1357,12 @@ fn poll_playlists() {
 
                     if let Some(page) = page_data {
                         total = page.total;
-                        playlist_track_ids.extend(page.items.iter().map(|item| item.track.id));
+                        playlist_track_ids.extend(
+                            page.items
+                                .iter()
+                                .filter(|item| !item.is_local)
+                                .filter_map(|item| item.track.id),
+                        );
                     } else {
                         return;
                     }
@@ -807,3 +1394,267 @@ fn poll_playlists() {
         sleep(Duration::from_secs(20));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, net::TcpStream, thread::JoinHandle};
+
+    /// Answer each connection in turn with the next response, returning the requests it was sent.
+    fn mock_server(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let request = read_request(&stream);
+                    stream.write_all(response.as_bytes()).unwrap();
+                    request
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    fn read_request(stream: &TcpStream) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut request = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        request.push_str(&String::from_utf8(body).unwrap());
+        request
+    }
+
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n{headers}\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn token(expires_at: Option<OffsetDateTime>) -> Token {
+        Token {
+            access: "current".into(),
+            expires_in: 3600,
+            expires_at,
+            refresh: Some("refresh".into()),
+            scopes: HashSet::new(),
+        }
+    }
+
+    fn track(id: &str, name: &str) -> Track {
+        Track {
+            id: Some(TrackId::from(id).unwrap()),
+            name: name.into(),
+            album: Album {
+                id: None,
+                name: String::new(),
+                image: None,
+            },
+            artist: Artist {
+                id: None,
+                name: String::new(),
+                image: None,
+            },
+            duration_ms: 180_000,
+            is_local: false,
+            uri: format!("spotify:track:{id}"),
+        }
+    }
+
+    fn ids(queue: &[Track]) -> Vec<&str> {
+        queue
+            .iter()
+            .map(|track| track.id.as_ref().map_or("", ArrayString::as_str))
+            .collect()
+    }
+
+    fn valid_token() -> Token {
+        token(Some(OffsetDateTime::now_utc() + TimeDuration::hours(1)))
+    }
+
+    fn test_client(url: &str, token: Token) -> SpotifyClient {
+        let agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .proxy(None)
+            .build()
+            .new_agent();
+        SpotifyClient::with_agent(
+            "client".into(),
+            HashSet::new(),
+            std::env::temp_dir().join(format!("cantus-test-token-{}.json", fastrand::u64(..))),
+            token,
+            agent,
+            &format!("{url}/v1/"),
+            url,
+        )
+    }
+
+    #[test]
+    fn api_get_returns_the_body() {
+        let (url, server) = mock_server(vec![response("200 OK", "", r#"{"is_playing":true}"#)]);
+        let client = test_client(&url, valid_token());
+
+        assert_eq!(
+            client.api_get("me/player").unwrap(),
+            r#"{"is_playing":true}"#
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /v1/me/player HTTP/1.1"));
+        assert!(
+            requests[0]
+                .to_lowercase()
+                .contains("authorization: bearer current")
+        );
+    }
+
+    #[test]
+    fn api_delete_payload_sends_the_json() {
+        let (url, server) = mock_server(vec![response("200 OK", "", "")]);
+        let client = test_client(&url, valid_token());
+        let payload = r#"{"ids":["4uLU6hMCjMI75M1A2tKUQC"]}"#;
+
+        client.api_delete_payload("me/tracks", payload).unwrap();
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("DELETE /v1/me/tracks HTTP/1.1"));
+        assert!(
+            requests[0]
+                .to_lowercase()
+                .contains("content-type: application/json")
+        );
+        assert!(requests[0].ends_with(payload));
+    }
+
+    #[test]
+    fn expired_token_is_refreshed_first() {
+        let (url, server) = mock_server(vec![
+            response(
+                "200 OK",
+                "",
+                r#"{"access_token":"renewed","expires_in":3600,"scope":""}"#,
+            ),
+            response("200 OK", "", "{}"),
+        ]);
+        let client = test_client(&url, token(None));
+
+        client.api_get("me").unwrap();
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /api/token HTTP/1.1"));
+        assert!(requests[0].contains("grant_type=refresh_token"));
+        assert!(requests[0].contains("refresh_token=refresh"));
+        assert!(
+            requests[1]
+                .to_lowercase()
+                .contains("authorization: bearer renewed")
+        );
+        // The old refresh token is kept when a new one isn't sent back
+        assert!(!client.token.read().is_expired());
+        assert_eq!(client.token.read().refresh.as_deref(), Some("refresh"));
+        let _ = fs::remove_file(&client.cache_path);
+    }
+
+    #[test]
+    fn rate_limited_request_is_retried() {
+        let (url, server) = mock_server(vec![
+            response("429 Too Many Requests", "retry-after: 0\r\n", ""),
+            response("200 OK", "", "[]"),
+        ]);
+        let client = test_client(&url, valid_token());
+
+        assert_eq!(client.api_get("me/playlists").unwrap(), "[]");
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].starts_with("GET /v1/me/playlists HTTP/1.1"));
+    }
+
+    #[test]
+    fn queue_position_tells_same_named_tracks_apart() {
+        let queue = [track("a", "Intro"), track("b", "Song"), track("c", "Intro")];
+        assert_eq!(queue_position(&queue, 0, TrackId::from("c").ok()), Some(2));
+        assert_eq!(queue_position(&queue, 2, TrackId::from("a").ok()), Some(0));
+        assert_eq!(queue_position(&queue, 0, TrackId::from("z").ok()), None);
+        assert_eq!(queue_position(&queue, 0, None), None);
+    }
+
+    #[test]
+    fn queue_position_prefers_the_copy_at_or_after_the_current_index() {
+        let queue = [track("a", "Song"), track("b", "Other"), track("a", "Song")];
+        assert_eq!(queue_position(&queue, 0, TrackId::from("a").ok()), Some(0));
+        assert_eq!(queue_position(&queue, 1, TrackId::from("a").ok()), Some(2));
+        assert_eq!(queue_position(&queue, 2, TrackId::from("a").ok()), Some(2));
+        // Past the end of the queue still finds the latest copy behind it
+        assert_eq!(queue_position(&queue, 10, TrackId::from("a").ok()), Some(2));
+    }
+
+    #[test]
+    fn merge_keeps_history_up_to_the_current_track() {
+        let mut queue = vec![
+            track("a", "Intro"),
+            track("b", "Song"),
+            track("c", "Intro"),
+            track("d", "Outro"),
+        ];
+        let new_queue = vec![track("c", "Intro"), track("e", "Encore")];
+        let index = merge_queue(&mut queue, 1, new_queue, true);
+        assert_eq!(index, 2);
+        assert_eq!(ids(&queue), ["a", "b", "c", "e"]);
+    }
+
+    #[test]
+    fn merge_resolves_a_track_queued_twice_forwards() {
+        let mut queue = vec![track("a", "Song"), track("b", "Other"), track("a", "Song")];
+        let index = merge_queue(&mut queue, 1, vec![track("a", "Song")], true);
+        assert_eq!(index, 2);
+        assert_eq!(ids(&queue), ["a", "b", "a"]);
+    }
+
+    #[test]
+    fn merge_replaces_the_queue_on_a_new_context_or_unknown_track() {
+        let mut queue = vec![track("a", "Song"), track("b", "Other")];
+        assert_eq!(
+            merge_queue(&mut queue, 0, vec![track("b", "Other")], false),
+            0
+        );
+        assert_eq!(ids(&queue), ["b"]);
+
+        let mut queue = vec![track("a", "Song"), track("b", "Other")];
+        assert_eq!(merge_queue(&mut queue, 0, vec![track("z", "New")], true), 0);
+        assert_eq!(ids(&queue), ["z"]);
+    }
+
+    #[test]
+    fn playing_track_is_found_by_id_before_name() {
+        let queue = [
+            track("a", "Intro"),
+            track("b", "Intro (Reprise)"),
+            track("c", "Intro"),
+        ];
+        assert_eq!(playing_position(&queue, 0, &track("c", "Intro")), Some(2));
+        assert_eq!(
+            playing_position(&queue, 0, &track("b", "Intro (Reprise)")),
+            Some(1)
+        );
+        // The name is only used for ids missing from the queue, such as relinked tracks
+        assert_eq!(
+            playing_position(&queue, 2, &track("z", "Intro (Reprise)")),
+            Some(1)
+        );
+        assert_eq!(playing_position(&queue, 0, &track("z", "Outro")), None);
+    }
+}
@@ -1,13 +1,27 @@
+use crate::backend::PlaybackBackend;
+use crate::pipelines::IMAGE_SIZE;
 use crate::render::update_color_palettes;
 use crate::{
-    ARTIST_DATA_CACHE, Album, Artist, CondensedPlaylist, IMAGES_CACHE, PlaybackState, Track,
+    ARTIST_DATA_CACHE, Album, Artist, CondensedPlaylist, IMAGE_FAILURES, IMAGES_CACHE,
+    PlaybackState, RepeatMode, Track, image_fetch_failed, should_fetch_image,
 };
 use arrayvec::ArrayString;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::thread::spawn;
 use std::time::Instant;
-use tracing::warn;
+
+/// Debug backend, controls only affect the local playback state.
+pub struct DebugBackend;
+
+impl PlaybackBackend for DebugBackend {
+    fn play(&self) {}
+    fn pause(&self) {}
+    fn seek_ms(&self, _ms: u32) {}
+    fn set_volume(&self, _pct: u8) {}
+    fn skip_next(&self) {}
+    fn skip_prev(&self) {}
+}
 
 fn random_arraystring() -> ArrayString<22> {
     let mut s = ArrayString::<22>::new();
@@ -26,16 +40,19 @@ fn artist() -> Artist {
     }
 }
 
-fn track(name: &str, album_img: &str, duration: u32) -> Track {
+fn track(name: &str, album: &str, album_img: &str, duration: u32) -> Track {
     Track {
         id: random_arraystring(),
         name: name.into(),
         album: Album {
             id: random_arraystring(),
+            name: album.into(),
             image: Some(album_img.into()),
         },
         artist: artist(),
         duration_ms: duration,
+        is_local: false,
+        uri: String::new(),
     }
 }
 
@@ -58,100 +75,120 @@ pub fn debug_playbackstate() -> PlaybackState {
     let queue = vec![
         track(
             "King Of My Heart",
+            "reputation",
             "https://i.scdn.co/image/ab67616d00004851da5d5aeeabacacc1263c0f4b",
             214320,
         ),
         track(
             "Slut! (Taylor's Version)",
+            "1989 (Taylor's Version)",
             "https://i.scdn.co/image/ab67616d00004851904445d70d04eb24d6bb79ac",
             180381,
         ),
         track(
             "Stay Beautiful",
+            "Taylor Swift",
             "https://i.scdn.co/image/ab67616d000048512f8c0fd72a80a93f8c53b96c",
             236053,
         ),
         track(
+            "Speak Now",
             "Speak Now",
             "https://i.scdn.co/image/ab67616d00004851be4ec62353ee75fa11f6d6f7",
             248146,
         ),
         track(
             "Superstar (Taylor’s Version)",
+            "Fearless (Taylor's Version)",
             "https://i.scdn.co/image/ab67616d00004851a48964b5d9a3d6968ae3e0de",
             263865,
         ),
         track(
             "Anti-Hero (feat. Bleachers)",
+            "Midnights (The Til Dawn Edition)",
             "https://i.scdn.co/image/ab67616d000048519954fa9f0ba8534c3897b59d",
             228397,
         ),
         track(
             "The Man",
+            "Lover",
             "https://i.scdn.co/image/ab67616d000048519a6d517fc78fb707bed067c2",
             219385,
         ),
         track(
             "Never Grow Up",
+            "Speak Now",
             "https://i.scdn.co/image/ab67616d000048512e4ec3175d848eca7b76b07f",
             290466,
         ),
         track(
             "Enchanted (Taylor's Version)",
+            "Speak Now (Taylor's Version)",
             "https://i.scdn.co/image/ab67616d000048510b04da4f224b51ff86e0a481",
             353253,
         ),
         track(
+            "Only The Young",
             "Only The Young",
             "https://i.scdn.co/image/ab67616d000048514aa13f6de271d8403a82e4a8",
             157507,
         ),
         track(
             "So Long, London",
+            "THE TORTURED POETS DEPARTMENT",
             "https://i.scdn.co/image/ab67616d000048518ecc33f195df6aa257c39eaa",
             262974,
         ),
         track(
             "seven",
+            "folklore",
             "https://i.scdn.co/image/ab67616d0000485195f754318336a07e85ec59bc",
             208906,
         ),
         track(
             "marjorie",
+            "evermore",
             "https://i.scdn.co/image/ab67616d0000485125751b4b32829d6bbfe6be7f",
             257773,
         ),
         track(
             "The Archer",
+            "Lover",
             "https://i.scdn.co/image/ab67616d00004851cde19bd5377f06eb0bca3256",
             210960,
         ),
         track(
             "Holy Ground (Taylor's Version)",
+            "Red (Taylor's Version)",
             "https://i.scdn.co/image/ab67616d00004851318443aab3531a0558e79a4d",
             202960,
         ),
         track(
             "august - the long pond studio sessions",
+            "folklore: the long pond studio sessions",
             "https://i.scdn.co/image/ab67616d00004851045514e3ed4e1767a7c3ece5",
             260000,
         ),
         track(
             "Sparks Fly",
+            "Speak Now",
             "https://i.scdn.co/image/ab67616d00004851be4ec62353ee75fa11f6d6f7",
             336826,
         ),
         track(
             "ME!",
+            "Lover",
             "https://i.scdn.co/image/ab67616d00004851d8f29c3584e77996dd2f9950",
             213026,
         ),
         track(
             "Breathe",
+            "Fearless (Platinum Edition)",
             "https://i.scdn.co/image/ab67616d0000485134e5885465afc8a497ac1b7e",
             263986,
         ),
         track(
+            "Lover (Remix) [feat. Shawn Mendes]",
             "Lover (Remix) [feat. Shawn Mendes]",
             "https://i.scdn.co/image/ab67616d0000485159457bdb1edb5c6417f3baa2",
             221306,
@@ -247,19 +284,26 @@ pub fn debug_playbackstate() -> PlaybackState {
     // Return the new state
     PlaybackState {
         playing: true,
+        shuffle: false,
+        repeat: RepeatMode::Context,
         progress: 5213,
         volume: Some(100),
+        pre_mute_volume: None,
         queue,
         queue_index: 7,
         playlists,
+        devices: Vec::new(),
+        no_active_device: false,
+        trimmed_history: (0, 0),
         interaction: false,
         last_interaction: Instant::now(),
+        last_play_toggle: Instant::now(),
         last_progress_update: Instant::now(),
     }
 }
 
 fn ensure_image_cached(url: &str) {
-    if IMAGES_CACHE.contains_key(url) {
+    if !should_fetch_image(url) {
         return;
     }
     IMAGES_CACHE.insert(url.to_owned(), None);
@@ -267,24 +311,32 @@ fn ensure_image_cached(url: &str) {
     let url = url.to_owned();
     spawn(move || {
         let agent = ureq::Agent::new_with_defaults();
-        let mut response = match agent.get(&url).call() {
-            Ok(response) => response,
+        let bytes = match agent
+            .get(&url)
+            .call()
+            .and_then(|mut response| response.body_mut().read_to_vec())
+        {
+            Ok(bytes) => bytes,
             Err(err) => {
-                warn!("Failed to cache image {url}: {err}");
+                image_fetch_failed(&url, &err.to_string());
                 return;
             }
         };
-        let Ok(dynamic_image) =
-            image::load_from_memory(&response.body_mut().read_to_vec().unwrap())
-        else {
-            warn!("Failed to cache image {url}: failed to read image");
+        let Ok(dynamic_image) = image::load_from_memory(&bytes) else {
+            image_fetch_failed(&url, "failed to read image");
             return;
         };
-        let dynamic_image = if dynamic_image.width() != 64 || dynamic_image.height() != 64 {
-            dynamic_image.resize_to_fill(64, 64, image::imageops::FilterType::Lanczos3)
-        } else {
-            dynamic_image
-        };
+        let dynamic_image =
+            if dynamic_image.width() != *IMAGE_SIZE || dynamic_image.height() != *IMAGE_SIZE {
+                dynamic_image.resize_to_fill(
+                    *IMAGE_SIZE,
+                    *IMAGE_SIZE,
+                    image::imageops::FilterType::Lanczos3,
+                )
+            } else {
+                dynamic_image
+            };
+        IMAGE_FAILURES.remove(&url);
         IMAGES_CACHE.insert(url, Some(Arc::new(dynamic_image.to_rgba8())));
         update_color_palettes();
     });
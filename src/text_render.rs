@@ -1,56 +1,415 @@
-use crate::PANEL_START;
-use crate::config::CONFIG;
+use crate::config::{CONFIG, LineToken, TextOverflow};
 use crate::render::TrackRender;
-use wgpu::{Device, Queue, RenderPass};
+use crate::{ALBUM_PALETTE_CACHE, PANEL_START, Track, TrackId};
+use std::{collections::HashMap, fs, path::Path};
+use tracing::warn;
+use wgpu::{Device, MultisampleState, Queue, RenderPass};
 use wgpu_text::{
     BrushBuilder, TextBrush,
     glyph_brush::{
-        BuiltInLineBreaker, HorizontalAlign, Layout, OwnedSection, OwnedText, Section, Text,
-        VerticalAlign, ab_glyph::FontArc, ab_glyph::PxScale,
+        BuiltInLineBreaker, Extra, FontId, HorizontalAlign, Layout, OwnedSection, OwnedText,
+        Section, Text, VerticalAlign,
+        ab_glyph::{Font, FontArc, FontVec, PxScale, VariableFont},
     },
 };
 
 const FONT_SIZE: f32 = 17.0;
 const FONT_SIZE_SMALL: f32 = 14.0;
+const FONT_SIZE_TIME: f32 = 11.0;
+/// How much smaller all text is drawn on compact bars, see `Config::compact`.
+const COMPACT_TEXT_SCALE: f32 = 0.85;
+/// Shortest bar with room for the time label under the title and artist lines.
+pub const TIME_LABEL_MIN_HEIGHT: f32 = 48.0;
+/// Space between the tooltip text and the edges of its pill.
+pub const TOOLTIP_PADDING: f32 = 12.0;
+
+const EMBEDDED_FONT: &[u8] = include_bytes!("../assets/NotoSans-Bold.ttf");
+
+const LIGHT_TEXT: [f32; 3] = [0.94, 0.94, 0.94];
+const DARK_TEXT: [f32; 3] = [0.08, 0.08, 0.08];
+/// Characters per second the current track title scrolls at when it doesn't fit.
+const MARQUEE_SPEED: f32 = 4.0;
+/// Seconds the title rests at either end of the scroll.
+const MARQUEE_PAUSE: f32 = 1.5;
+
+/// Measured widths to keep before starting over, the countdown text changes every second.
+const MAX_CACHED_WIDTHS: usize = 512;
+/// Pixels the available width is rounded down to when caching a cut title, so tracks sliding
+/// along don't need a new cut every frame.
+const ELLIPSIS_WIDTH_STEP: f32 = 4.0;
+
+/// Palettes brighter than this on average get dark text.
+const DARK_TEXT_LUMINANCE: f32 = 0.6;
+
+/// Pick light or dark text to contrast with the album palette, light until a palette is cached.
+fn text_color(track: &Track) -> [f32; 3] {
+    let Some(palette) = track
+        .album
+        .id
+        .and_then(|id| ALBUM_PALETTE_CACHE.get(&id))
+        .and_then(|data_ref| data_ref.clone())
+        .filter(|palette| !palette.is_empty())
+    else {
+        return LIGHT_TEXT;
+    };
+    let luminance = palette
+        .iter()
+        .map(|color| {
+            let [r, g, b, _] = color.to_le_bytes();
+            (0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b)) / 255.0
+        })
+        .sum::<f32>()
+        / palette.len() as f32;
+    if luminance > DARK_TEXT_LUMINANCE {
+        DARK_TEXT
+    } else {
+        LIGHT_TEXT
+    }
+}
+
+fn load_font(path: &Path, weight: Option<f32>) -> Option<FontArc> {
+    let bytes = fs::read(path)
+        .map_err(|e| warn!("Failed to read font {path:?}: {e}"))
+        .ok()?;
+    let mut font = FontVec::try_from_vec(bytes)
+        .map_err(|e| warn!("Failed to parse font {path:?}: {e}"))
+        .ok()?;
+    if let Some(weight) = weight
+        && !font.set_variation(b"wght", weight)
+    {
+        warn!("Font {path:?} has no weight axis, ignoring font_weight");
+    }
+    Some(FontArc::new(font))
+}
+
+/// Split text into runs drawn with the first font that has each character, the same split is used
+/// when measuring so widths match what gets drawn.
+fn font_runs<'a>(fonts: &[FontArc], text: &'a str) -> Vec<(FontId, &'a str)> {
+    let font_for = |c: char| {
+        fonts
+            .iter()
+            .position(|font| font.glyph_id(c).0 != 0)
+            .unwrap_or_default()
+    };
+    let mut runs = Vec::new();
+    let mut run_start = 0;
+    let mut run_font = None;
+    for (i, c) in text.char_indices() {
+        let font = font_for(c);
+        if run_font != Some(font) {
+            if let Some(previous) = run_font {
+                runs.push((FontId(previous), &text[run_start..i]));
+            }
+            run_start = i;
+            run_font = Some(font);
+        }
+    }
+    if let Some(font) = run_font {
+        runs.push((FontId(font), &text[run_start..]));
+    }
+    runs
+}
+
+/// Format milliseconds as `m:ss`.
+fn format_time(ms: f32) -> String {
+    let seconds = (ms / 1000.0).max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Format seconds as `12s`, or `3m12s` from a minute up.
+fn format_countdown(seconds: f32) -> String {
+    let seconds = seconds.max(0.0);
+    if seconds >= 60.0 {
+        format!("{}m{}s", (seconds / 60.0).floor(), (seconds % 60.0).floor())
+    } else {
+        format!("{}s", seconds.round())
+    }
+}
+
+/// Fill in a piece of `bottom_line_format` for the track.
+fn line_token(token: &LineToken, track: &Track, track_render: &TrackRender) -> String {
+    match token {
+        LineToken::Text(text) => text.clone(),
+        LineToken::Artist => track.artist.name.clone(),
+        LineToken::Album => track.album.name.clone(),
+        LineToken::Time => format_countdown(track_render.seconds_until_start),
+        LineToken::Remaining => {
+            let played = if track_render.is_current {
+                track_render.seconds_until_start
+            } else {
+                0.0
+            };
+            format_countdown(track.duration_ms as f32 / 1000.0 - played)
+        }
+    }
+}
+
+/// The artist, followed by the album when it's known.
+fn tooltip_subtitle(track: &Track) -> String {
+    if track.album.name.is_empty() {
+        track.artist.name.clone()
+    } else {
+        format!("{}\u{2004}•\u{2004}{}", track.artist.name, track.album.name)
+    }
+}
+
+/// The font size text of `size` is drawn at, smaller on compact bars.
+fn font_size(size: f32) -> f32 {
+    if CONFIG.load().compact() {
+        size * COMPACT_TEXT_SCALE
+    } else {
+        size
+    }
+}
+
+/// Lay out a single line of text, mirroring where it sits for right to left timelines.
+fn text_section(
+    fonts: &[FontArc],
+    text: &str,
+    pos: (f32, f32),
+    size: f32,
+    h_align: HorizontalAlign,
+    color: [f32; 4],
+    max_width: f32,
+) -> OwnedSection {
+    // The glyphs themselves stay readable
+    let (pos, h_align) = if CONFIG.load().mirrored() {
+        let h_align = match h_align {
+            HorizontalAlign::Left => HorizontalAlign::Right,
+            HorizontalAlign::Right => HorizontalAlign::Left,
+            HorizontalAlign::Center => HorizontalAlign::Center,
+        };
+        ((CONFIG.load().width - pos.0, pos.1), h_align)
+    } else {
+        (pos, h_align)
+    };
+    let size = font_size(size);
+    OwnedSection {
+        screen_position: pos,
+        bounds: (max_width, f32::INFINITY),
+        layout: Layout::SingleLine {
+            line_breaker: BuiltInLineBreaker::AnyCharLineBreaker,
+            h_align,
+            v_align: VerticalAlign::Center,
+        },
+        text: font_runs(fonts, text)
+            .into_iter()
+            .map(|(font_id, run)| {
+                OwnedText::new(run)
+                    .with_scale(size)
+                    .with_color(color)
+                    .with_font_id(font_id)
+            })
+            .collect(),
+    }
+}
+
+/// Glyph brush that remembers the width of text it has laid out, its fonts never change.
+struct Brush {
+    inner: TextBrush<FontArc>,
+    /// Widths keyed by the text and the bits of its font size.
+    widths: HashMap<(String, u32), f32>,
+    /// Ellipsized text keyed by the full text, the bits of its font size, and the width step.
+    ellipsized: HashMap<(String, u32, u32), String>,
+}
+
+impl Brush {
+    fn text_width(&mut self, fonts: &[FontArc], text: &str, size: f32) -> f32 {
+        let key = (text.to_owned(), font_size(size).to_bits());
+        if let Some(&width) = self.widths.get(&key) {
+            return width;
+        }
+        let width = self.measure(fonts, text, size);
+        if self.widths.len() >= MAX_CACHED_WIDTHS {
+            self.widths.clear();
+        }
+        self.widths.insert(key, width);
+        width
+    }
+
+    /// Lay the text out to find its width, without caching it.
+    fn measure(&mut self, fonts: &[FontArc], text: &str, size: f32) -> f32 {
+        let size = font_size(size);
+        let texts = font_runs(fonts, text)
+            .into_iter()
+            .map(|(font_id, run)| Text::new(run).with_scale(size).with_font_id(font_id))
+            .collect();
+        self.inner
+            .glyph_bounds(
+                Section::default()
+                    .with_text(texts)
+                    .with_layout(Layout::SingleLine {
+                        line_breaker: BuiltInLineBreaker::AnyCharLineBreaker,
+                        h_align: HorizontalAlign::Left,
+                        v_align: VerticalAlign::Center,
+                    }),
+            )
+            .map_or(0.0, |b| b.width())
+    }
+}
 
 pub struct TextRenderer {
-    brush: TextBrush<FontArc>,
+    brush: Brush,
+    /// The main font followed by the fallbacks, in the order the brush knows them.
+    fonts: Vec<FontArc>,
     sections: Vec<OwnedSection>,
+    /// The track whose title is scrolling, and the time it started.
+    marquee: Option<(TrackId, f32)>,
+    /// Multiplies the alpha of all text, follows the opacity of the rest of the bar.
+    opacity: f32,
 }
 
 impl TextRenderer {
-    pub fn new(device: &Device, format: wgpu::TextureFormat) -> Self {
-        let font = FontArc::try_from_slice(include_bytes!("../assets/NotoSans-Bold.ttf")).unwrap();
+    pub fn new(device: &Device, format: wgpu::TextureFormat, sample_count: u32) -> Self {
+        let embedded =
+            FontArc::try_from_slice(EMBEDDED_FONT).expect("Embedded font should be valid");
+        let config = CONFIG.load();
+        let custom = config
+            .font_path
+            .as_deref()
+            .and_then(|path| load_font(path, config.font_weight));
+        let fallbacks = config
+            .font_fallbacks
+            .iter()
+            .filter_map(|path| load_font(path, None));
+        let has_custom = custom.is_some();
+        let mut fonts = custom.into_iter().chain(fallbacks).collect::<Vec<_>>();
+        // The bundled font stays in the chain so Latin text always has a match
+        if has_custom {
+            fonts.push(embedded);
+        } else {
+            fonts.insert(0, embedded);
+        }
         Self {
-            brush: BrushBuilder::using_font(font).build(device, 0, 0, format),
+            brush: Brush {
+                inner: BrushBuilder::using_fonts(fonts.clone())
+                    .with_multisample(MultisampleState {
+                        count: sample_count,
+                        ..Default::default()
+                    })
+                    .build(device, 0, 0, format),
+                widths: HashMap::new(),
+                ellipsized: HashMap::new(),
+            },
+            fonts,
             sections: Vec::new(),
+            marquee: None,
+            opacity: 1.0,
         }
     }
 
-    pub fn render(&mut self, track_render: &TrackRender) {
-        let track = track_render.track;
+    /// Cut the text short enough to fit with a trailing ellipsis.
+    ///
+    /// The cut point is binary searched, and only the result is cached, rounded down to
+    /// `ELLIPSIS_WIDTH_STEP` so it fits anywhere in the step.
+    fn ellipsize(
+        brush: &mut Brush,
+        fonts: &[FontArc],
+        text: &str,
+        size: f32,
+        available_width: f32,
+    ) -> String {
+        if brush.text_width(fonts, text, size) <= available_width {
+            return text.to_owned();
+        }
+        let step = (available_width / ELLIPSIS_WIDTH_STEP).max(0.0) as u32;
+        let key = (text.to_owned(), font_size(size).to_bits(), step);
+        if let Some(cut) = brush.ellipsized.get(&key) {
+            return cut.clone();
+        }
+
+        let width = step as f32 * ELLIPSIS_WIDTH_STEP;
+        let ends = text
+            .char_indices()
+            .map(|(i, c)| i + c.len_utf8())
+            .collect::<Vec<_>>();
+        let with_ellipsis = |end: usize| format!("{}\u{2026}", text[..end].trim_end());
+        // Longer prefixes are never narrower, so the ones that fit all come first
+        let fitting =
+            ends.partition_point(|&end| brush.measure(fonts, &with_ellipsis(end), size) <= width);
+        let cut = with_ellipsis(fitting.checked_sub(1).map_or(0, |index| ends[index]));
+
+        if brush.ellipsized.len() >= MAX_CACHED_WIDTHS {
+            brush.ellipsized.clear();
+        }
+        brush.ellipsized.insert(key, cut.clone());
+        cut
+    }
+
+    /// Scroll the title a character at a time, glyph brush culls glyphs past the bounds.
+    fn marquee_text<'a>(
+        brush: &mut Brush,
+        fonts: &[FontArc],
+        marquee: &mut Option<(TrackId, f32)>,
+        id: TrackId,
+        text: &'a str,
+        available_width: f32,
+        time: f32,
+    ) -> &'a str {
+        let start = match *marquee {
+            Some((marquee_id, start)) if marquee_id == id => start,
+            _ => {
+                *marquee = Some((id, time));
+                time
+            }
+        };
+
+        // The fewest characters to skip for the end of the title to be visible
+        let fits = |brush: &mut Brush, visible: &str| {
+            brush.text_width(fonts, visible, FONT_SIZE) <= available_width
+        };
+        let max_skip = if CONFIG.load().mirrored() {
+            text.char_indices()
+                .rev()
+                .position(|(i, _)| fits(brush, &text[..i]))
+                .map_or(0, |p| p + 1)
+        } else {
+            text.char_indices()
+                .position(|(i, _)| fits(brush, &text[i..]))
+                .unwrap_or_default()
+        };
+        let scroll_time = max_skip as f32 / MARQUEE_SPEED;
+        let t = (time - start) % (scroll_time + MARQUEE_PAUSE * 2.0);
+        let skip =
+            (((t - MARQUEE_PAUSE).clamp(0.0, scroll_time) * MARQUEE_SPEED) as usize).min(max_skip);
+        if CONFIG.load().mirrored() {
+            // Mirrored text is right aligned and overflows to the left, so reveal it by growing a prefix
+            let keep = text.chars().count() - (max_skip - skip);
+            text.char_indices()
+                .nth(keep)
+                .map_or(text, |(i, _)| &text[..i])
+        } else {
+            text.char_indices()
+                .nth(skip)
+                .map_or(text, |(i, _)| &text[i..])
+        }
+    }
+
+    pub fn render(&mut self, track_render: &TrackRender, track: &Track, time: f32) {
         let text_start_left = track_render.start_x + 12.0;
-        let text_start_right = track_render.start_x + track_render.width - CONFIG.height - 8.0;
+        let text_start_right =
+            track_render.start_x + track_render.width - CONFIG.load().height - 8.0;
         let available_width = text_start_right - text_start_left;
 
         if available_width <= 0.0 {
             return;
         }
 
-        let text_color = [0.94, 0.94, 0.94, (available_width / 100.0).min(1.0)];
+        let [r, g, b] = text_color(track);
+        let text_color = [r, g, b, (available_width / 100.0).min(1.0)];
 
+        let fonts = &self.fonts;
         let mut queue_text =
             |text: String, pos: (f32, f32), size: f32, h_align: HorizontalAlign| {
-                self.sections.push(OwnedSection {
-                    screen_position: pos,
-                    bounds: (available_width + 2.0, f32::INFINITY),
-                    layout: Layout::SingleLine {
-                        line_breaker: BuiltInLineBreaker::AnyCharLineBreaker,
-                        h_align,
-                        v_align: VerticalAlign::Center,
-                    },
-                    text: vec![OwnedText::new(text).with_scale(size).with_color(text_color)],
-                });
+                self.sections.push(text_section(
+                    fonts,
+                    &text,
+                    pos,
+                    size,
+                    h_align,
+                    text_color,
+                    available_width + 2.0,
+                ));
             };
 
         let song_name = track
@@ -63,58 +422,99 @@ impl TextRenderer {
             .unwrap_or("")
             .trim();
 
-        let top_y = PANEL_START + (CONFIG.height * 0.26).floor();
-        let bottom_y = PANEL_START + (CONFIG.height * 0.57).floor();
+        let top_y = PANEL_START + (CONFIG.load().height * 0.26).floor();
+        let bottom_y = PANEL_START + (CONFIG.load().height * 0.57).floor();
 
-        let measure_layout = Layout::SingleLine {
-            line_breaker: BuiltInLineBreaker::AnyCharLineBreaker,
-            h_align: HorizontalAlign::Left,
-            v_align: VerticalAlign::Center,
-        };
-
-        let measured_width = self
-            .brush
-            .glyph_bounds(
-                Section::default()
-                    .add_text(Text::new(song_name).with_scale(FONT_SIZE))
-                    .with_layout(measure_layout),
-            )
-            .map_or(0.0, |b| b.width());
+        let measured_width = self.brush.text_width(fonts, song_name, FONT_SIZE);
 
         let width_ratio = available_width / measured_width;
-        let (x, align, size) = if width_ratio <= 1.0 {
+        let marquee_id = track
+            .id
+            .filter(|_| track_render.is_current && width_ratio <= 1.0);
+        let (song_name, x, align, size) = if let Some(id) = marquee_id {
+            let visible = Self::marquee_text(
+                &mut self.brush,
+                fonts,
+                &mut self.marquee,
+                id,
+                song_name,
+                available_width,
+                time,
+            );
+            (
+                visible.to_owned(),
+                text_start_left,
+                HorizontalAlign::Left,
+                FONT_SIZE,
+            )
+        } else if width_ratio <= 1.0 && CONFIG.load().text_overflow == TextOverflow::Ellipsis {
+            (
+                Self::ellipsize(
+                    &mut self.brush,
+                    fonts,
+                    song_name,
+                    FONT_SIZE,
+                    available_width,
+                ),
+                text_start_left,
+                HorizontalAlign::Left,
+                FONT_SIZE,
+            )
+        } else if width_ratio <= 1.0 {
             (
+                song_name.to_owned(),
                 text_start_left,
                 HorizontalAlign::Left,
                 FONT_SIZE * width_ratio.max(0.8),
             )
         } else {
-            (text_start_right, HorizontalAlign::Right, FONT_SIZE)
-        };
-        queue_text(song_name.to_owned(), (x, top_y), size, align);
-
-        let time_text = if track_render.seconds_until_start >= 60.0 {
-            format!(
-                "{}m{}s",
-                (track_render.seconds_until_start / 60.0).floor(),
-                (track_render.seconds_until_start % 60.0).floor()
+            (
+                song_name.to_owned(),
+                text_start_right,
+                HorizontalAlign::Right,
+                FONT_SIZE,
             )
-        } else {
-            format!("{}s", track_render.seconds_until_start.round())
         };
+        queue_text(song_name, (x, top_y), size, align);
 
-        let bottom_merged = format!("{time_text}\u{2004}•\u{2004}{}", track.artist.name);
+        let config = CONFIG.load();
+        let tokens = &config.bottom_line_format.0;
+        let bottom_merged: String = tokens
+            .iter()
+            .map(|token| line_token(token, track, track_render))
+            .collect();
         let measured_bottom_width = self
             .brush
-            .glyph_bounds(
-                Section::default()
-                    .add_text(Text::new(&bottom_merged).with_scale(FONT_SIZE_SMALL))
-                    .with_layout(measure_layout),
-            )
-            .map_or(0.0, |b| b.width());
+            .text_width(fonts, &bottom_merged, FONT_SIZE_SMALL);
+
+        // Two details either side of a separator are spread to the ends of the current track
+        let spread = match tokens.as_slice() {
+            [left, LineToken::Text(_), right]
+                if !matches!(left, LineToken::Text(_)) && !matches!(right, LineToken::Text(_)) =>
+            {
+                Some((left, right))
+            }
+            _ => None,
+        };
 
         let bottom_ratio = available_width / measured_bottom_width;
-        if bottom_ratio <= 1.0 || !track_render.is_current {
+        if let Some((left, right)) = spread
+            && bottom_ratio > 1.0
+            && track_render.is_current
+        {
+            queue_text(
+                line_token(left, track, track_render),
+                (text_start_left, bottom_y),
+                FONT_SIZE_SMALL,
+                HorizontalAlign::Left,
+            );
+            queue_text(
+                line_token(right, track, track_render),
+                (text_start_right, bottom_y),
+                FONT_SIZE_SMALL,
+                HorizontalAlign::Right,
+            );
+        } else {
             let align = if bottom_ratio >= 1.0 {
                 HorizontalAlign::Right
             } else {
@@ -125,28 +525,147 @@ impl TextRenderer {
             } else {
                 text_start_left
             };
-            queue_text(
-                bottom_merged,
-                (x, bottom_y),
-                FONT_SIZE_SMALL * bottom_ratio.clamp(0.8, 1.0),
-                align,
-            );
+            let (text, size) = match config.text_overflow {
+                TextOverflow::Ellipsis => (
+                    Self::ellipsize(
+                        &mut self.brush,
+                        fonts,
+                        &bottom_merged,
+                        FONT_SIZE_SMALL,
+                        available_width,
+                    ),
+                    FONT_SIZE_SMALL,
+                ),
+                TextOverflow::Shrink => (
+                    bottom_merged,
+                    FONT_SIZE_SMALL * bottom_ratio.clamp(0.8, 1.0),
+                ),
+            };
+            queue_text(text, (x, bottom_y), size, align);
+        }
+    }
+
+    /// Label the playhead with the elapsed and total time of the current track.
+    pub fn render_time_label(&mut self, track: &Track, elapsed_ms: f32, playhead_x: f32) {
+        let duration_ms = track.duration_ms as f32;
+        let text = format!(
+            "{} / {}",
+            format_time(elapsed_ms.min(duration_ms)),
+            format_time(duration_ms)
+        );
+        let [r, g, b] = text_color(track);
+        let y = PANEL_START + (CONFIG.load().height * 0.86).floor();
+        self.sections.push(text_section(
+            &self.fonts,
+            &text,
+            (playhead_x - 6.0, y),
+            FONT_SIZE_TIME,
+            HorizontalAlign::Right,
+            [r, g, b, 0.8],
+            f32::INFINITY,
+        ));
+    }
+
+    /// Label a time tick, just right of the line along the bottom of the bar.
+    pub fn render_tick_label(&mut self, text: &str, x: f32, alpha: f32) {
+        let [r, g, b] = LIGHT_TEXT;
+        self.sections.push(text_section(
+            &self.fonts,
+            text,
+            (x + 3.0, PANEL_START + (CONFIG.load().height * 0.86).floor()),
+            FONT_SIZE_TIME,
+            HorizontalAlign::Left,
+            [r, g, b, alpha * 0.6],
+            f32::INFINITY,
+        ));
+    }
+
+    /// Lay out the rating badge centred on `pos`, like `7/10`, gold once rated like the stars.
+    pub fn render_rating_badge(&mut self, rating: u8, slots: usize, pos: (f32, f32), alpha: f32) {
+        let (text, [r, g, b]) = if rating == 0 {
+            (format!("-/{slots}"), [0.6, 0.6, 0.6])
         } else {
-            queue_text(
-                time_text,
-                (text_start_left, bottom_y),
-                FONT_SIZE_SMALL,
+            (format!("{rating}/{slots}"), [1.0, 0.85, 0.2])
+        };
+        self.sections.push(text_section(
+            &self.fonts,
+            &text,
+            pos,
+            FONT_SIZE_SMALL,
+            HorizontalAlign::Center,
+            [r, g, b, alpha],
+            f32::INFINITY,
+        ));
+    }
+
+    pub const fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity;
+    }
+
+    /// Width of the full title or the artist and album line, whichever is longer.
+    pub fn tooltip_width(&mut self, track: &Track) -> f32 {
+        let title = self.brush.text_width(&self.fonts, &track.name, FONT_SIZE);
+        let artist = self
+            .brush
+            .text_width(&self.fonts, &tooltip_subtitle(track), FONT_SIZE_SMALL);
+        title.max(artist)
+    }
+
+    /// Width of a tooltip label.
+    pub fn label_width(&mut self, text: &str) -> f32 {
+        self.brush.text_width(&self.fonts, text, FONT_SIZE_SMALL)
+    }
+
+    /// Lay out a label centred on the tooltip spanning `x0` to `x1`, coloured to suit the track.
+    pub fn render_label(&mut self, track: &Track, text: &str, x0: f32, x1: f32) {
+        let [r, g, b] = text_color(track);
+        let y = PANEL_START + (CONFIG.load().height * 0.26).floor();
+        self.sections.push(text_section(
+            &self.fonts,
+            text,
+            ((x0 + x1) * 0.5, y),
+            FONT_SIZE_SMALL,
+            HorizontalAlign::Center,
+            [r, g, b, 1.0],
+            x1 - x0,
+        ));
+    }
+
+    /// Lay out the untruncated title, artist and album on the tooltip spanning `x0` to `x1`.
+    pub fn render_tooltip(&mut self, track: &Track, x0: f32, x1: f32) {
+        let [r, g, b] = text_color(track);
+        let top_y = PANEL_START + (CONFIG.load().height * 0.26).floor();
+        let bottom_y = PANEL_START + (CONFIG.load().height * 0.57).floor();
+        for (text, y, size) in [
+            (&track.name, top_y, FONT_SIZE),
+            (&tooltip_subtitle(track), bottom_y, FONT_SIZE_SMALL),
+        ] {
+            self.sections.push(text_section(
+                &self.fonts,
+                text,
+                (x0 + TOOLTIP_PADDING, y),
+                size,
                 HorizontalAlign::Left,
-            );
-            queue_text(
-                track.artist.name.clone(),
-                (text_start_right, bottom_y),
-                FONT_SIZE_SMALL,
-                HorizontalAlign::Right,
-            );
+                [r, g, b, 1.0],
+                x1 - x0,
+            ));
         }
     }
 
+    /// Queue a plain white line of small text, for the debug overlay.
+    #[cfg(feature = "debug_overlay")]
+    pub fn render_debug_text(&mut self, text: &str, pos: (f32, f32)) {
+        self.sections.push(text_section(
+            &self.fonts,
+            text,
+            pos,
+            FONT_SIZE_TIME,
+            HorizontalAlign::Left,
+            [1.0, 1.0, 1.0, 0.9],
+            f32::INFINITY,
+        ));
+    }
+
     pub fn draw(
         &mut self,
         device: &Device,
@@ -156,15 +675,25 @@ impl TextRenderer {
         height: u32,
         scale: f32,
     ) {
-        self.brush.update_matrix(
+        let opacity = self.opacity;
+        let (width, height) = (width as f32, height as f32);
+        let matrix = if CONFIG.load().vertical() {
+            // Rotated a quarter turn like the shaders, the surface is the scene turned on its side
             [
-                [2.0 / width as f32, 0.0, 0.0, 0.0],
-                [0.0, -2.0 / height as f32, 0.0, 0.0],
+                [0.0, -2.0 / height, 0.0, 0.0],
+                [-2.0 / width, 0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0, 1.0],
+            ]
+        } else {
+            [
+                [2.0 / width, 0.0, 0.0, 0.0],
+                [0.0, -2.0 / height, 0.0, 0.0],
                 [0.0, 0.0, 1.0, 0.0],
                 [-1.0, 1.0, 0.0, 1.0],
-            ],
-            queue,
-        );
+            ]
+        };
+        self.brush.inner.update_matrix(matrix, queue);
 
         let sections = std::mem::take(&mut self.sections);
         let refs: Vec<Section> = sections
@@ -183,13 +712,21 @@ impl TextRenderer {
                             y: t.scale.y * scale,
                         },
                         font_id: t.font_id,
-                        extra: t.extra,
+                        extra: Extra {
+                            color: [
+                                t.extra.color[0],
+                                t.extra.color[1],
+                                t.extra.color[2],
+                                t.extra.color[3] * opacity,
+                            ],
+                            z: t.extra.z,
+                        },
                     })
                     .collect(),
             })
             .collect();
 
-        self.brush.queue(device, queue, refs).unwrap();
-        self.brush.draw(rpass);
+        self.brush.inner.queue(device, queue, refs).unwrap();
+        self.brush.inner.draw(rpass);
     }
 }